@@ -0,0 +1,9 @@
+create table email_codes (
+    user_id bigint not null references users (id) on delete cascade,
+    purpose varchar(16) not null,
+    code_hash varchar(100) not null,
+    failed_attempts integer not null default 0,
+    created_at timestamptz not null default now(),
+    expires_at timestamptz not null,
+    primary key (user_id, purpose)
+);
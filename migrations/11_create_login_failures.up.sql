@@ -0,0 +1,8 @@
+create table login_failures (
+    scope varchar(16) not null,
+    key varchar(320) not null,
+    failures integer not null,
+    last_failed_at timestamptz not null default now(),
+    locked_until timestamptz,
+    primary key (scope, key)
+);
@@ -0,0 +1,3 @@
+alter table users alter column password_hash type varchar(100);
+alter table recovery_codes alter column code_hash type varchar(100);
+alter table email_codes alter column code_hash type varchar(100);
@@ -0,0 +1,4 @@
+-- hashes peppered with a derived key carry its id in their params
+alter table users alter column password_hash type varchar(128);
+alter table recovery_codes alter column code_hash type varchar(128);
+alter table email_codes alter column code_hash type varchar(128);
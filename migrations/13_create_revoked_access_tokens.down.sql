@@ -0,0 +1,3 @@
+drop table revoked_access_tokens;
+alter table refresh_tokens drop column access_token_expires_at;
+alter table refresh_tokens drop column access_token_jti;
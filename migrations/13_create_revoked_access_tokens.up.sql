@@ -0,0 +1,8 @@
+-- every refresh token is issued along with an access token,
+-- so that all access tokens of a session can be revoked
+alter table refresh_tokens add column access_token_jti uuid;
+alter table refresh_tokens add column access_token_expires_at timestamptz;
+create table revoked_access_tokens (
+    jti uuid primary key,
+    expires_at timestamptz not null
+);
@@ -0,0 +1,11 @@
+create table roles (
+    id bigserial primary key,
+    name varchar(50) not null unique
+);
+create table user_roles (
+    user_id bigint not null references users (id) on delete cascade,
+    role_id bigint not null references roles (id) on delete cascade,
+    primary key (user_id, role_id)
+);
+-- the first admin has to be granted the role directly in the database
+insert into roles (name) values ('admin');
@@ -0,0 +1,4 @@
+alter table sessions drop column scopes;
+drop table user_permissions;
+drop table role_permissions;
+drop table permissions;
@@ -0,0 +1,27 @@
+create table permissions (
+    id bigserial primary key,
+    name varchar(100) not null unique,
+    description text not null
+);
+create table role_permissions (
+    role_id bigint not null references roles (id) on delete cascade,
+    permission_id bigint not null references permissions (id)
+        on delete cascade,
+    primary key (role_id, permission_id)
+);
+create table user_permissions (
+    user_id bigint not null references users (id) on delete cascade,
+    permission_id bigint not null references permissions (id)
+        on delete cascade,
+    primary key (user_id, permission_id)
+);
+-- null lets the session use every permission of the user
+alter table sessions add column scopes text[];
+insert into permissions (name, description)
+values
+    ('users:read', 'View users along with their roles and permissions'),
+    ('users:write', 'Grant and revoke roles and permissions of users');
+insert into role_permissions (role_id, permission_id)
+select roles.id, permissions.id
+from roles, permissions
+where roles.name = 'admin';
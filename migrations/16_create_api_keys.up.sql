@@ -0,0 +1,13 @@
+create table api_keys (
+    id uuid primary key,
+    user_id bigint not null references users (id) on delete cascade,
+    name varchar(100) not null,
+    prefix varchar(16) not null,
+    token_hash varchar(64) not null unique,
+    -- null lets the key use every permission of the user
+    scopes text[],
+    created_at timestamptz not null default now(),
+    last_used_at timestamptz,
+    expires_at timestamptz
+);
+create index api_keys_user_id_idx on api_keys (user_id);
@@ -0,0 +1,2 @@
+drop table service_account_permissions;
+drop table service_accounts;
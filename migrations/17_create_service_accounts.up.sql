@@ -0,0 +1,14 @@
+create table service_accounts (
+    id bigserial primary key,
+    name varchar(100) not null,
+    client_id varchar(64) not null unique,
+    client_secret_hash varchar(64) not null,
+    created_at timestamptz not null default now()
+);
+create table service_account_permissions (
+    service_account_id bigint not null references service_accounts (id)
+        on delete cascade,
+    permission_id bigint not null references permissions (id)
+        on delete cascade,
+    primary key (service_account_id, permission_id)
+);
@@ -0,0 +1,4 @@
+drop table oauth_access_tokens;
+drop table oauth_authorization_codes;
+drop table oauth_consents;
+drop table oauth_clients;
@@ -0,0 +1,36 @@
+create table oauth_clients (
+    id bigserial primary key,
+    name varchar(100) not null,
+    client_id varchar(64) not null unique,
+    -- null for public clients, which can only rely on PKCE
+    client_secret_hash varchar(64),
+    redirect_uris text[] not null,
+    created_at timestamptz not null default now()
+);
+create table oauth_consents (
+    user_id bigint not null references users (id) on delete cascade,
+    oauth_client_id bigint not null references oauth_clients (id)
+        on delete cascade,
+    scopes text[] not null,
+    granted_at timestamptz not null default now(),
+    primary key (user_id, oauth_client_id)
+);
+create table oauth_authorization_codes (
+    code_hash varchar(64) primary key,
+    oauth_client_id bigint not null references oauth_clients (id)
+        on delete cascade,
+    user_id bigint not null references users (id) on delete cascade,
+    redirect_uri text not null,
+    scopes text[] not null,
+    nonce text,
+    code_challenge varchar(128) not null,
+    expires_at timestamptz not null
+);
+create table oauth_access_tokens (
+    token_hash varchar(64) primary key,
+    oauth_client_id bigint not null references oauth_clients (id)
+        on delete cascade,
+    user_id bigint not null references users (id) on delete cascade,
+    scopes text[] not null,
+    expires_at timestamptz not null
+);
@@ -0,0 +1,7 @@
+drop index oauth_access_tokens_authorization_code_hash_idx;
+alter table oauth_access_tokens
+    drop column authorization_code_hash,
+    drop column session_id;
+alter table oauth_authorization_codes
+    drop column used_at,
+    drop column session_id;
@@ -0,0 +1,15 @@
+-- both only live for minutes, so the outstanding ones are dropped
+-- rather than left without the session they were issued in
+delete from oauth_access_tokens;
+delete from oauth_authorization_codes;
+alter table oauth_authorization_codes
+    add column session_id uuid not null references sessions (id)
+        on delete cascade,
+    -- set once exchanged, as codes are kept around to detect replays
+    add column used_at timestamptz;
+alter table oauth_access_tokens
+    add column session_id uuid not null references sessions (id)
+        on delete cascade,
+    add column authorization_code_hash varchar(64) not null;
+create index oauth_access_tokens_authorization_code_hash_idx
+    on oauth_access_tokens (authorization_code_hash);
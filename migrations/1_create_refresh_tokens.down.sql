@@ -0,0 +1,2 @@
+drop table refresh_tokens;
+alter table users add column refresh_token varchar(32);
@@ -0,0 +1,10 @@
+alter table users drop column refresh_token;
+create table refresh_tokens (
+    token_hash varchar(64) primary key,
+    family_id uuid not null,
+    user_id bigint not null references users (id) on delete cascade,
+    expires_at timestamptz not null,
+    rotated boolean not null default false, -- if true, then already exchanged
+    revoked boolean not null default false
+);
+create index refresh_tokens_family_id_idx on refresh_tokens (family_id);
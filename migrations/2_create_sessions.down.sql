@@ -0,0 +1,6 @@
+alter index refresh_tokens_session_id_idx
+    rename to refresh_tokens_family_id_idx;
+alter table refresh_tokens drop constraint refresh_tokens_session_id_fkey;
+alter table refresh_tokens rename column session_id to family_id;
+alter table refresh_tokens add column revoked boolean not null default false;
+drop table sessions;
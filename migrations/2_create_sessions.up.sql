@@ -0,0 +1,19 @@
+create table sessions (
+    id uuid primary key,
+    user_id bigint not null references users (id) on delete cascade,
+    device_label varchar(100),
+    user_agent varchar(256),
+    ip_address varchar(45),
+    created_at timestamptz not null default now(),
+    last_used_at timestamptz not null default now(),
+    expires_at timestamptz not null
+);
+create index sessions_user_id_idx on sessions (user_id);
+-- existing token families have no session to belong to
+delete from refresh_tokens;
+alter table refresh_tokens drop column revoked;
+alter table refresh_tokens rename column family_id to session_id;
+alter table refresh_tokens
+    add foreign key (session_id) references sessions (id) on delete cascade;
+alter index refresh_tokens_family_id_idx
+    rename to refresh_tokens_session_id_idx;
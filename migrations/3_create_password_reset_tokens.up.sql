@@ -0,0 +1,8 @@
+create table password_reset_tokens (
+    token_hash varchar(64) primary key,
+    user_id bigint not null references users (id) on delete cascade,
+    expires_at timestamptz not null,
+    consumed_at timestamptz
+);
+create index password_reset_tokens_user_id_idx
+    on password_reset_tokens (user_id);
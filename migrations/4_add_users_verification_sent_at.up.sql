@@ -0,0 +1 @@
+alter table users add column verification_sent_at timestamptz;
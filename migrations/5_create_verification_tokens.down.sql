@@ -0,0 +1,6 @@
+alter table users add column verification_sent_at timestamptz;
+alter table users
+    add column verification_token uuid not null unique
+    default gen_random_uuid();
+alter table users alter column verification_token drop default;
+drop table verification_tokens;
@@ -0,0 +1,19 @@
+create table verification_tokens (
+    token_hash varchar(64) primary key,
+    user_id bigint not null references users (id) on delete cascade,
+    created_at timestamptz not null default now(),
+    expires_at timestamptz not null,
+    consumed_at timestamptz
+);
+create index verification_tokens_user_id_idx on verification_tokens (user_id);
+-- keep links that were already sent working for a day
+insert into verification_tokens (token_hash, user_id, created_at, expires_at)
+select
+    encode(sha256(verification_token::text::bytea), 'hex'),
+    id,
+    coalesce(verification_sent_at, now()),
+    now() + interval '1 day'
+from users
+where not verified;
+alter table users drop column verification_token;
+alter table users drop column verification_sent_at;
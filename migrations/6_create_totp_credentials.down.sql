@@ -0,0 +1,2 @@
+drop table two_factor_challenges;
+drop table totp_credentials;
@@ -0,0 +1,15 @@
+create table totp_credentials (
+    user_id bigint primary key references users (id) on delete cascade,
+    encrypted_secret bytea not null,
+    confirmed_at timestamptz,
+    last_used_step bigint
+);
+create table two_factor_challenges (
+    token_hash varchar(64) primary key,
+    user_id bigint not null references users (id) on delete cascade,
+    device_label varchar(100),
+    failed_attempts integer not null default 0,
+    expires_at timestamptz not null
+);
+create index two_factor_challenges_user_id_idx
+    on two_factor_challenges (user_id);
@@ -0,0 +1,8 @@
+create table recovery_codes (
+    id bigserial primary key,
+    user_id bigint not null
+        references totp_credentials (user_id) on delete cascade,
+    code_hash varchar(100) not null,
+    used_at timestamptz
+);
+create index recovery_codes_user_id_idx on recovery_codes (user_id);
@@ -0,0 +1,18 @@
+create table webauthn_credentials (
+    id bytea primary key,
+    user_id bigint not null references users (id) on delete cascade,
+    public_key bytea not null,
+    algorithm integer not null, -- COSE algorithm identifier
+    sign_count bigint not null,
+    name varchar(100),
+    created_at timestamptz not null default now(),
+    last_used_at timestamptz
+);
+create index webauthn_credentials_user_id_idx
+    on webauthn_credentials (user_id);
+create table webauthn_challenges (
+    challenge_hash varchar(64) primary key,
+    user_id bigint references users (id) on delete cascade, -- if null, then any user may authenticate
+    ceremony varchar(16) not null,
+    expires_at timestamptz not null
+);
@@ -0,0 +1,7 @@
+create table magic_link_tokens (
+    token_hash varchar(64) primary key,
+    user_id bigint not null references users (id) on delete cascade,
+    expires_at timestamptz not null,
+    consumed_at timestamptz
+);
+create index magic_link_tokens_user_id_idx on magic_link_tokens (user_id);
@@ -0,0 +1,6 @@
+crate::api::router! {
+    /users,
+    /service_accounts,
+    /oauth_clients,
+    /permissions,
+}
@@ -0,0 +1,46 @@
+use axum::{extract::State, Json};
+
+use crate::{
+    database::oauth_client::{self, OauthClient},
+    extractors::{Admin, HasRole},
+    Pool,
+};
+
+#[tracing::instrument(
+    name = "List OAuth clients",
+    skip_all,
+    fields(admin_id = %admin.user.id)
+)]
+pub async fn handler(
+    admin: HasRole<Admin>,
+    State(pool): State<Pool>,
+) -> crate::Result<Json<Vec<OauthClient>>> {
+    let oauth_clients = oauth_client::list(&pool).await?;
+    Ok(Json(oauth_clients))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_user_without_role(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let req = Request::builder()
+            .method("GET")
+            .uri("/admin/oauth_clients")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,80 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    database::oauth_client,
+    error::Error,
+    extractors::{Admin, HasRole},
+    telemetry, Pool,
+};
+
+/// Codes, consents and access tokens of the client go along with it.
+#[tracing::instrument(
+    name = "Delete OAuth client",
+    skip_all,
+    fields(admin_id = %admin.user.id, oauth_client_id = %id)
+)]
+pub async fn handler(
+    admin: HasRole<Admin>,
+    Path(id): Path<i64>,
+    State(pool): State<Pool>,
+) -> crate::Result<StatusCode> {
+    if !oauth_client::delete(id, &pool).await? {
+        Err(Error::UnknownOauthClient).map_err(telemetry::warn)?;
+    }
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestOauthClient, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn stops_authorizing_client(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::grant_role(&mut server, &pool, "admin").await;
+        let client = TestOauthClient::register(&pool).await;
+        let code = client.authorize(&mut server).await;
+        let req = Request::builder()
+            .method("GET")
+            .uri("/admin/oauth_clients")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        let oauth_clients = read_json::<Vec<serde_json::Value>>(res).await;
+        let req = Request::builder()
+            .method("DELETE")
+            .uri(format!("/admin/oauth_clients/{}", oauth_clients[0]["id"]))
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = client.exchange_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn fails_on_unknown_oauth_client(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::grant_role(&mut server, &pool, "admin").await;
+        let req = Request::builder()
+            .method("DELETE")
+            .uri("/admin/oauth_clients/1")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
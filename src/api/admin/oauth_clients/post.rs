@@ -0,0 +1,169 @@
+use axum::{extract::State, http::StatusCode, Json};
+use reqwest::Url;
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::{
+    database::oauth_client::{self, OauthClient},
+    extractors::{validated::Form, Admin, HasRole},
+    services::token::TokenService,
+    Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    #[validate(length(
+        min = 1,
+        max = 100,
+        message = "must be between 1 and 100 characters long"
+    ))]
+    name: String,
+    /// Space-delimited URIs the client may be redirected back to.
+    #[validate(custom(
+        function = "absolute_urls",
+        message = "must be a list of absolute URLs"
+    ))]
+    redirect_uris: String,
+    /// Public clients, such as single-page apps, cannot keep a secret.
+    #[serde(default)]
+    public: bool,
+}
+
+/// The client secret is only ever returned here,
+/// as just its hash is stored.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedOauthClient {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_secret: Option<String>,
+    #[serde(flatten)]
+    oauth_client: OauthClient,
+}
+
+#[tracing::instrument(
+    name = "Create OAuth client",
+    skip_all,
+    fields(admin_id = %admin.user.id)
+)]
+pub async fn handler(
+    admin: HasRole<Admin>,
+    State(pool): State<Pool>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<(StatusCode, Json<CreatedOauthClient>)> {
+    let redirect_uris = payload
+        .redirect_uris
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    let (client_id, client_secret) =
+        TokenService::generate_oauth_client_credentials();
+    let client_secret = (!payload.public).then_some(client_secret);
+    let oauth_client = oauth_client::insert(
+        &payload.name,
+        &client_id,
+        client_secret.as_ref(),
+        &redirect_uris,
+        &pool,
+    )
+    .await?;
+    let created = CreatedOauthClient {
+        client_secret: client_secret
+            .map(|secret| secret.expose_secret().to_owned()),
+        oauth_client,
+    };
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+fn absolute_urls(redirect_uris: &str) -> Result<(), ValidationError> {
+    let mut redirect_uris = redirect_uris.split_whitespace().peekable();
+    let is_valid = redirect_uris.peek().is_some()
+        && redirect_uris.all(|uri| {
+            // fragments are not allowed by RFC 6749
+            Url::parse(uri).map_or(false, |url| url.fragment().is_none())
+        });
+    if is_valid {
+        Ok(())
+    } else {
+        Err(ValidationError::new("must be a list of absolute URLs"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_user_without_role(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server
+            .call(request(&[("redirect_uris", "https://app.example.com")]))
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn returns_secret_of_confidential_client_only(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::grant_role(&mut server, &pool, "admin").await;
+        let redirect_uris =
+            "https://app.example.com/a https://app.example.com/b";
+        let res = server
+            .call(request(&[("redirect_uris", redirect_uris)]))
+            .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert!(body["clientSecret"].is_string());
+        assert_eq!(body["confidential"], true);
+        assert_eq!(
+            body["redirectUris"],
+            serde_json::json!([
+                "https://app.example.com/a",
+                "https://app.example.com/b"
+            ])
+        );
+        let res = server
+            .call(request(&[
+                ("redirect_uris", "https://app.example.com"),
+                ("public", "true"),
+            ]))
+            .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert!(body.get("clientSecret").is_none());
+        assert_eq!(body["confidential"], false);
+    }
+
+    #[sqlx::test]
+    async fn fails_on_relative_redirect_uri(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::grant_role(&mut server, &pool, "admin").await;
+        let res = server
+            .call(request(&[("redirect_uris", "/callback")]))
+            .await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    fn request(body: &[(&str, &str)]) -> Request<Body> {
+        let mut body = body.to_vec();
+        body.push(("name", "app"));
+        let body = serde_urlencoded::to_string(body).unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/admin/oauth_clients")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
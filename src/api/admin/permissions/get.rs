@@ -0,0 +1,65 @@
+use axum::{extract::State, Json};
+
+use crate::{
+    database::permission::{self, Permission},
+    extractors::{Admin, HasRole, UsersRead},
+    Pool,
+};
+
+#[tracing::instrument(
+    name = "List permissions",
+    skip_all,
+    fields(admin_id = %admin.user.id)
+)]
+pub async fn handler(
+    admin: HasRole<Admin, UsersRead>,
+    State(pool): State<Pool>,
+) -> crate::Result<Json<Vec<Permission>>> {
+    let permissions = permission::list(&pool).await?;
+    Ok(Json(permissions))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_user_without_role(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn lists_permission_catalog(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::grant_role(&mut server, &pool, "admin").await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let permissions = read_json::<Vec<serde_json::Value>>(res).await;
+        let names = permissions
+            .iter()
+            .map(|permission| permission["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["users:read", "users:write"]);
+        assert!(permissions[0]["description"].is_string());
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri("/admin/permissions")
+            .body(Body::empty())
+            .unwrap()
+    }
+}
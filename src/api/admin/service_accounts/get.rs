@@ -0,0 +1,46 @@
+use axum::{extract::State, Json};
+
+use crate::{
+    database::service_account::{self, ServiceAccount},
+    extractors::{Admin, HasRole},
+    Pool,
+};
+
+#[tracing::instrument(
+    name = "List service accounts",
+    skip_all,
+    fields(admin_id = %admin.user.id)
+)]
+pub async fn handler(
+    admin: HasRole<Admin>,
+    State(pool): State<Pool>,
+) -> crate::Result<Json<Vec<ServiceAccount>>> {
+    let service_accounts = service_account::list(&pool).await?;
+    Ok(Json(service_accounts))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_user_without_role(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let req = Request::builder()
+            .method("GET")
+            .uri("/admin/service_accounts")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+}
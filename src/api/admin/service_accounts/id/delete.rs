@@ -0,0 +1,97 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    database::service_account,
+    error::Error,
+    extractors::{Admin, HasRole},
+    telemetry, Pool,
+};
+
+/// Tokens already issued to the service account stay valid
+/// until they expire, which the short service token TTL keeps brief.
+#[tracing::instrument(
+    name = "Delete service account",
+    skip_all,
+    fields(admin_id = %admin.user.id, service_account_id = %id)
+)]
+pub async fn handler(
+    admin: HasRole<Admin>,
+    Path(id): Path<i64>,
+    State(pool): State<Pool>,
+) -> crate::Result<StatusCode> {
+    if !service_account::delete(id, &pool).await? {
+        Err(Error::UnknownServiceAccount).map_err(telemetry::warn)?;
+    }
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+    use secrecy::ExposeSecret;
+
+    use crate::{
+        database::service_account,
+        services::token::TokenService,
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn stops_issuing_tokens(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::grant_role(&mut server, &pool, "admin").await;
+        let (client_id, client_secret) =
+            TokenService::generate_client_credentials();
+        let service_account = service_account::insert(
+            "service",
+            &client_id,
+            &client_secret,
+            &[],
+            &pool,
+        )
+        .await
+        .unwrap();
+        let req = Request::builder()
+            .method("DELETE")
+            .uri(format!("/admin/service_accounts/{}", service_account.id))
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = [
+            ("grant_type", "client_credentials"),
+            ("client_id", &client_id),
+            ("client_secret", client_secret.expose_secret()),
+        ];
+        let req = Request::builder()
+            .method("POST")
+            .uri("/oauth/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(serde_urlencoded::to_string(body).unwrap()))
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn fails_on_unknown_service_account(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::grant_role(&mut server, &pool, "admin").await;
+        let req = Request::builder()
+            .method("DELETE")
+            .uri("/admin/service_accounts/1")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
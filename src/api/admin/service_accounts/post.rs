@@ -0,0 +1,122 @@
+use axum::{extract::State, http::StatusCode, Json};
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    database::service_account::{self, ServiceAccount},
+    extractors::{validated::Form, Admin, HasRole},
+    services::token::TokenService,
+    Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    #[validate(length(
+        min = 1,
+        max = 100,
+        message = "must be between 1 and 100 characters long"
+    ))]
+    name: String,
+    /// Space-delimited permissions to grant.
+    scope: Option<String>,
+}
+
+/// The client secret is only ever returned here,
+/// as just its hash is stored.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedServiceAccount {
+    client_secret: String,
+    #[serde(flatten)]
+    service_account: ServiceAccount,
+}
+
+#[tracing::instrument(
+    name = "Create service account",
+    skip_all,
+    fields(admin_id = %admin.user.id)
+)]
+pub async fn handler(
+    admin: HasRole<Admin>,
+    State(pool): State<Pool>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<(StatusCode, Json<CreatedServiceAccount>)> {
+    let permissions = payload
+        .scope
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    let (client_id, client_secret) =
+        TokenService::generate_client_credentials();
+    let service_account = service_account::insert(
+        &payload.name,
+        &client_id,
+        &client_secret,
+        &permissions,
+        &pool,
+    )
+    .await?;
+    let created = CreatedServiceAccount {
+        client_secret: client_secret.expose_secret().to_owned(),
+        service_account,
+    };
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_user_without_role(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request("users:read")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn returns_client_credentials(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::grant_role(&mut server, &pool, "admin").await;
+        let res = server.call(request("users:read unknown")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert!(body["clientId"].is_string());
+        assert!(body["clientSecret"].is_string());
+        assert_eq!(body["permissions"], serde_json::json!(["users:read"]));
+        let list_request = Request::builder()
+            .method("GET")
+            .uri("/admin/service_accounts")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(list_request).await;
+        let service_accounts = read_json::<Vec<serde_json::Value>>(res).await;
+        assert_eq!(service_accounts.len(), 1);
+        assert_eq!(service_accounts[0]["clientId"], body["clientId"]);
+        assert!(service_accounts[0].get("clientSecret").is_none());
+    }
+
+    fn request(scope: &str) -> Request<Body> {
+        let body = [("name", "service"), ("scope", scope)];
+        let body = serde_urlencoded::to_string(body).unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/admin/service_accounts")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
@@ -0,0 +1,69 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    database::user,
+    error::Error,
+    extractors::{Admin, HasRole, UsersWrite},
+    telemetry, Pool,
+};
+
+/// Lets the user start sessions and use their API keys again.
+#[tracing::instrument(
+    name = "Enable user",
+    skip_all,
+    fields(admin_id = %admin.user.id, user_id = %user_id)
+)]
+pub async fn handler(
+    admin: HasRole<Admin, UsersWrite>,
+    Path(user_id): Path<i64>,
+    State(pool): State<Pool>,
+) -> crate::Result<StatusCode> {
+    if !user::enable(user_id, &pool).await? {
+        Err(Error::UnknownUser).map_err(telemetry::warn)?;
+    }
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn allows_login_again(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        sqlx::query!(
+            "update users set disabled_at = now() where id = $1;",
+            user_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = server.call(request(user_id)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = TestUser::login(&mut server).await;
+        assert!(res.status().is_success());
+    }
+
+    fn request(user_id: i64) -> Request<Body> {
+        Request::builder()
+            .method("DELETE")
+            .uri(format!("/admin/users/{user_id}/disabled"))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
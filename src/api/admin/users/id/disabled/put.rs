@@ -0,0 +1,118 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    database::{
+        begin_transaction, commit, revoked_access_token, session, user,
+    },
+    error::Error,
+    extractors::{Admin, HasRole, UsersWrite},
+    services::denylist::AccessTokenDenylist,
+    telemetry, Pool,
+};
+
+/// Ends every session of the user and revokes their access tokens.
+/// New sessions are refused and API keys rejected until re-enabled.
+#[tracing::instrument(
+    name = "Disable user",
+    skip_all,
+    fields(admin_id = %admin.user.id, user_id = %user_id)
+)]
+pub async fn handler(
+    admin: HasRole<Admin, UsersWrite>,
+    Path(user_id): Path<i64>,
+    State(pool): State<Pool>,
+    State(access_token_denylist): State<AccessTokenDenylist>,
+) -> crate::Result<StatusCode> {
+    let mut transaction = begin_transaction(&pool).await?;
+    if !user::disable(user_id, &mut transaction).await? {
+        Err(Error::UnknownUser).map_err(telemetry::warn)?;
+    }
+    let revoked =
+        revoked_access_token::revoke_for_user(user_id, None, &mut transaction)
+            .await?;
+    session::delete_all_for_user(user_id, None, &mut transaction).await?;
+    commit(transaction).await?;
+    access_token_denylist.extend(&revoked);
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_user_without_role(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request(1)).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn fails_on_unknown_user(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        let res = server.call(request(user_id + 1)).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn revokes_tokens_and_refuses_login(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        let api_key = TestUser::create_api_key(&mut server, None).await;
+        let res = server.call(request(user_id)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server.call(protected_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = server.call(refresh_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        server.clear_cookies();
+        let mut req = protected_request();
+        req.headers_mut().insert(
+            AUTHORIZATION,
+            format!("Bearer {api_key}").parse().unwrap(),
+        );
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    fn request(user_id: i64) -> Request<Body> {
+        Request::builder()
+            .method("PUT")
+            .uri(format!("/admin/users/{user_id}/disabled"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn protected_request() -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri("/health_check/protected")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn refresh_request() -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/auth/refresh")
+            .body(Body::empty())
+            .unwrap()
+    }
+}
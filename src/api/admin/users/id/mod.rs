@@ -0,0 +1,5 @@
+crate::api::router! {
+    /roles,
+    /permissions,
+    /disabled,
+}
@@ -0,0 +1,65 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use crate::{
+    database::permission,
+    extractors::{Principal, UsersRead},
+    Pool,
+};
+
+/// Lists the permissions the user has, whether through roles or directly.
+#[tracing::instrument(
+    name = "List user's permissions",
+    skip_all,
+    fields(principal = %principal, user_id = %user_id)
+)]
+pub async fn handler(
+    principal: Principal<UsersRead>,
+    Path(user_id): Path<i64>,
+    State(pool): State<Pool>,
+) -> crate::Result<Json<Vec<String>>> {
+    let permissions = permission::list_for_user(user_id, &pool).await?;
+    Ok(Json(permissions))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_without_scope(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request(1)).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn lists_permissions_granted_through_roles(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        let res = server.call(request(user_id)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let permissions = read_json::<Vec<String>>(res).await;
+        assert_eq!(permissions, ["users:read", "users:write"]);
+    }
+
+    fn request(user_id: i64) -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri(format!("/admin/users/{user_id}/permissions"))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
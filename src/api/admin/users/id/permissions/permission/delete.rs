@@ -0,0 +1,93 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    database::{begin_transaction, commit, permission, revoked_access_token},
+    error::Error,
+    extractors::{Admin, HasRole, UsersWrite},
+    services::denylist::AccessTokenDenylist,
+    telemetry, Pool,
+};
+
+#[tracing::instrument(
+    name = "Revoke permission from user",
+    skip_all,
+    fields(
+        admin_id = %admin.user.id,
+        user_id = %user_id,
+        permission = %permission,
+    )
+)]
+pub async fn handler(
+    admin: HasRole<Admin, UsersWrite>,
+    Path((user_id, permission)): Path<(i64, String)>,
+    State(pool): State<Pool>,
+    State(access_token_denylist): State<AccessTokenDenylist>,
+) -> crate::Result<StatusCode> {
+    let mut transaction = begin_transaction(&pool).await?;
+    if !permission::revoke(user_id, &permission, &mut transaction).await? {
+        Err(Error::UnknownUserOrPermission).map_err(telemetry::warn)?;
+    }
+    // the permission must not outlive the access tokens scoped to it
+    let revoked =
+        revoked_access_token::revoke_for_user(user_id, None, &mut transaction)
+            .await?;
+    commit(transaction).await?;
+    access_token_denylist.extend(&revoked);
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn revokes_permission_immediately(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::grant_role(&mut server, &pool, "admin").await;
+        let user_id =
+            TestUser::grant_permission(&mut server, &pool, "users:read").await;
+        let res = server.call(request(user_id, "users:read")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server.call(request(user_id, "users:read")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let refresh_request = Request::builder()
+            .method("POST")
+            .uri("/auth/refresh")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(refresh_request).await;
+        assert!(res.status().is_success());
+        // the permission is still granted through the role
+        let res = server.call(request(user_id, "users:read")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn fails_on_permission_granted_through_role(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        let res = server.call(request(user_id, "users:read")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn request(user_id: i64, permission: &str) -> Request<Body> {
+        Request::builder()
+            .method("DELETE")
+            .uri(format!("/admin/users/{user_id}/permissions/{permission}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
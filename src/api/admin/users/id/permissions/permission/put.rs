@@ -0,0 +1,120 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    database::{begin_transaction, commit, permission, revoked_access_token},
+    error::Error,
+    extractors::{Admin, HasRole, UsersWrite},
+    services::denylist::AccessTokenDenylist,
+    telemetry, Pool,
+};
+
+#[tracing::instrument(
+    name = "Grant permission to user",
+    skip_all,
+    fields(
+        admin_id = %admin.user.id,
+        user_id = %user_id,
+        permission = %permission,
+    )
+)]
+pub async fn handler(
+    admin: HasRole<Admin, UsersWrite>,
+    Path((user_id, permission)): Path<(i64, String)>,
+    State(pool): State<Pool>,
+    State(access_token_denylist): State<AccessTokenDenylist>,
+) -> crate::Result<StatusCode> {
+    let mut transaction = begin_transaction(&pool).await?;
+    if !permission::grant(user_id, &permission, &mut transaction).await? {
+        Err(Error::UnknownUserOrPermission).map_err(telemetry::warn)?;
+    }
+    // access tokens carry scopes, refreshed ones will have the new one
+    let revoked =
+        revoked_access_token::revoke_for_user(user_id, None, &mut transaction)
+            .await?;
+    commit(transaction).await?;
+    access_token_denylist.extend(&revoked);
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_without_scope(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request(1, "users:read")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn fails_on_unknown_permission(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        let res = server.call(request(user_id, "unknown")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn grants_permission_with_next_access_token(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        sqlx::query!(
+            r#"
+            insert into permissions (name, description)
+            values ('reports:read', 'View reports');
+            "#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let res = server.call(request(user_id, "reports:read")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server.call(permissions_request(user_id)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = server.call(refresh_request()).await;
+        assert!(res.status().is_success());
+        let res = server.call(permissions_request(user_id)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let permissions = read_json::<Vec<String>>(res).await;
+        assert_eq!(permissions, ["reports:read", "users:read", "users:write"]);
+    }
+
+    fn request(user_id: i64, permission: &str) -> Request<Body> {
+        Request::builder()
+            .method("PUT")
+            .uri(format!("/admin/users/{user_id}/permissions/{permission}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn permissions_request(user_id: i64) -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri(format!("/admin/users/{user_id}/permissions"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn refresh_request() -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/auth/refresh")
+            .body(Body::empty())
+            .unwrap()
+    }
+}
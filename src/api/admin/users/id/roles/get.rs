@@ -0,0 +1,90 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use crate::{
+    database::role,
+    extractors::{Principal, UsersRead},
+    Pool,
+};
+
+#[tracing::instrument(
+    name = "List user's roles",
+    skip_all,
+    fields(principal = %principal, user_id = %user_id)
+)]
+pub async fn handler(
+    principal: Principal<UsersRead>,
+    Path(user_id): Path<i64>,
+    State(pool): State<Pool>,
+) -> crate::Result<Json<Vec<String>>> {
+    let roles = role::list_for_user(user_id, &pool).await?;
+    Ok(Json(roles))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_user_without_role(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request(1)).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn lists_roles_for_admin(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        let res = server.call(request(user_id)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let roles = read_json::<Vec<String>>(res).await;
+        assert_eq!(roles, ["admin"]);
+    }
+
+    #[sqlx::test]
+    async fn limits_api_key_to_its_scopes(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        let read_key =
+            TestUser::create_api_key(&mut server, Some("users:read")).await;
+        let write_key =
+            TestUser::create_api_key(&mut server, Some("users:write")).await;
+        server.clear_cookies();
+        let mut req = request(user_id);
+        req.headers_mut().insert(
+            AUTHORIZATION,
+            format!("Bearer {read_key}").parse().unwrap(),
+        );
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let mut req = request(user_id);
+        req.headers_mut().insert(
+            AUTHORIZATION,
+            format!("Bearer {write_key}").parse().unwrap(),
+        );
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    fn request(user_id: i64) -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri(format!("/admin/users/{user_id}/roles"))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -0,0 +1,77 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    database::{begin_transaction, commit, revoked_access_token, role},
+    error::Error,
+    extractors::{Admin, HasRole, UsersWrite},
+    services::denylist::AccessTokenDenylist,
+    telemetry, Pool,
+};
+
+#[tracing::instrument(
+    name = "Revoke role from user",
+    skip_all,
+    fields(admin_id = %admin.user.id, user_id = %user_id, role = %role)
+)]
+pub async fn handler(
+    admin: HasRole<Admin, UsersWrite>,
+    Path((user_id, role)): Path<(i64, String)>,
+    State(pool): State<Pool>,
+    State(access_token_denylist): State<AccessTokenDenylist>,
+) -> crate::Result<StatusCode> {
+    let mut transaction = begin_transaction(&pool).await?;
+    if !role::revoke(user_id, &role, &mut transaction).await? {
+        Err(Error::UnknownUserOrRole).map_err(telemetry::warn)?;
+    }
+    // the role must not outlive the access tokens issued with it
+    let revoked =
+        revoked_access_token::revoke_for_user(user_id, None, &mut transaction)
+            .await?;
+    commit(transaction).await?;
+    access_token_denylist.extend(&revoked);
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn revokes_role_immediately(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        let res = server.call(request(user_id, "admin")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server.call(request(user_id, "admin")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let refresh_request = Request::builder()
+            .method("POST")
+            .uri("/auth/refresh")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(refresh_request).await;
+        assert!(res.status().is_success());
+        let res = server.call(request(user_id, "admin")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    fn request(user_id: i64, role: &str) -> Request<Body> {
+        Request::builder()
+            .method("DELETE")
+            .uri(format!("/admin/users/{user_id}/roles/{role}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -0,0 +1,207 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+
+use crate::{
+    database::{begin_transaction, commit, revoked_access_token, role},
+    error::Error,
+    extractors::{Admin, HasRole, UsersWrite},
+    services::denylist::AccessTokenDenylist,
+    telemetry, Pool,
+};
+
+#[tracing::instrument(
+    name = "Grant role to user",
+    skip_all,
+    fields(admin_id = %admin.user.id, user_id = %user_id, role = %role)
+)]
+pub async fn handler(
+    admin: HasRole<Admin, UsersWrite>,
+    Path((user_id, role)): Path<(i64, String)>,
+    State(pool): State<Pool>,
+    State(access_token_denylist): State<AccessTokenDenylist>,
+) -> crate::Result<StatusCode> {
+    let mut transaction = begin_transaction(&pool).await?;
+    if !role::grant(user_id, &role, &mut transaction).await? {
+        Err(Error::UnknownUserOrRole).map_err(telemetry::warn)?;
+    }
+    // access tokens carry roles, refreshed ones will have the new role
+    let revoked =
+        revoked_access_token::revoke_for_user(user_id, None, &mut transaction)
+            .await?;
+    commit(transaction).await?;
+    access_token_denylist.extend(&revoked);
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE},
+            Request, StatusCode,
+        },
+    };
+
+    use secrecy::ExposeSecret;
+
+    use crate::{
+        database::service_account,
+        services::token::TokenService,
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_user_without_role(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request(1, "admin")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn fails_for_user_with_permission_but_without_role(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id =
+            TestUser::grant_permission(&mut server, &pool, "users:write").await;
+        let res = server.call(request(user_id, "admin")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn fails_for_admin_with_narrowed_scopes(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        let res = TestUser::login_for_tokens(&mut server).await;
+        server.clear_cookies();
+        let body = read_json::<serde_json::Value>(res).await;
+        let body = [
+            ("refresh_token", body["refreshToken"].as_str().unwrap()),
+            ("scope", "users:read"),
+        ];
+        let req = Request::builder()
+            .method("POST")
+            .uri("/auth/refresh/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(serde_urlencoded::to_string(body).unwrap()))
+            .unwrap();
+        let res = server.call(req).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["accessToken"].as_str().unwrap();
+        let mut req = request(user_id, "admin");
+        req.headers_mut().insert(
+            AUTHORIZATION,
+            format!("Bearer {access_token}").parse().unwrap(),
+        );
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn fails_with_machine_credentials(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        let api_key =
+            TestUser::create_api_key(&mut server, Some("users:write")).await;
+        let (client_id, client_secret) =
+            TokenService::generate_client_credentials();
+        service_account::insert(
+            "service",
+            &client_id,
+            &client_secret,
+            &["users:write".into()],
+            &pool,
+        )
+        .await
+        .unwrap();
+        let body = [
+            ("grant_type", "client_credentials"),
+            ("client_id", &client_id),
+            ("client_secret", client_secret.expose_secret()),
+        ];
+        let req = Request::builder()
+            .method("POST")
+            .uri("/oauth/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(serde_urlencoded::to_string(body).unwrap()))
+            .unwrap();
+        let res = server.call(req).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let service_token = body["access_token"].as_str().unwrap();
+        server.clear_cookies();
+        // API keys carry no roles, service tokens are not users' tokens
+        let rejections = [
+            (api_key.as_str(), StatusCode::FORBIDDEN),
+            (service_token, StatusCode::UNAUTHORIZED),
+        ];
+        for (token, status) in rejections {
+            let mut req = request(user_id, "admin");
+            req.headers_mut().insert(
+                AUTHORIZATION,
+                format!("Bearer {token}").parse().unwrap(),
+            );
+            let res = server.call(req).await;
+            assert_eq!(res.status(), status);
+        }
+    }
+
+    #[sqlx::test]
+    async fn fails_on_unknown_role(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        let res = server.call(request(user_id, "unknown")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn grants_role_with_next_access_token(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        sqlx::query!("insert into roles (name) values ('support');")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let res = server.call(request(user_id, "support")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server.call(roles_request(user_id)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = server.call(refresh_request()).await;
+        assert!(res.status().is_success());
+        let res = server.call(roles_request(user_id)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let roles = read_json::<Vec<String>>(res).await;
+        assert_eq!(roles, ["admin", "support"]);
+    }
+
+    fn request(user_id: i64, role: &str) -> Request<Body> {
+        Request::builder()
+            .method("PUT")
+            .uri(format!("/admin/users/{user_id}/roles/{role}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn roles_request(user_id: i64) -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri(format!("/admin/users/{user_id}/roles"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn refresh_request() -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/auth/refresh")
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -0,0 +1,105 @@
+use axum::{extract::State, Json};
+
+use crate::{
+    database::api_key::{self, ApiKey},
+    extractors::User,
+    Pool,
+};
+
+#[tracing::instrument(
+    name = "List user's API keys",
+    skip_all,
+    fields(user_id = %user.id)
+)]
+pub async fn handler(
+    user: User,
+    State(pool): State<Pool>,
+) -> crate::Result<Json<Vec<ApiKey>>> {
+    let api_keys =
+        api_key::list(user.id, user.credential.api_key_id(), &pool).await?;
+    Ok(Json(api_keys))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn lists_keys_without_secrets(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let key =
+            TestUser::create_api_key(&mut server, Some("users:read")).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let api_keys = read_json::<Vec<serde_json::Value>>(res).await;
+        assert_eq!(api_keys.len(), 1);
+        assert!(api_keys[0].get("key").is_none());
+        assert!(key.starts_with(api_keys[0]["prefix"].as_str().unwrap()));
+        assert_eq!(api_keys[0]["scopes"], serde_json::json!(["users:read"]));
+        assert!(api_keys[0]["lastUsedAt"].is_null());
+        assert_eq!(api_keys[0]["current"], false);
+    }
+
+    #[sqlx::test]
+    async fn records_last_use_of_current_key(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let key = TestUser::create_api_key(&mut server, None).await;
+        let mut req = request();
+        req.headers_mut()
+            .insert(AUTHORIZATION, format!("Bearer {key}").parse().unwrap());
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let api_keys = read_json::<Vec<serde_json::Value>>(res).await;
+        assert!(api_keys[0]["lastUsedAt"].is_string());
+        assert_eq!(api_keys[0]["current"], true);
+    }
+
+    #[sqlx::test]
+    async fn records_last_use_at_most_once_a_minute(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let key = TestUser::create_api_key(&mut server, None).await;
+        let last_used_at = sqlx::query!(
+            r#"
+            update api_keys
+            set last_used_at = now() - interval '30 seconds'
+            returning last_used_at;
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .last_used_at;
+        let mut req = request();
+        req.headers_mut()
+            .insert(AUTHORIZATION, format!("Bearer {key}").parse().unwrap());
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let api_keys = read_json::<Vec<serde_json::Value>>(res).await;
+        assert_eq!(api_keys[0]["current"], true);
+        let unchanged = sqlx::query!("select last_used_at from api_keys;")
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .last_used_at;
+        assert_eq!(unchanged, last_used_at);
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri("/auth/api_keys")
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -0,0 +1,79 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use uuid::Uuid;
+
+use crate::{
+    database::api_key, error::Error, extractors::User, telemetry, Pool,
+};
+
+/// Revokes the key right away, as keys are looked up on every request.
+#[tracing::instrument(
+    name = "Revoke user's API key",
+    skip_all,
+    fields(user_id = %user.id, api_key_id = %id)
+)]
+pub async fn handler(
+    user: User,
+    Path(id): Path<Uuid>,
+    State(pool): State<Pool>,
+) -> crate::Result<StatusCode> {
+    if !api_key::delete_for_user(&id, user.id, &pool).await? {
+        Err(Error::UnknownApiKey).map_err(telemetry::warn)?;
+    }
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+    };
+    use uuid::Uuid;
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_on_unknown_key(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request(&Uuid::new_v4())).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn rejects_revoked_key(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let key = TestUser::create_api_key(&mut server, None).await;
+        let res = server.call(list_request(None)).await;
+        let api_keys = read_json::<Vec<serde_json::Value>>(res).await;
+        let id = api_keys[0]["id"].as_str().unwrap().parse().unwrap();
+        let res = server.call(request(&id)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        server.clear_cookies();
+        let res = server.call(list_request(Some(&key))).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    fn request(id: &Uuid) -> Request<Body> {
+        Request::builder()
+            .method("DELETE")
+            .uri(format!("/auth/api_keys/{id}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn list_request(key: Option<&str>) -> Request<Body> {
+        let mut req = Request::builder().method("GET").uri("/auth/api_keys");
+        if let Some(key) = key {
+            req = req.header(AUTHORIZATION, format!("Bearer {key}"));
+        }
+        req.body(Body::empty()).unwrap()
+    }
+}
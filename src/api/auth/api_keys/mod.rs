@@ -0,0 +1,5 @@
+crate::api::router! {
+    get,
+    post,
+    /:id,
+}
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, Json};
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    database::{
+        api_key::{self, ApiKey},
+        permission,
+    },
+    error::Error,
+    extractors::{validated::Form, User},
+    services::token::TokenService,
+    telemetry, Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    #[validate(length(
+        min = 1,
+        max = 100,
+        message = "must be between 1 and 100 characters long"
+    ))]
+    name: String,
+    scope: Option<String>,
+    #[validate(range(min = 1, message = "must be positive"))]
+    expires_in: Option<u64>,
+}
+
+/// The key itself is only ever returned here, as just its hash is stored.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedApiKey {
+    key: String,
+    #[serde(flatten)]
+    api_key: ApiKey,
+}
+
+#[tracing::instrument(
+    name = "Create API key",
+    skip_all,
+    fields(user_id = %user.id)
+)]
+pub async fn handler(
+    user: User,
+    State(pool): State<Pool>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<(StatusCode, Json<CreatedApiKey>)> {
+    // keys cannot create keys, or a leaked one could outlive its revocation
+    if user.credential.session_id().is_none() {
+        Err(Error::Forbidden).map_err(telemetry::warn)?;
+    }
+    let scopes: Option<Vec<_>> = payload
+        .scope
+        .as_deref()
+        .map(|scope| scope.split_whitespace().map(str::to_owned).collect());
+    if let Some(scopes) = &scopes {
+        if !permission::all_exist(scopes, &pool).await? {
+            Err(Error::UnknownScope).map_err(telemetry::warn)?;
+        }
+    }
+    let (prefix, key) = TokenService::generate_api_key();
+    let api_key = api_key::insert(
+        user.id,
+        &payload.name,
+        &prefix,
+        &key,
+        scopes.as_deref(),
+        payload.expires_in.map(Duration::from_secs),
+        &pool,
+    )
+    .await?;
+    let created = CreatedApiKey {
+        key: key.expose_secret().to_owned(),
+        api_key,
+    };
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE},
+            Request, StatusCode,
+        },
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_logged_out_user(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.call(request("name")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn fails_on_empty_name(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request("")).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[sqlx::test]
+    async fn fails_on_unknown_scope(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let req = request_with_scope("name", "users:read users:delete");
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let res = server.call(request_with_scope("name", "users:read")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+
+    #[sqlx::test]
+    async fn returns_key_with_its_prefix(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request("name")).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = read_json::<serde_json::Value>(res).await;
+        let key = body["key"].as_str().unwrap();
+        let prefix = body["prefix"].as_str().unwrap();
+        assert!(prefix.starts_with("ak_"));
+        assert!(key.starts_with(prefix));
+        assert_eq!(body["name"], "name");
+        assert!(body["lastUsedAt"].is_null());
+    }
+
+    #[sqlx::test]
+    async fn fails_for_api_key(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let key = TestUser::create_api_key(&mut server, None).await;
+        server.clear_cookies();
+        let mut req = request("name");
+        req.headers_mut()
+            .insert(AUTHORIZATION, format!("Bearer {key}").parse().unwrap());
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    fn request(name: &str) -> Request<Body> {
+        let body = serde_urlencoded::to_string([("name", name)]).unwrap();
+        request_with_body(body)
+    }
+
+    fn request_with_scope(name: &str, scope: &str) -> Request<Body> {
+        let body = [("name", name), ("scope", scope)];
+        request_with_body(serde_urlencoded::to_string(body).unwrap())
+    }
+
+    fn request_with_body(body: String) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/auth/api_keys")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
@@ -5,13 +5,19 @@ use serde::Deserialize;
 use validator::Validate;
 
 use crate::{
-    database::Executor,
+    api::auth::begin_attempt,
+    config::auth,
+    database::{
+        api_key, begin_transaction, commit,
+        login_failure::{self, Subject},
+        revoked_access_token, session, Executor,
+    },
     domain::validated_password::{
         ascii, at_least_8, at_most_32, digit, lowercase, uppercase, Password,
     },
     error::Error,
-    extractors::{validated::Form, User},
-    services::hash::PasswordHasher,
+    extractors::{validated::Form, ClientInfo, User},
+    services::{denylist::AccessTokenDenylist, hash::PasswordHasher},
     telemetry, Pool,
 };
 
@@ -49,12 +55,20 @@ pub struct Payload {
 
 pub async fn handler(
     user: User,
+    client: ClientInfo,
+    State(auth_config): State<auth::Config>,
     State(password_hasher): State<PasswordHasher>,
+    State(access_token_denylist): State<AccessTokenDenylist>,
     State(pool): State<Pool>,
     Form(payload): Form<Payload>,
 ) -> crate::Result<StatusCode> {
-    let expected_password_hash = get_password_hash(user.id, &pool)
-        .await?
+    let credentials = get_credentials(user.id, &pool).await?;
+    // accounts without an email can only be throttled by their id
+    let account = credentials.email.unwrap_or_else(|| user.id.to_string());
+    let subjects = Subject::all(&account, client.ip_address);
+    let attempt = begin_attempt(&subjects, &auth_config.lockout, &pool).await?;
+    let expected_password_hash = credentials
+        .password_hash
         .unwrap_or_else(|| password_hasher.mock_password_hash());
     let moved_password_hasher = password_hasher.clone();
     let is_password_valid = telemetry::instrument_blocking_task(move || {
@@ -63,23 +77,54 @@ pub async fn handler(
     })
     .await??;
     if !is_password_valid {
-        Err(Error::InvalidPassword).map_err(telemetry::warn)?;
+        return Err(attempt.fail(Error::InvalidPassword))
+            .map_err(telemetry::warn);
     }
+    attempt.release(&pool).await?;
+    login_failure::clear(Subject::Account(&account), &pool).await?;
     let new_password_hash = telemetry::instrument_blocking_task(move || {
         password_hasher.hash_password(payload.new_password.as_ref())
     })
     .await??;
-    update_password_hash(user.id, new_password_hash, &pool).await?;
+    // other sessions and API keys might belong to whoever
+    // learned the old password
+    let mut transaction = begin_transaction(&pool).await?;
+    update_password_hash(user.id, new_password_hash, &mut transaction).await?;
+    let revoked = revoked_access_token::revoke_for_user(
+        user.id,
+        user.credential.session_id(),
+        &mut transaction,
+    )
+    .await?;
+    session::delete_all_for_user(
+        user.id,
+        user.credential.session_id(),
+        &mut transaction,
+    )
+    .await?;
+    api_key::delete_all_for_user(
+        user.id,
+        user.credential.api_key_id(),
+        &mut transaction,
+    )
+    .await?;
+    commit(transaction).await?;
+    access_token_denylist.extend(&revoked);
     Ok(StatusCode::OK)
 }
 
-async fn get_password_hash<'e, E: Executor<'e>>(
+struct Credentials {
+    email: Option<String>,
+    password_hash: Option<Secret<String>>,
+}
+
+async fn get_credentials<'e, E: Executor<'e>>(
     user_id: i64,
     executor: E,
-) -> anyhow::Result<Option<Secret<String>>> {
+) -> anyhow::Result<Credentials> {
     sqlx::query!(
         r#"
-        select password_hash
+        select email, password_hash
         from users
         where id = $1;
         "#,
@@ -87,7 +132,10 @@ async fn get_password_hash<'e, E: Executor<'e>>(
     )
     .fetch_one(executor)
     .await
-    .map(|r| r.password_hash.map(Secret::new))
+    .map(|r| Credentials {
+        email: r.email,
+        password_hash: r.password_hash.map(Secret::new),
+    })
     .context("Failed to get password hash from the database")
 }
 
@@ -110,3 +158,74 @@ async fn update_password_hash<'e, E: Executor<'e>>(
     .map(|_| ())
     .context("Failed to update password hash in the database")
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE},
+            Request, StatusCode,
+        },
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    const NEW_PASSWORD: &str = "New-password-123";
+
+    #[sqlx::test]
+    async fn revokes_other_sessions(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = TestUser::login_for_tokens(&mut server).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let other_access_token = body["accessToken"].as_str().unwrap();
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        let res = server.call(protected_request(None)).await;
+        assert!(res.status().is_success());
+        server.clear_cookies();
+        let req = protected_request(Some(other_access_token));
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn revokes_api_keys(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let key = TestUser::create_api_key(&mut server, None).await;
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        server.clear_cookies();
+        let res = server.call(protected_request(Some(&key))).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    fn request() -> Request<Body> {
+        let body = serde_urlencoded::to_string([
+            ("current_password", TestUser::password().as_str()),
+            ("new_password", NEW_PASSWORD),
+        ])
+        .unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/auth/change_password")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn protected_request(access_token: Option<&str>) -> Request<Body> {
+        let mut req = Request::builder()
+            .method("GET")
+            .uri("/health_check/protected");
+        if let Some(access_token) = access_token {
+            req = req.header(AUTHORIZATION, format!("Bearer {access_token}"));
+        }
+        req.body(Body::empty()).unwrap()
+    }
+}
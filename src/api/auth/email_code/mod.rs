@@ -0,0 +1,151 @@
+use std::net::IpAddr;
+
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{
+    api::auth::begin_attempt,
+    config::auth,
+    database::{
+        begin_transaction, commit,
+        email_code::{self, Purpose},
+        login_failure::{self, Subject},
+        Executor,
+    },
+    error::Error,
+    services::{
+        email::{EmailClient, SendEmailRequest},
+        hash::PasswordHasher,
+        token::TokenService,
+    },
+    telemetry, Pool,
+};
+
+crate::api::router! {
+    post,
+    /verify,
+}
+
+/// Replaces the previous code for the same purpose
+/// and emails the new one to the user. Nothing is sent
+/// while the previous code is on cooldown, without telling the client,
+/// as that would reveal whether the account exists.
+#[tracing::instrument(
+    name = "Issue email code",
+    skip(recipient, auth_config, password_hasher, email_client, executor),
+    err(Debug)
+)]
+pub async fn issue_email_code<'e, E: Executor<'e>>(
+    user_id: i64,
+    purpose: Purpose,
+    recipient: &str,
+    auth_config: &auth::Config,
+    password_hasher: PasswordHasher,
+    email_client: &EmailClient,
+    executor: E,
+) -> anyhow::Result<()> {
+    let code = TokenService::generate_email_code();
+    let code_hash = {
+        let code = code.clone();
+        telemetry::instrument_blocking_task(move || {
+            password_hasher.hash_password(&code)
+        })
+        .await??
+    };
+    let is_issued = email_code::upsert(
+        user_id,
+        purpose,
+        &code_hash,
+        auth_config.email_code_ttl,
+        auth_config.email_code_cooldown,
+        executor,
+    )
+    .await?;
+    if !is_issued {
+        tracing::info!("Email code is on cooldown");
+        return Ok(());
+    }
+    send_email_code(email_client, recipient, purpose, &code).await
+}
+
+/// Consumes a matching code and returns the id of its owner.
+/// A wrong guess counts as a failed attempt even though
+/// the request fails, both against the code, which is no longer accepted
+/// once `email_code_max_failed_attempts` is reached, and against
+/// the same lockout as password attempts.
+#[tracing::instrument(
+    name = "Consume email code",
+    skip(code, auth_config, password_hasher, pool)
+)]
+pub async fn consume_email_code(
+    email: &str,
+    purpose: Purpose,
+    code: &Secret<String>,
+    ip_address: Option<IpAddr>,
+    auth_config: &auth::Config,
+    password_hasher: PasswordHasher,
+    pool: &Pool,
+) -> crate::Result<i64> {
+    let subjects = Subject::all(email, ip_address);
+    let attempt = begin_attempt(&subjects, &auth_config.lockout, pool).await?;
+    let mut transaction = begin_transaction(pool).await?;
+    let stored = email_code::find_for_update(email, purpose, &mut transaction)
+        .await?
+        .filter(|stored| {
+            stored.failed_attempts < auth_config.email_code_max_failed_attempts
+        });
+    let Some(stored) = stored else {
+        return Err(attempt.fail(Error::InvalidEmailCode))
+            .map_err(telemetry::warn);
+    };
+    let is_valid = {
+        let code = code.clone();
+        let code_hash = stored.code_hash;
+        telemetry::instrument_blocking_task(move || {
+            password_hasher.verify_password(&code, &code_hash)
+        })
+        .await??
+    };
+    if !is_valid {
+        email_code::record_failed_attempt(
+            stored.user_id,
+            purpose,
+            &mut transaction,
+        )
+        .await?;
+        commit(transaction).await?;
+        return Err(attempt.fail(Error::InvalidEmailCode))
+            .map_err(telemetry::warn);
+    }
+    email_code::delete(stored.user_id, purpose, &mut transaction).await?;
+    commit(transaction).await?;
+    attempt.release(pool).await?;
+    login_failure::clear(Subject::Account(email), pool).await?;
+    Ok(stored.user_id)
+}
+
+#[tracing::instrument(name = "Send email code", skip(email_client, code))]
+async fn send_email_code(
+    email_client: &EmailClient,
+    recipient: &str,
+    purpose: Purpose,
+    code: &Secret<String>,
+) -> anyhow::Result<()> {
+    let (subject, text) = match purpose {
+        Purpose::Verification => {
+            ("Account verification", "Your verification code is")
+        }
+        Purpose::Login => ("Log in", "Your login code is"),
+    };
+    let code = code.expose_secret();
+    let request = SendEmailRequest {
+        recipient,
+        subject,
+        text_body: &format!("{text} {code}"),
+        html_body: &format!("<p>{text} <b>{code}</b></p>"),
+    };
+    email_client
+        .send_email(&request)
+        .await
+        .context("Failed to send an email code")
+}
@@ -0,0 +1,127 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{
+    api::auth::email_code::issue_email_code,
+    config::auth,
+    database::{begin_transaction, commit, email_code::Purpose, Executor},
+    extractors::validated::Form,
+    services::{email::EmailClient, hash::PasswordHasher},
+    telemetry, Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    #[validate(email(message = "is not a valid email"))]
+    email: String,
+}
+
+#[tracing::instrument(
+    name = "Request login code",
+    skip_all,
+    fields(email = %payload.email)
+)]
+pub async fn handler(
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(password_hasher): State<PasswordHasher>,
+    State(email_client): State<EmailClient>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<StatusCode> {
+    let user_id = match find_user_id(&payload.email, &pool).await? {
+        Some(id) => id,
+        None => return Ok(StatusCode::ACCEPTED),
+    };
+    let mut transaction = begin_transaction(&pool).await?;
+    // responding with an error would reveal that the account exists
+    let is_sent = issue_email_code(
+        user_id,
+        Purpose::Login,
+        &payload.email,
+        &auth_config,
+        password_hasher,
+        &email_client,
+        &mut transaction,
+    )
+    .await
+    .map_err(telemetry::error)
+    .is_ok();
+    if is_sent {
+        commit(transaction).await?;
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[tracing::instrument(name = "Find user by email", skip(executor), err(Debug))]
+async fn find_user_id<'e, E: Executor<'e>>(
+    email: &str,
+    executor: E,
+) -> anyhow::Result<Option<i64>> {
+    let id = sqlx::query!(
+        r#"
+        select id
+        from users
+        where email = $1;
+        "#,
+        email
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to select user id from database")?
+    .map(|r| r.id);
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use wiremock::ResponseTemplate;
+
+    use crate::{
+        test_helpers::{
+            extract_email_code, when_sending_an_email, TestServer, TestUser,
+        },
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn does_not_reveal_unknown_email(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let mock = when_sending_an_email()
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0);
+        server.mount_mock(mock).await;
+        let res = TestUser::request_email_code(&mut server).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+    }
+
+    #[sqlx::test]
+    async fn sends_email_with_code(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::request_email_code(&mut server).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let email_requests = server.received_emails().await;
+        assert_eq!(email_requests.len(), 2);
+        let code = extract_email_code(email_requests.last().unwrap());
+        assert_eq!(code.len(), 6);
+    }
+
+    #[sqlx::test]
+    async fn does_not_send_another_code_within_cooldown(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        TestUser::request_email_code(&mut server).await;
+        let code =
+            extract_email_code(server.received_emails().await.last().unwrap());
+        let res = TestUser::request_email_code(&mut server).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert_eq!(server.received_emails().await.len(), 2);
+        let res = TestUser::submit_email_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,286 @@
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use secrecy::Secret;
+use serde::Deserialize;
+use tower_cookies::Cookies;
+use validator::Validate;
+
+use crate::{
+    api::auth::{
+        email_code::consume_email_code, ensure_transport, start_session,
+        two_factor::issue_challenge_if_enabled, Login,
+    },
+    config::auth::{self, TokenTransport},
+    database::{begin_transaction, commit, email_code::Purpose, Executor},
+    extractors::{validated::Form, ClientInfo},
+    services::{
+        cookie::CookieService, hash::PasswordHasher, token::TokenService,
+    },
+    Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct Payload {
+    #[validate(email(message = "is not a valid email"))]
+    email: String,
+    code: Secret<String>,
+    #[validate(length(
+        max = 100,
+        message = "cannot be longer than 100 characters"
+    ))]
+    device_label: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Log in with email code",
+    skip_all,
+    fields(email = %payload.email)
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn handler(
+    cookies: Cookies,
+    client: ClientInfo,
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(password_hasher): State<PasswordHasher>,
+    State(token_service): State<TokenService>,
+    State(cookie_service): State<CookieService>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<Response> {
+    ensure_transport(
+        auth_config.token_transport,
+        TokenTransport::accepts_cookie,
+    )?;
+    let tokens = match log_in(
+        &payload,
+        &client,
+        &auth_config,
+        &pool,
+        password_hasher,
+        &token_service,
+    )
+    .await?
+    {
+        Login::Session(tokens) => tokens,
+        Login::Challenge(challenge) => {
+            return Ok((StatusCode::ACCEPTED, Json(challenge)).into_response())
+        }
+    };
+    cookie_service.set_access_token(&cookies, tokens.access_token);
+    cookie_service.set_refresh_token(&cookies, tokens.refresh_token);
+    Ok(StatusCode::OK.into_response())
+}
+
+/// Receiving the code proves ownership of the email,
+/// so the user is marked as verified as well.
+pub async fn log_in(
+    payload: &Payload,
+    client: &ClientInfo,
+    auth_config: &auth::Config,
+    pool: &Pool,
+    password_hasher: PasswordHasher,
+    token_service: &TokenService,
+) -> crate::Result<Login> {
+    let user_id = consume_email_code(
+        &payload.email,
+        Purpose::Login,
+        &payload.code,
+        client.ip_address,
+        auth_config,
+        password_hasher,
+        pool,
+    )
+    .await?;
+    verify_user(user_id, pool).await?;
+    let device_label = payload.device_label.as_deref();
+    if let Some(challenge) = issue_challenge_if_enabled(
+        user_id,
+        device_label,
+        auth_config.two_factor_challenge_ttl,
+        pool,
+    )
+    .await?
+    {
+        return Ok(Login::Challenge(challenge));
+    }
+    let mut transaction = begin_transaction(pool).await?;
+    let tokens = start_session(
+        user_id,
+        device_label,
+        client,
+        auth_config,
+        token_service,
+        &mut transaction,
+    )
+    .await?;
+    commit(transaction).await?;
+    Ok(Login::Session(tokens))
+}
+
+async fn verify_user<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update users
+        set verified = true
+        where id = $1;
+        "#,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to verify user")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        config::auth::TokenTransport,
+        test_helpers::{extract_email_code, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn logs_user_in_once(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        TestUser::request_email_code(&mut server).await;
+        let code = last_code(&server).await;
+        let res = TestUser::submit_email_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let req = Request::builder()
+            .method("GET")
+            .uri("/health_check/protected")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert!(res.status().is_success());
+        let res = TestUser::submit_email_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn discards_code_after_too_many_failures(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.email_code_max_failed_attempts = 2;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        TestUser::request_email_code(&mut server).await;
+        let code = last_code(&server).await;
+        let wrong_code = if code == "000000" { "000001" } else { "000000" };
+        let res = TestUser::submit_email_code(&mut server, wrong_code).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = TestUser::submit_email_code(&mut server, wrong_code).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = TestUser::submit_email_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn keeps_failed_attempts_across_reissues(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.email_code_max_failed_attempts = 2;
+            config.auth.email_code_cooldown = Duration::ZERO;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        for _ in 0..2 {
+            TestUser::request_email_code(&mut server).await;
+            let code = last_code(&server).await;
+            let wrong_code = if code == "000000" { "000001" } else { "000000" };
+            let res =
+                TestUser::submit_email_code(&mut server, wrong_code).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+        TestUser::request_email_code(&mut server).await;
+        let code = last_code(&server).await;
+        let res = TestUser::submit_email_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn locks_out_after_failed_attempts(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.lockout.account_threshold = 2;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        TestUser::request_email_code(&mut server).await;
+        let code = last_code(&server).await;
+        let wrong_code = if code == "000000" { "000001" } else { "000000" };
+        let res = TestUser::submit_email_code(&mut server, wrong_code).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = TestUser::submit_email_code(&mut server, wrong_code).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let res = TestUser::submit_email_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[sqlx::test]
+    async fn rejects_expired_code(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        TestUser::request_email_code(&mut server).await;
+        sqlx::query!(r#"update email_codes set expires_at = now();"#)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let code = last_code(&server).await;
+        let res = TestUser::submit_email_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn requires_second_factor(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::enable_two_factor(&mut server).await;
+        server.clear_cookies();
+        TestUser::request_email_code(&mut server).await;
+        let code = last_code(&server).await;
+        let res = TestUser::submit_email_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert!(server.cookie("refresh_token").is_none());
+    }
+
+    #[sqlx::test]
+    async fn fails_if_cookie_transport_is_disabled(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.token_transport = TokenTransport::Bearer;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        TestUser::request_email_code(&mut server).await;
+        let code = last_code(&server).await;
+        let res = TestUser::submit_email_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(server.cookie("refresh_token").is_none());
+    }
+
+    async fn last_code(server: &TestServer) -> String {
+        extract_email_code(server.received_emails().await.last().unwrap())
+    }
+}
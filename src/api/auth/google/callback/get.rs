@@ -3,13 +3,14 @@ use axum::{
     extract::{Query, State},
     http::StatusCode,
 };
-use secrecy::{ExposeSecret, Secret};
 use tower_cookies::Cookies;
-use uuid::Uuid;
 
 use crate::{
+    api::auth::{ensure_transport, start_session},
+    config::auth::{self, TokenTransport},
     database::{begin_transaction, commit, Executor},
     error::Error,
+    extractors::ClientInfo,
     services::{
         cookie::CookieService,
         oauth::{AuthRequest, OauthClient, User},
@@ -18,62 +19,49 @@ use crate::{
     telemetry, Pool,
 };
 
+#[allow(clippy::too_many_arguments)]
 pub async fn handler(
     cookies: Cookies,
+    client: ClientInfo,
     Query(auth_req): Query<AuthRequest>,
+    State(auth_config): State<auth::Config>,
     State(pool): State<Pool>,
     State(oauth_client): State<OauthClient>,
     State(token_service): State<TokenService>,
     State(cookie_service): State<CookieService>,
 ) -> crate::Result<StatusCode> {
+    ensure_transport(
+        auth_config.token_transport,
+        TokenTransport::accepts_cookie,
+    )?;
     let user = oauth_client.fetch_google_user(auth_req).await?;
     let mut transaction = begin_transaction(&pool).await?;
-    let user = match get_db_user(&user.email, &mut transaction).await? {
-        Some(user) => user,
-        None => {
-            let verification_token = Uuid::new_v4();
-            let id = insert_user_returning_id(
-                &user,
-                &verification_token,
-                &mut transaction,
-            )
-            .await?;
-            DbUser {
-                id,
-                refresh_token: None,
-            }
-        }
+    let user_id = match get_user_id(&user.email, &mut transaction).await? {
+        Some(id) => id,
+        None => insert_user_returning_id(&user, &mut transaction).await?,
     };
-    let refresh_token = match user.refresh_token {
-        Some(token) => token,
-        None => {
-            let token = TokenService::generate_refresh_token();
-            insert_refresh_token(user.id, &token, &mut transaction).await?;
-            token
-        }
-    };
-    let access_token = telemetry::instrument_blocking_task(move || {
-        token_service.generate_access_token(user.id)
-    })
-    .await??;
-    cookie_service.set_access_token(&cookies, access_token);
-    cookie_service.set_refresh_token(&cookies, refresh_token);
+    let tokens = start_session(
+        user_id,
+        None,
+        &client,
+        &auth_config,
+        &token_service,
+        &mut transaction,
+    )
+    .await?;
+    cookie_service.set_access_token(&cookies, tokens.access_token);
+    cookie_service.set_refresh_token(&cookies, tokens.refresh_token);
     commit(transaction).await?;
     Ok(StatusCode::OK)
 }
 
-struct DbUser {
-    id: i64,
-    refresh_token: Option<Secret<String>>,
-}
-
-async fn get_db_user<'e, E: Executor<'e>>(
+async fn get_user_id<'e, E: Executor<'e>>(
     email: &str,
     executor: E,
-) -> anyhow::Result<Option<DbUser>> {
-    match sqlx::query!(
+) -> anyhow::Result<Option<i64>> {
+    let id = sqlx::query!(
         r#"
-        select id, refresh_token
+        select id
         from users
         where email = $1;
         "#,
@@ -82,37 +70,25 @@ async fn get_db_user<'e, E: Executor<'e>>(
     .fetch_optional(executor)
     .await
     .context("Failed to get db user")?
-    {
-        Some(row) => {
-            let user = DbUser {
-                id: row.id,
-                refresh_token: row.refresh_token.map(Secret::new),
-            };
-            Ok(Some(user))
-        }
-        None => Ok(None),
-    }
+    .map(|r| r.id);
+    Ok(id)
 }
 
 async fn insert_user_returning_id<'e, E: Executor<'e>>(
     user: &User,
-    verification_token: &Uuid,
     executor: E,
 ) -> crate::Result<i64> {
     match sqlx::query!(
         r#"
-        insert into users (
-          name, email, verified, picture_url, verification_token
-        )
-        values ($1, $2, $3, $4, $5)
+        insert into users (name, email, verified, picture_url)
+        values ($1, $2, $3, $4)
         on conflict do nothing
         returning id;
         "#,
         user.name,
         user.email,
         user.email_verified,
-        user.picture_url,
-        verification_token
+        user.picture_url
     )
     .fetch_optional(executor)
     .await
@@ -122,23 +98,3 @@ async fn insert_user_returning_id<'e, E: Executor<'e>>(
         None => Err(Error::EmailTaken).map_err(telemetry::warn),
     }
 }
-
-async fn insert_refresh_token<'e, E: Executor<'e>>(
-    user_id: i64,
-    refresh_token: &Secret<String>,
-    executor: E,
-) -> anyhow::Result<()> {
-    sqlx::query!(
-        r#"
-        update users
-        set refresh_token = $1
-        where id = $2;
-        "#,
-        refresh_token.expose_secret(),
-        user_id
-    )
-    .execute(executor)
-    .await
-    .map(|_| ())
-    .context("Failed to update refresh token for user")
-}
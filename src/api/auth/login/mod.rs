@@ -1,3 +1,5 @@
 crate::api::router! {
     post,
+    /token,
+    /two_factor = "2fa",
 }
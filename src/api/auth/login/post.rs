@@ -1,14 +1,28 @@
 use anyhow::Context;
-use axum::{extract::State, http::StatusCode};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
 use tower_cookies::Cookies;
 use validator::Validate;
 
 use crate::{
-    database::Executor,
+    api::auth::{
+        begin_attempt, ensure_transport, start_session,
+        two_factor::issue_challenge_if_enabled, Login,
+    },
+    config::auth::{self, TokenTransport},
+    database::{
+        begin_transaction, commit,
+        login_failure::{self, Subject},
+        Executor,
+    },
     error::Error,
-    extractors::validated::Form,
+    extractors::{validated::Form, ClientInfo},
     services::{
         cookie::CookieService, hash::PasswordHasher, token::TokenService,
     },
@@ -20,6 +34,11 @@ use crate::{
 pub struct Payload {
     email: String,
     password: Secret<String>,
+    #[validate(length(
+        max = 100,
+        message = "cannot be longer than 100 characters"
+    ))]
+    device_label: Option<String>,
 }
 
 #[tracing::instrument(
@@ -27,47 +46,110 @@ pub struct Payload {
     skip_all,
     fields(email = %payload.email)
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn handler(
     cookies: Cookies,
+    client: ClientInfo,
+    State(auth_config): State<auth::Config>,
     State(pool): State<Pool>,
     State(password_hasher): State<PasswordHasher>,
     State(token_service): State<TokenService>,
     State(cookie_service): State<CookieService>,
     Form(payload): Form<Payload>,
-) -> crate::Result<StatusCode> {
-    let user = find_user(&payload.email, &pool).await?;
+) -> crate::Result<Response> {
+    ensure_transport(
+        auth_config.token_transport,
+        TokenTransport::accepts_cookie,
+    )?;
+    let tokens = match log_in(
+        &payload,
+        &client,
+        &auth_config,
+        &pool,
+        password_hasher,
+        &token_service,
+    )
+    .await?
+    {
+        Login::Session(tokens) => tokens,
+        Login::Challenge(challenge) => {
+            return Ok((StatusCode::ACCEPTED, Json(challenge)).into_response())
+        }
+    };
+    cookie_service.set_access_token(&cookies, tokens.access_token);
+    cookie_service.set_refresh_token(&cookies, tokens.refresh_token);
+    Ok(StatusCode::OK.into_response())
+}
+
+/// Checks the credentials and either starts a session
+/// or asks for the second factor if the user has one.
+pub async fn log_in(
+    payload: &Payload,
+    client: &ClientInfo,
+    auth_config: &auth::Config,
+    pool: &Pool,
+    password_hasher: PasswordHasher,
+    token_service: &TokenService,
+) -> crate::Result<Login> {
+    let subjects = Subject::all(&payload.email, client.ip_address);
+    let attempt = begin_attempt(&subjects, &auth_config.lockout, pool).await?;
+    let user = find_user(&payload.email, pool).await?;
+    let needs_rehash = match &user.password_hash {
+        Some(password_hash) => password_hasher.needs_rehash(password_hash)?,
+        None => false,
+    };
     let password_hash = user
         .password_hash
         .unwrap_or_else(|| password_hasher.mock_password_hash());
+    let password = payload.password.clone();
+    let hasher = password_hasher.clone();
     let is_password_valid = instrument_blocking_task(move || {
-        password_hasher.verify_password(&payload.password, &password_hash)
+        hasher.verify_password(&password, &password_hash)
     })
     .await??;
     if !is_password_valid {
-        Err(Error::InvalidCredentials).map_err(telemetry::warn)?;
+        return Err(attempt.fail(Error::InvalidCredentials))
+            .map_err(telemetry::warn);
     }
-    let access_token = instrument_blocking_task(move || {
-        token_service.generate_access_token(user.id)
-    })
-    .await??;
-    let refresh_token = match user.refresh_token {
-        Some(token) => token,
-        None => {
-            let token = TokenService::generate_refresh_token();
-            save_refresh_token(user.id, &token, &pool).await?;
-            token
-        }
-    };
-    cookie_service.set_access_token(&cookies, access_token);
-    cookie_service.set_refresh_token(&cookies, refresh_token);
-    Ok(StatusCode::OK)
+    attempt.release(pool).await?;
+    if needs_rehash {
+        rehash_password(user.id, &payload.password, password_hasher, pool)
+            .await?;
+    }
+    if auth_config.require_verified_email && !user.verified {
+        Err(Error::UnverifiedEmail).map_err(telemetry::warn)?;
+    }
+    if let Some(challenge) = issue_challenge_if_enabled(
+        user.id,
+        payload.device_label.as_deref(),
+        auth_config.two_factor_challenge_ttl,
+        pool,
+    )
+    .await?
+    {
+        // failures keep counting until the second factor is checked too
+        return Ok(Login::Challenge(challenge));
+    }
+    login_failure::clear(Subject::Account(&payload.email), pool).await?;
+    let mut transaction = begin_transaction(pool).await?;
+    let tokens = start_session(
+        user.id,
+        payload.device_label.as_deref(),
+        client,
+        auth_config,
+        token_service,
+        &mut transaction,
+    )
+    .await?;
+    commit(transaction).await?;
+    Ok(Login::Session(tokens))
 }
 
 #[derive(Clone, Debug, Default)]
 struct User {
     id: i64,
     password_hash: Option<Secret<String>>,
-    refresh_token: Option<Secret<String>>,
+    verified: bool,
 }
 
 #[tracing::instrument(name = "Find user by email", skip(executor), err(Debug))]
@@ -77,7 +159,7 @@ async fn find_user<'e, E: Executor<'e>>(
 ) -> anyhow::Result<User> {
     match sqlx::query!(
         r#"
-        select id, password_hash, refresh_token
+        select id, password_hash, verified
         from users
         where email = $1;
         "#,
@@ -91,46 +173,59 @@ async fn find_user<'e, E: Executor<'e>>(
         Some(r) => Ok(User {
             id: r.id,
             password_hash: r.password_hash.map(Secret::new),
-            refresh_token: r.refresh_token.map(Secret::new),
+            verified: r.verified,
         }),
         None => Ok(User::default()),
     }
 }
 
-#[tracing::instrument(
-    name = "Save user's refresh token",
-    skip(executor),
-    err(Debug)
-)]
-async fn save_refresh_token<'e, E: Executor<'e>>(
+/// Replaces a hash peppered with the legacy pepper,
+/// see [`PasswordHasher`] for the migration.
+#[tracing::instrument(name = "Rehash password", skip_all, err(Debug))]
+async fn rehash_password<'e, E: Executor<'e>>(
     user_id: i64,
-    refresh_token: &Secret<String>,
+    password: &Secret<String>,
+    password_hasher: PasswordHasher,
     executor: E,
 ) -> anyhow::Result<()> {
+    let password = password.clone();
+    let password_hash = instrument_blocking_task(move || {
+        password_hasher.hash_password(&password)
+    })
+    .await??;
     sqlx::query!(
         r#"
         update users
-        set refresh_token = $1
+        set password_hash = $1
         where id = $2;
         "#,
-        refresh_token.expose_secret(),
+        password_hash.expose_secret(),
         user_id
     )
     .execute(executor)
     .await
     .map(|_| ())
-    .context("Failed to set user's refresh token")
+    .context("Failed to update password hash in the database")
 }
 
 #[cfg(test)]
 mod tests {
+    use std::net::SocketAddr;
+
+    use argon2::{password_hash::SaltString, Argon2, Params, PasswordHasher};
+    use secrecy::ExposeSecret;
+
     use crate::{
-        test_helpers::{TestServer, TestUser},
-        Pool,
+        test_helpers::{read_json, TestServer, TestUser},
+        Config, Pool,
     };
     use axum::{
         body::Body,
-        http::{header::CONTENT_TYPE, Request, StatusCode},
+        extract::ConnectInfo,
+        http::{
+            header::{CONTENT_TYPE, RETRY_AFTER, SET_COOKIE},
+            Request, StatusCode,
+        },
     };
 
     #[sqlx::test]
@@ -156,6 +251,32 @@ mod tests {
         assert!(res.status().is_success());
     }
 
+    #[sqlx::test]
+    async fn rejects_unverified_email_if_required(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.require_verified_email = true;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn accepts_verified_email_if_required(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.require_verified_email = true;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::verify(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login(&mut server).await;
+        assert!(res.status().is_success());
+    }
+
     #[sqlx::test]
     async fn logs_user_in(pool: Pool) {
         let mut server = TestServer::new(pool).await;
@@ -165,9 +286,143 @@ mod tests {
             .uri("/health_check/protected")
             .body(Body::empty())
             .unwrap();
-        let _res = server.call(req).await;
-        // TODO find a way to handle cookies
-        // assert!(res.status().is_success());
+        let res = server.call(req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[sqlx::test]
+    async fn sets_same_site_cookies(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login(&mut server).await;
+        assert!(res.status().is_success());
+        let cookies = res.headers().get_all(SET_COOKIE);
+        assert_eq!(cookies.iter().count(), 2);
+        for cookie in cookies {
+            assert!(cookie.to_str().unwrap().contains("SameSite=Lax"));
+        }
+    }
+
+    #[sqlx::test]
+    async fn returns_challenge_if_two_factor_is_enabled(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::enable_two_factor(&mut server).await;
+        server.clear_cookies();
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert!(server.cookie("access_token").is_none());
+        assert!(server.cookie("refresh_token").is_none());
+        let body = read_json::<serde_json::Value>(res).await;
+        assert!(body["challenge"].is_string());
+    }
+
+    #[sqlx::test]
+    async fn locks_account_out_after_repeated_failures(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.lockout.account_threshold = 3;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        for _ in 0..2 {
+            let res = server.call(request(&TestUser::email(), "wrong")).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+        let res = server.call(request(&TestUser::email(), "wrong")).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after = res.headers()[RETRY_AFTER].to_str().unwrap();
+        assert!(retry_after.parse::<u64>().unwrap() > 0);
+    }
+
+    #[sqlx::test]
+    async fn locks_client_out_across_accounts(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.lockout.ip_threshold = 3;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let peer = SocketAddr::from(([10, 0, 0, 1], 443));
+        for i in 0..3 {
+            let email = format!("{i}@domain.com");
+            let mut req = request(&email, "wrong");
+            req.extensions_mut().insert(ConnectInfo(peer));
+            server.call(req).await;
+        }
+        let mut req = request(&TestUser::email(), &TestUser::password());
+        req.extensions_mut().insert(ConnectInfo(peer));
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let res = TestUser::login(&mut server).await;
+        assert!(res.status().is_success());
+    }
+
+    #[sqlx::test]
+    async fn resets_failures_on_success(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.lockout.account_threshold = 2;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        for _ in 0..2 {
+            let res = server.call(request(&TestUser::email(), "wrong")).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            let res = TestUser::login(&mut server).await;
+            assert!(res.status().is_success());
+        }
+    }
+
+    #[sqlx::test]
+    async fn rehashes_password_peppered_with_legacy_pepper(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let config = Config::new().unwrap();
+        let params = Params::new(
+            config.password_hasher.m_cost,
+            config.password_hasher.t_cost,
+            config.password_hasher.p_cost,
+            None,
+        )
+        .unwrap();
+        let legacy_hash = Argon2::new_with_secret(
+            config.server.hmac_secret.expose_secret().as_bytes(),
+            Default::default(),
+            Default::default(),
+            params,
+        )
+        .unwrap()
+        .hash_password(
+            TestUser::password().as_bytes(),
+            &SaltString::generate(&mut rand::thread_rng()),
+        )
+        .unwrap()
+        .to_string();
+        sqlx::query!(
+            "update users set password_hash = $1;",
+            legacy_hash.clone()
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let res = TestUser::login(&mut server).await;
+        assert!(res.status().is_success());
+        let password_hash = sqlx::query!(
+            r#"select password_hash as "password_hash!" from users;"#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .password_hash;
+        assert_ne!(password_hash, legacy_hash);
+        assert!(password_hash.contains("keyid="));
+        let res = TestUser::login(&mut server).await;
+        assert!(res.status().is_success());
     }
 
     fn request(email: &str, password: &str) -> Request<Body> {
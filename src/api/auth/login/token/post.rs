@@ -0,0 +1,100 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::{
+    api::auth::{
+        ensure_transport,
+        login::post::{log_in, Payload},
+        Login,
+    },
+    config::auth::{self, TokenTransport},
+    extractors::{validated::Form, ClientInfo},
+    services::{hash::PasswordHasher, token::TokenService},
+    Pool,
+};
+
+#[tracing::instrument(name = "Log in existing user for tokens", skip_all)]
+pub async fn handler(
+    client: ClientInfo,
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(password_hasher): State<PasswordHasher>,
+    State(token_service): State<TokenService>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<Response> {
+    ensure_transport(
+        auth_config.token_transport,
+        TokenTransport::accepts_bearer,
+    )?;
+    match log_in(
+        &payload,
+        &client,
+        &auth_config,
+        &pool,
+        password_hasher,
+        &token_service,
+    )
+    .await?
+    {
+        Login::Session(tokens) => {
+            Ok(tokens.into_json(&token_service).into_response())
+        }
+        Login::Challenge(challenge) => {
+            Ok((StatusCode::ACCEPTED, Json(challenge)).into_response())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+    };
+
+    use crate::{
+        config::auth::TokenTransport,
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn returns_tokens_in_body(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        server.clear_cookies();
+        let res = TestUser::login_for_tokens(&mut server).await;
+        assert!(res.status().is_success());
+        assert!(server.cookie("access_token").is_none());
+        assert!(server.cookie("refresh_token").is_none());
+        let body = read_json::<serde_json::Value>(res).await;
+        assert!(body["refreshToken"].is_string());
+        assert_eq!(body["tokenType"], "Bearer");
+        let access_token = body["accessToken"].as_str().unwrap();
+        let req = Request::builder()
+            .method("GET")
+            .uri("/health_check/protected")
+            .header(AUTHORIZATION, format!("Bearer {access_token}"))
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[sqlx::test]
+    async fn fails_if_bearer_transport_is_disabled(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.token_transport = TokenTransport::Cookie;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login_for_tokens(&mut server).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
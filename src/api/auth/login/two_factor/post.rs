@@ -0,0 +1,550 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use secrecy::Secret;
+use serde::Deserialize;
+use sqlx::Transaction;
+use tower_cookies::Cookies;
+use validator::Validate;
+
+use crate::{
+    api::auth::{
+        begin_attempt, ensure_transport, start_session, SessionTokens,
+    },
+    config::auth::{self, TokenTransport},
+    database::{
+        begin_transaction, commit,
+        login_failure::{self, Subject},
+        Database, Executor,
+    },
+    error::Error,
+    extractors::{validated::Form, ClientInfo},
+    services::{
+        cookie::CookieService, hash::PasswordHasher, token::TokenService,
+        totp::TotpService,
+    },
+    telemetry, Pool,
+};
+
+const MAX_FAILED_ATTEMPTS: i32 = 5;
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    challenge: Secret<String>,
+    code: String,
+}
+
+#[tracing::instrument(name = "Complete two-factor login", skip_all)]
+#[allow(clippy::too_many_arguments)]
+pub async fn handler(
+    cookies: Cookies,
+    client: ClientInfo,
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(totp_service): State<TotpService>,
+    State(token_service): State<TokenService>,
+    State(cookie_service): State<CookieService>,
+    State(password_hasher): State<PasswordHasher>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<StatusCode> {
+    ensure_transport(
+        auth_config.token_transport,
+        TokenTransport::accepts_cookie,
+    )?;
+    let tokens = complete_challenge(
+        &payload,
+        &client,
+        &auth_config,
+        &pool,
+        &totp_service,
+        &token_service,
+        password_hasher,
+    )
+    .await?;
+    cookie_service.set_access_token(&cookies, tokens.access_token);
+    cookie_service.set_refresh_token(&cookies, tokens.refresh_token);
+    Ok(StatusCode::OK)
+}
+
+/// Starts the session the challenge was issued for
+/// if the code is either a fresh TOTP code or an unused recovery code.
+/// Wrong codes count against the same lockout as passwords,
+/// so that logging in again does not bring more guesses.
+pub async fn complete_challenge(
+    payload: &Payload,
+    client: &ClientInfo,
+    auth_config: &auth::Config,
+    pool: &Pool,
+    totp_service: &TotpService,
+    token_service: &TokenService,
+    password_hasher: PasswordHasher,
+) -> crate::Result<SessionTokens> {
+    let mut transaction = begin_transaction(pool).await?;
+    let challenge =
+        match find_challenge_for_update(&payload.challenge, &mut transaction)
+            .await?
+        {
+            Some(challenge) => challenge,
+            None => Err(Error::InvalidTwoFactorChallenge)
+                .map_err(telemetry::warn)?,
+        };
+    let subjects = Subject::all(&challenge.account, client.ip_address);
+    let attempt = begin_attempt(&subjects, &auth_config.lockout, pool).await?;
+    let credential =
+        match find_credential_for_update(challenge.user_id, &mut transaction)
+            .await?
+        {
+            Some(credential) => credential,
+            None => Err(Error::InvalidTwoFactorChallenge)
+                .map_err(telemetry::warn)?,
+        };
+    let (secret, is_legacy) = totp_service
+        .decrypt_secret(challenge.user_id, &credential.encrypted_secret)?;
+    if is_legacy {
+        let encrypted_secret =
+            totp_service.encrypt_secret(challenge.user_id, &secret)?;
+        update_encrypted_secret(
+            challenge.user_id,
+            &encrypted_secret,
+            &mut transaction,
+        )
+        .await?;
+    }
+    let step = TotpService::verify_code(
+        &secret,
+        &payload.code,
+        credential.last_used_step,
+    );
+    let is_code_valid = match step {
+        Some(step) => {
+            update_last_used_step(challenge.user_id, step, &mut transaction)
+                .await?;
+            true
+        }
+        None => {
+            use_recovery_code(
+                challenge.user_id,
+                &payload.code,
+                password_hasher,
+                &mut transaction,
+            )
+            .await?
+        }
+    };
+    if !is_code_valid {
+        if challenge.failed_attempts + 1 >= MAX_FAILED_ATTEMPTS {
+            delete_challenge(&payload.challenge, &mut transaction).await?;
+        } else {
+            record_failed_attempt(&payload.challenge, &mut transaction).await?;
+        }
+        commit(transaction).await?;
+        return Err(attempt.fail(Error::InvalidTwoFactorCode))
+            .map_err(telemetry::warn);
+    }
+    delete_challenge(&payload.challenge, &mut transaction).await?;
+    let tokens = start_session(
+        challenge.user_id,
+        challenge.device_label.as_deref(),
+        client,
+        auth_config,
+        token_service,
+        &mut transaction,
+    )
+    .await?;
+    commit(transaction).await?;
+    attempt.release(pool).await?;
+    login_failure::clear(Subject::Account(&challenge.account), pool).await?;
+    Ok(tokens)
+}
+
+struct Challenge {
+    user_id: i64,
+    /// The email the user logs in with, or the id if they have none.
+    account: String,
+    device_label: Option<String>,
+    failed_attempts: i32,
+}
+
+struct RecoveryCode {
+    id: i64,
+    code_hash: Secret<String>,
+}
+
+struct Credential {
+    encrypted_secret: Vec<u8>,
+    last_used_step: Option<i64>,
+}
+
+#[tracing::instrument(name = "Find two-factor challenge", skip_all, err(Debug))]
+async fn find_challenge_for_update<'e, E: Executor<'e>>(
+    challenge: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<Option<Challenge>> {
+    sqlx::query_as!(
+        Challenge,
+        r#"
+        select
+          c.user_id,
+          coalesce(u.email, u.id::text) as "account!",
+          c.device_label,
+          c.failed_attempts
+        from two_factor_challenges c
+        join users u on u.id = c.user_id
+        where c.token_hash = $1 and c.expires_at > now()
+        for update of c;
+        "#,
+        TokenService::hash_token(challenge)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch two-factor challenge")
+}
+
+async fn find_credential_for_update<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<Option<Credential>> {
+    sqlx::query_as!(
+        Credential,
+        r#"
+        select encrypted_secret, last_used_step
+        from totp_credentials
+        where user_id = $1 and confirmed_at is not null
+        for update;
+        "#,
+        user_id
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch TOTP credential")
+}
+
+async fn update_encrypted_secret<'e, E: Executor<'e>>(
+    user_id: i64,
+    encrypted_secret: &[u8],
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update totp_credentials
+        set encrypted_secret = $2
+        where user_id = $1;
+        "#,
+        user_id,
+        encrypted_secret
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to update encrypted TOTP secret")
+}
+
+async fn update_last_used_step<'e, E: Executor<'e>>(
+    user_id: i64,
+    step: i64,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update totp_credentials
+        set last_used_step = $2
+        where user_id = $1;
+        "#,
+        user_id,
+        step
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to update last used TOTP step")
+}
+
+/// Marks the matching unused recovery code as used.
+#[tracing::instrument(
+    name = "Use recovery code",
+    skip(code, password_hasher, executor),
+    err(Debug)
+)]
+async fn use_recovery_code(
+    user_id: i64,
+    code: &str,
+    password_hasher: PasswordHasher,
+    executor: &mut Transaction<'_, Database>,
+) -> anyhow::Result<bool> {
+    let code = match TotpService::normalize_recovery_code(code) {
+        Some(code) => code,
+        None => return Ok(false),
+    };
+    let recovery_codes = sqlx::query!(
+        r#"
+        select id, code_hash
+        from recovery_codes
+        where user_id = $1 and used_at is null
+        for update;
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *executor)
+    .await
+    .context("Failed to fetch recovery codes")?
+    .into_iter()
+    .map(|r| RecoveryCode {
+        id: r.id,
+        code_hash: Secret::new(r.code_hash),
+    })
+    .collect::<Vec<_>>();
+    let matching_id = telemetry::instrument_blocking_task(move || {
+        for recovery_code in recovery_codes {
+            if password_hasher
+                .verify_password(&code, &recovery_code.code_hash)?
+            {
+                return Ok(Some(recovery_code.id));
+            }
+        }
+        anyhow::Ok(None)
+    })
+    .await??;
+    let id = match matching_id {
+        Some(id) => id,
+        None => return Ok(false),
+    };
+    sqlx::query!(
+        r#"
+        update recovery_codes
+        set used_at = now()
+        where id = $1;
+        "#,
+        id
+    )
+    .execute(&mut *executor)
+    .await
+    .context("Failed to mark recovery code as used")?;
+    Ok(true)
+}
+
+async fn record_failed_attempt<'e, E: Executor<'e>>(
+    challenge: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update two_factor_challenges
+        set failed_attempts = failed_attempts + 1
+        where token_hash = $1;
+        "#,
+        TokenService::hash_token(challenge)
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to record failed two-factor attempt")
+}
+
+async fn delete_challenge<'e, E: Executor<'e>>(
+    challenge: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        delete from two_factor_challenges
+        where token_hash = $1;
+        "#,
+        TokenService::hash_token(challenge)
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to delete two-factor challenge")
+}
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm::{
+        aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+        Aes256Gcm,
+    };
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+    use secrecy::{ExposeSecret, Secret};
+    use sha2::{Digest, Sha256};
+
+    use crate::{
+        config::Config,
+        services::totp::TotpService,
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn logs_user_in_with_valid_code(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let secret = TestUser::enable_two_factor(&mut server).await.secret;
+        server.clear_cookies();
+        let challenge = challenge(&mut server).await;
+        let res = server.call(request(&challenge, &next_code(&secret))).await;
+        assert!(res.status().is_success());
+        assert!(server.cookie("refresh_token").is_some());
+        let req = Request::builder()
+            .method("GET")
+            .uri("/health_check/protected")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[sqlx::test]
+    async fn encrypts_legacy_secret_again(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let secret = TestUser::enable_two_factor(&mut server).await.secret;
+        let config = Config::new().unwrap();
+        let hmac_secret = config.server.hmac_secret.expose_secret().as_bytes();
+        let legacy_cipher = Aes256Gcm::new(&Sha256::digest(hmac_secret));
+        let user_id = sqlx::query!("select user_id from totp_credentials;")
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .user_id;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: secret.expose_secret(),
+            aad: &user_id.to_be_bytes(),
+        };
+        let ciphertext = legacy_cipher.encrypt(&nonce, payload).unwrap();
+        let legacy_secret = [nonce.as_slice(), &ciphertext].concat();
+        sqlx::query!(
+            "update totp_credentials set encrypted_secret = $1;",
+            &legacy_secret
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        server.clear_cookies();
+        let challenge = challenge(&mut server).await;
+        let res = server.call(request(&challenge, &next_code(&secret))).await;
+        assert!(res.status().is_success());
+        let encrypted_secret =
+            sqlx::query!("select encrypted_secret from totp_credentials;")
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+                .encrypted_secret;
+        assert_ne!(encrypted_secret, legacy_secret);
+        let (decrypted, is_legacy) = config
+            .auth
+            .totp_service(hmac_secret, false)
+            .decrypt_secret(user_id, &encrypted_secret)
+            .unwrap();
+        assert_eq!(decrypted.expose_secret(), secret.expose_secret());
+        assert!(!is_legacy);
+    }
+
+    #[sqlx::test]
+    async fn rejects_used_challenge(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let secret = TestUser::enable_two_factor(&mut server).await.secret;
+        let challenge = challenge(&mut server).await;
+        let res = server.call(request(&challenge, &next_code(&secret))).await;
+        assert!(res.status().is_success());
+        let res = server.call(request(&challenge, &next_code(&secret))).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_reused_code(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let secret = TestUser::enable_two_factor(&mut server).await.secret;
+        let code = next_code(&secret);
+        let challenge_1 = challenge(&mut server).await;
+        let res = server.call(request(&challenge_1, &code)).await;
+        assert!(res.status().is_success());
+        let challenge_2 = challenge(&mut server).await;
+        let res = server.call(request(&challenge_2, &code)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn revokes_challenge_after_too_many_failures(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.lockout.account_threshold = 10;
+        })
+        .await;
+        TestUser::enter_session(&mut server).await;
+        let secret = TestUser::enable_two_factor(&mut server).await.secret;
+        let challenge = challenge(&mut server).await;
+        for _ in 0..super::MAX_FAILED_ATTEMPTS {
+            let res = server.call(request(&challenge, "000000x")).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+        let res = server.call(request(&challenge, &next_code(&secret))).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn locks_account_out_across_challenges(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.lockout.account_threshold = 3;
+        })
+        .await;
+        TestUser::enter_session(&mut server).await;
+        let secret = TestUser::enable_two_factor(&mut server).await.secret;
+        let challenge_1 = challenge(&mut server).await;
+        for _ in 0..2 {
+            let res = server.call(request(&challenge_1, "000000x")).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+        let challenge_2 = challenge(&mut server).await;
+        let res = server.call(request(&challenge_2, "000000x")).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let res = server
+            .call(request(&challenge_2, &next_code(&secret)))
+            .await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[sqlx::test]
+    async fn accepts_recovery_code_once(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let two_factor = TestUser::enable_two_factor(&mut server).await;
+        let code = two_factor.recovery_codes[0].to_uppercase();
+        let res = TestUser::complete_login(&mut server, &code).await;
+        assert!(res.status().is_success());
+        let res = TestUser::complete_login(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let code = &two_factor.recovery_codes[1];
+        let res = TestUser::complete_login(&mut server, code).await;
+        assert!(res.status().is_success());
+    }
+
+    /// Codes of the current step are already spent on confirmation.
+    fn next_code(secret: &Secret<Vec<u8>>) -> String {
+        TotpService::generate_code(secret, TotpService::current_step() + 1)
+    }
+
+    async fn challenge(server: &mut TestServer) -> String {
+        let res = TestUser::login(server).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let body = read_json::<serde_json::Value>(res).await;
+        body["challenge"].as_str().unwrap().to_owned()
+    }
+
+    fn request(challenge: &str, code: &str) -> Request<Body> {
+        let body = serde_urlencoded::to_string([
+            ("challenge", challenge),
+            ("code", code),
+        ])
+        .unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/auth/login/2fa")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
@@ -0,0 +1,87 @@
+use axum::{extract::State, Json};
+
+use crate::{
+    api::auth::{
+        ensure_transport,
+        login::two_factor::post::{complete_challenge, Payload},
+        TokenResponse,
+    },
+    config::auth::{self, TokenTransport},
+    extractors::{validated::Form, ClientInfo},
+    services::{hash::PasswordHasher, token::TokenService, totp::TotpService},
+    Pool,
+};
+
+#[tracing::instrument(name = "Complete two-factor login for tokens", skip_all)]
+pub async fn handler(
+    client: ClientInfo,
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(totp_service): State<TotpService>,
+    State(token_service): State<TokenService>,
+    State(password_hasher): State<PasswordHasher>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<Json<TokenResponse>> {
+    ensure_transport(
+        auth_config.token_transport,
+        TokenTransport::accepts_bearer,
+    )?;
+    let tokens = complete_challenge(
+        &payload,
+        &client,
+        &auth_config,
+        &pool,
+        &totp_service,
+        &token_service,
+        password_hasher,
+    )
+    .await?;
+    Ok(tokens.into_json(&token_service))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+
+    use crate::{
+        services::totp::TotpService,
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn returns_tokens_in_body(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let secret = TestUser::enable_two_factor(&mut server).await.secret;
+        server.clear_cookies();
+        let res = TestUser::login_for_tokens(&mut server).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let body = read_json::<serde_json::Value>(res).await;
+        let challenge = body["challenge"].as_str().unwrap();
+        let code = TotpService::generate_code(
+            &secret,
+            TotpService::current_step() + 1,
+        );
+        let body = serde_urlencoded::to_string([
+            ("challenge", challenge),
+            ("code", &code),
+        ])
+        .unwrap();
+        let req = Request::builder()
+            .method("POST")
+            .uri("/auth/login/2fa/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap();
+        let res = server.call(req).await;
+        assert!(res.status().is_success());
+        assert!(server.cookie("access_token").is_none());
+        let body = read_json::<serde_json::Value>(res).await;
+        assert!(body["accessToken"].is_string());
+        assert!(body["refreshToken"].is_string());
+    }
+}
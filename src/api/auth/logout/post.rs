@@ -0,0 +1,177 @@
+use axum::{extract::State, http::StatusCode};
+use tower_cookies::Cookies;
+
+use crate::{
+    database::{begin_transaction, commit, revoked_access_token, session},
+    extractors::User,
+    services::{cookie::CookieService, denylist::AccessTokenDenylist},
+    Pool,
+};
+
+/// Ends the session of the refresh token cookie
+/// as well as the one the access token was issued for.
+#[tracing::instrument(name = "Log out current session", skip_all)]
+pub async fn handler(
+    user: Option<User>,
+    cookies: Cookies,
+    State(pool): State<Pool>,
+    State(cookie_service): State<CookieService>,
+    State(access_token_denylist): State<AccessTokenDenylist>,
+) -> crate::Result<StatusCode> {
+    let mut transaction = begin_transaction(&pool).await?;
+    let mut revoked = Vec::new();
+    let session = user.as_ref().and_then(|user| {
+        let session_id = user.credential.session_id()?;
+        Some((*session_id, user.id))
+    });
+    if let Some((session_id, user_id)) = session {
+        revoked.extend(
+            revoked_access_token::revoke_for_session(
+                &session_id,
+                user_id,
+                &mut transaction,
+            )
+            .await?,
+        );
+        session::delete_for_user(&session_id, user_id, &mut transaction)
+            .await?;
+    }
+    if let Some(refresh_token) = cookie_service.get_refresh_token(&cookies) {
+        revoked.extend(
+            revoked_access_token::revoke_for_refresh_token(
+                &refresh_token,
+                &mut transaction,
+            )
+            .await?,
+        );
+        session::delete_by_refresh_token(&refresh_token, &mut transaction)
+            .await?;
+    }
+    commit(transaction).await?;
+    access_token_denylist.extend(&revoked);
+    cookie_service.remove_access_token(&cookies);
+    cookie_service.remove_refresh_token(&cookies);
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn succeeds_for_logged_out_user(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+    }
+
+    #[sqlx::test]
+    async fn removes_cookies(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        assert!(server.cookie("access_token").is_none());
+        assert!(server.cookie("refresh_token").is_none());
+        let res = server.call(protected_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn invalidates_refresh_token(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let refresh_token = server.cookie("refresh_token").unwrap();
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        assert_eq!(count_sessions(&pool).await, 0);
+        server.set_cookie("refresh_token", refresh_token);
+        let res = server.call(refresh_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn revokes_access_token(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let access_token = server.cookie("access_token").unwrap();
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        server.set_cookie("access_token", access_token);
+        let res = server.call(protected_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn revokes_session_of_bearer_token(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login_for_tokens(&mut server).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let authorization =
+            format!("Bearer {}", body["accessToken"].as_str().unwrap());
+        let mut req = request();
+        req.headers_mut()
+            .insert(AUTHORIZATION, authorization.parse().unwrap());
+        let res = server.call(req).await;
+        assert!(res.status().is_success());
+        assert_eq!(count_sessions(&pool).await, 0);
+        let mut req = protected_request();
+        req.headers_mut()
+            .insert(AUTHORIZATION, authorization.parse().unwrap());
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn keeps_other_sessions(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let res = TestUser::login(&mut server).await;
+        assert!(res.status().is_success());
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        assert_eq!(count_sessions(&pool).await, 1);
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/auth/logout")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn protected_request() -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri("/health_check/protected")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn refresh_request() -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/auth/refresh")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn count_sessions(pool: &Pool) -> i64 {
+        sqlx::query!(r#"select count(*) as "count!" from sessions;"#)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+            .count
+    }
+}
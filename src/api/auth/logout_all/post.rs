@@ -0,0 +1,118 @@
+use axum::{extract::State, http::StatusCode};
+use tower_cookies::Cookies;
+
+use crate::{
+    database::{begin_transaction, commit, revoked_access_token, session},
+    extractors::User,
+    services::{cookie::CookieService, denylist::AccessTokenDenylist},
+    Pool,
+};
+
+#[tracing::instrument(
+    name = "Log out all user's sessions",
+    skip_all,
+    fields(user_id = %user.id)
+)]
+pub async fn handler(
+    user: User,
+    cookies: Cookies,
+    State(pool): State<Pool>,
+    State(cookie_service): State<CookieService>,
+    State(access_token_denylist): State<AccessTokenDenylist>,
+) -> crate::Result<StatusCode> {
+    let mut transaction = begin_transaction(&pool).await?;
+    let revoked =
+        revoked_access_token::revoke_for_user(user.id, None, &mut transaction)
+            .await?;
+    session::delete_all_for_user(user.id, None, &mut transaction).await?;
+    commit(transaction).await?;
+    access_token_denylist.extend(&revoked);
+    cookie_service.remove_access_token(&cookies);
+    cookie_service.remove_refresh_token(&cookies);
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_logged_out_user(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn revokes_all_sessions(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let other_refresh_token = server.cookie("refresh_token").unwrap();
+        let res = TestUser::login(&mut server).await;
+        assert!(res.status().is_success());
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        assert!(server.cookie("access_token").is_none());
+        assert!(server.cookie("refresh_token").is_none());
+        let sessions =
+            sqlx::query!(r#"select count(*) as "count!" from sessions;"#)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+                .count;
+        assert_eq!(sessions, 0);
+        server.set_cookie("refresh_token", other_refresh_token);
+        let req = Request::builder()
+            .method("POST")
+            .uri("/auth/refresh")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn revokes_access_tokens_on_other_instances(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let access_token = server.cookie("access_token").unwrap();
+        let mut other_server = TestServer::with_config(pool, |config| {
+            config.auth.denylist_sync_interval = Duration::from_millis(10);
+        })
+        .await;
+        other_server.set_cookie("access_token", access_token);
+        let res = other_server.call(protected_request()).await;
+        assert!(res.status().is_success());
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let res = other_server.call(protected_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/auth/logout_all")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn protected_request() -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri("/health_check/protected")
+            .body(Body::empty())
+            .unwrap()
+    }
+}
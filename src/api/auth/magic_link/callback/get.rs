@@ -0,0 +1,233 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use secrecy::Secret;
+use serde::Deserialize;
+use tower_cookies::Cookies;
+
+use crate::{
+    api::auth::{
+        ensure_transport, start_session, two_factor::issue_challenge_if_enabled,
+    },
+    config::auth::{self, TokenTransport},
+    database::{begin_transaction, commit, Executor},
+    error::Error,
+    extractors::ClientInfo,
+    services::{cookie::CookieService, token::TokenService},
+    telemetry, Pool,
+};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Params {
+    token: Secret<String>,
+}
+
+/// Following the link proves ownership of the email,
+/// so the user is marked as verified as well.
+#[tracing::instrument(name = "Log in with magic link", skip_all)]
+pub async fn handler(
+    cookies: Cookies,
+    client: ClientInfo,
+    Query(params): Query<Params>,
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(token_service): State<TokenService>,
+    State(cookie_service): State<CookieService>,
+) -> crate::Result<Response> {
+    ensure_transport(
+        auth_config.token_transport,
+        TokenTransport::accepts_cookie,
+    )?;
+    let mut transaction = begin_transaction(&pool).await?;
+    let user_id = consume_magic_link_token(&params.token, &mut transaction)
+        .await?
+        .ok_or(Error::InvalidMagicLink)
+        .map_err(telemetry::warn)?;
+    verify_user(user_id, &mut transaction).await?;
+    commit(transaction).await?;
+    if let Some(challenge) = issue_challenge_if_enabled(
+        user_id,
+        None,
+        auth_config.two_factor_challenge_ttl,
+        &pool,
+    )
+    .await?
+    {
+        return Ok((StatusCode::ACCEPTED, Json(challenge)).into_response());
+    }
+    let mut transaction = begin_transaction(&pool).await?;
+    let tokens = start_session(
+        user_id,
+        None,
+        &client,
+        &auth_config,
+        &token_service,
+        &mut transaction,
+    )
+    .await?;
+    commit(transaction).await?;
+    cookie_service.set_access_token(&cookies, tokens.access_token);
+    cookie_service.set_refresh_token(&cookies, tokens.refresh_token);
+    Ok(StatusCode::OK.into_response())
+}
+
+#[tracing::instrument(name = "Consume magic link token", skip_all, err(Debug))]
+async fn consume_magic_link_token<'e, E: Executor<'e>>(
+    token: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<Option<i64>> {
+    let user_id = sqlx::query!(
+        r#"
+        update magic_link_tokens
+        set consumed_at = now()
+        where token_hash = $1
+          and consumed_at is null
+          and expires_at > now()
+        returning user_id;
+        "#,
+        TokenService::hash_token(token)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to consume magic link token")?
+    .map(|r| r.user_id);
+    Ok(user_id)
+}
+
+async fn verify_user<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update users
+        set verified = true
+        where id = $1;
+        "#,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to verify user")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        config::auth::TokenTransport,
+        test_helpers::{extract_email_link, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn rejects_unknown_token(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let req = request("/auth/magic_link/callback?token=unknown");
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn logs_user_in_once(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::request_magic_link(&mut server).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let link = magic_link(&server, 2).await;
+        let res = server.call(request(&link)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server.call(request("/health_check/protected")).await;
+        assert!(res.status().is_success());
+        let res = server.call(request(&link)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn invalidates_previous_link(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        TestUser::request_magic_link(&mut server).await;
+        let previous_link = magic_link(&server, 2).await;
+        TestUser::request_magic_link(&mut server).await;
+        let link = magic_link(&server, 3).await;
+        let res = server.call(request(&previous_link)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = server.call(request(&link)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn verifies_user(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.require_verified_email = true;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        TestUser::request_magic_link(&mut server).await;
+        let res = server.call(request(&magic_link(&server, 2).await)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn requires_second_factor(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::enable_two_factor(&mut server).await;
+        server.clear_cookies();
+        TestUser::request_magic_link(&mut server).await;
+        let res = server.call(request(&magic_link(&server, 2).await)).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert!(server.cookie("refresh_token").is_none());
+    }
+
+    /// Magic links are sent in the background,
+    /// so this waits for `count` emails to arrive first.
+    #[sqlx::test]
+    async fn fails_if_cookie_transport_is_disabled(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.token_transport = TokenTransport::Bearer;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        TestUser::request_magic_link(&mut server).await;
+        let res = server.call(request(&magic_link(&server, 2).await)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(server.cookie("refresh_token").is_none());
+    }
+
+    async fn magic_link(server: &TestServer, count: usize) -> String {
+        let link = server
+            .wait_for_emails(count)
+            .await
+            .iter()
+            .map(extract_email_link)
+            .filter(|link| link.path() == "/auth/magic_link/callback")
+            .last()
+            .unwrap();
+        format!("{}?{}", link.path(), link.query().unwrap())
+    }
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -0,0 +1,4 @@
+crate::api::router! {
+    post,
+    /callback,
+}
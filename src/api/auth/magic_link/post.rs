@@ -0,0 +1,226 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use tracing::Instrument;
+use validator::Validate;
+
+use crate::{
+    config::auth,
+    database::{begin_transaction, commit, Executor},
+    extractors::validated::Form,
+    services::{
+        email::{EmailClient, SendEmailRequest},
+        token::TokenService,
+    },
+    telemetry, Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    #[validate(email(message = "is not a valid email"))]
+    email: String,
+}
+
+/// Responds the same way whether the account exists or not,
+/// and as soon in both cases, as the email is sent in the background.
+#[tracing::instrument(
+    name = "Request magic link",
+    skip_all,
+    fields(email = %payload.email)
+)]
+pub async fn handler(
+    State(base_url): State<Url>,
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(email_client): State<EmailClient>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<StatusCode> {
+    if let Some(user_id) = find_user_id(&payload.email, &pool).await? {
+        let task = send_magic_link(
+            user_id,
+            payload.email,
+            base_url,
+            auth_config,
+            pool,
+            email_client,
+        );
+        tokio::spawn(task.in_current_span());
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Replaces the previous magic link before sending the new one,
+/// so that no transaction is held open while the email is sent.
+async fn send_magic_link(
+    user_id: i64,
+    email: String,
+    base_url: Url,
+    auth_config: auth::Config,
+    pool: Pool,
+    email_client: EmailClient,
+) {
+    let token = TokenService::generate_magic_link_token();
+    let result = async {
+        let mut transaction = begin_transaction(&pool).await?;
+        delete_magic_link_tokens(user_id, &mut transaction).await?;
+        insert_magic_link_token(
+            user_id,
+            &token,
+            auth_config.magic_link_ttl,
+            &mut transaction,
+        )
+        .await?;
+        commit(transaction).await?;
+        send_magic_link_email(&email_client, &email, &base_url, &token).await
+    };
+    result.await.map_err(telemetry::error).ok();
+}
+
+#[tracing::instrument(name = "Find user by email", skip(executor), err(Debug))]
+async fn find_user_id<'e, E: Executor<'e>>(
+    email: &str,
+    executor: E,
+) -> anyhow::Result<Option<i64>> {
+    let id = sqlx::query!(
+        r#"
+        select id
+        from users
+        where email = $1;
+        "#,
+        email
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to select user id from database")?
+    .map(|r| r.id);
+    Ok(id)
+}
+
+#[tracing::instrument(
+    name = "Delete previous magic link tokens",
+    skip(executor),
+    err(Debug)
+)]
+async fn delete_magic_link_tokens<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        delete from magic_link_tokens
+        where user_id = $1;
+        "#,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to delete magic link tokens")
+}
+
+#[tracing::instrument(
+    name = "Save magic link token",
+    skip(token, executor),
+    err(Debug)
+)]
+async fn insert_magic_link_token<'e, E: Executor<'e>>(
+    user_id: i64,
+    token: &Secret<String>,
+    ttl: Duration,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        insert into magic_link_tokens (token_hash, user_id, expires_at)
+        values ($1, $2, now() + make_interval(secs => $3));
+        "#,
+        TokenService::hash_token(token),
+        user_id,
+        ttl.as_secs_f64()
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to insert magic link token")
+}
+
+#[tracing::instrument(
+    name = "Send magic link email",
+    skip(email_client, base_url, token)
+)]
+async fn send_magic_link_email(
+    email_client: &EmailClient,
+    recipient: &str,
+    base_url: &Url,
+    token: &Secret<String>,
+) -> anyhow::Result<()> {
+    let mut magic_link = base_url.clone();
+    magic_link.set_path("auth/magic_link/callback");
+    magic_link.set_query(Some(&format!("token={}", token.expose_secret())));
+
+    let request = SendEmailRequest {
+        recipient,
+        subject: "Log in",
+        text_body: &format!("{magic_link}"),
+        html_body: &format!("<a>{magic_link}</a>"),
+    };
+    email_client
+        .send_email(&request)
+        .await
+        .context("Failed to send a magic link email")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use wiremock::ResponseTemplate;
+
+    use crate::{
+        test_helpers::{
+            extract_email_link, when_sending_an_email, TestServer, TestUser,
+        },
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn does_not_reveal_unknown_email(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let mock = when_sending_an_email()
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0);
+        server.mount_mock(mock).await;
+        let res = TestUser::request_magic_link(&mut server).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+    }
+
+    #[sqlx::test]
+    async fn does_not_reveal_email_failure(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        server.reset_mocks().await;
+        let mock =
+            when_sending_an_email().respond_with(ResponseTemplate::new(500));
+        server.mount_mock(mock).await;
+        let res = TestUser::request_magic_link(&mut server).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+    }
+
+    #[sqlx::test]
+    async fn sends_email_with_magic_link(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::request_magic_link(&mut server).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let email_requests = server.wait_for_emails(2).await;
+        assert_eq!(email_requests.len(), 2);
+        let link = extract_email_link(email_requests.last().unwrap());
+        assert_eq!(link.path(), "/auth/magic_link/callback");
+        assert!(link.query_pairs().any(|(k, _)| k == "token"));
+    }
+}
@@ -1,8 +1,218 @@
+use std::time::Duration;
+
+use axum::Json;
+use secrecy::{ExposeSecret, Secret};
+use serde::Serialize;
+use sqlx::Transaction;
+
+use crate::{
+    api::auth::two_factor::TwoFactorChallenge,
+    config::auth::{self, TokenTransport},
+    database::{
+        begin_transaction, commit,
+        login_failure::{self, Subject},
+        permission, refresh_token, role, session, user, Database,
+    },
+    error::Error,
+    extractors::ClientInfo,
+    services::token::TokenService,
+    telemetry, Pool,
+};
+
 crate::api::router! {
     /login,
+    /logout,
+    /logout_all,
     /signup,
     /google,
     /verify,
     /refresh,
     /change_password,
+    /forgot_password,
+    /reset_password,
+    /magic_link,
+    /email_code,
+    /sessions,
+    /api_keys,
+    /two_factor,
+    /webauthn,
+}
+
+pub struct SessionTokens {
+    pub access_token: Secret<String>,
+    pub refresh_token: Secret<String>,
+}
+
+/// Outcome of checking the first factor.
+pub enum Login {
+    Session(SessionTokens),
+    Challenge(TwoFactorChallenge),
+}
+
+/// Hands the tokens to clients that cannot keep cookies.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenResponse {
+    access_token: String,
+    refresh_token: String,
+    token_type: &'static str,
+    expires_in: u64,
+}
+
+impl SessionTokens {
+    pub fn into_json(
+        self,
+        token_service: &TokenService,
+    ) -> Json<TokenResponse> {
+        Json(TokenResponse {
+            access_token: self.access_token.expose_secret().to_owned(),
+            refresh_token: self.refresh_token.expose_secret().to_owned(),
+            token_type: "Bearer",
+            expires_in: token_service.access_token_ttl().as_secs(),
+        })
+    }
+}
+
+/// Rejects a request for tokens in a transport that is not enabled.
+pub fn ensure_transport(
+    transport: TokenTransport,
+    accepts: fn(TokenTransport) -> bool,
+) -> crate::Result<()> {
+    if !accepts(transport) {
+        Err(Error::UnsupportedTokenTransport).map_err(telemetry::warn)?;
+    }
+    Ok(())
+}
+
+/// Fails for disabled users, whose credentials are otherwise valid,
+/// and for unverified ones if a verified email is required,
+/// whichever way they logged in.
+#[tracing::instrument(
+    name = "Start new session",
+    skip(client, auth_config, token_service, transaction),
+    err(Debug)
+)]
+pub async fn start_session(
+    user_id: i64,
+    device_label: Option<&str>,
+    client: &ClientInfo,
+    auth_config: &auth::Config,
+    token_service: &TokenService,
+    transaction: &mut Transaction<'_, Database>,
+) -> crate::Result<SessionTokens> {
+    if auth_config.require_verified_email
+        && !user::is_verified(user_id, &mut *transaction).await?
+    {
+        Err(Error::UnverifiedEmail).map_err(telemetry::warn)?;
+    }
+    let refresh_token_ttl = token_service.refresh_token_ttl();
+    let session_id = session::insert(
+        user_id,
+        device_label,
+        client,
+        refresh_token_ttl,
+        &mut *transaction,
+    )
+    .await?
+    .ok_or(Error::AccountDisabled)
+    .map_err(telemetry::warn)?;
+    let roles = role::list_for_user(user_id, &mut *transaction).await?;
+    let scopes =
+        permission::list_for_session(&session_id, user_id, &mut *transaction)
+            .await?;
+    let token_service = token_service.clone();
+    let (access_token, claims) =
+        telemetry::instrument_blocking_task(move || {
+            token_service
+                .generate_access_token(user_id, session_id, roles, scopes)
+        })
+        .await??;
+    let refresh_token = TokenService::generate_refresh_token();
+    refresh_token::insert(
+        user_id,
+        &session_id,
+        &refresh_token,
+        refresh_token_ttl,
+        &claims,
+        &mut *transaction,
+    )
+    .await?;
+    Ok(SessionTokens {
+        access_token,
+        refresh_token,
+    })
+}
+
+/// A password attempt counted as failed against every subject
+/// before it is checked, so that concurrent attempts cannot all get
+/// past the threshold. Subjects reaching the threshold are locked out
+/// right away and the lockout is lifted if the attempt succeeds.
+pub struct Attempt<'a> {
+    lockouts: Vec<(Subject<'a>, Option<Duration>)>,
+}
+
+/// Counts the attempt, or fails while any of the subjects is locked out.
+pub async fn begin_attempt<'a>(
+    subjects: &[Subject<'a>],
+    lockout: &auth::Lockout,
+    pool: &Pool,
+) -> crate::Result<Attempt<'a>> {
+    let mut lockouts = Vec::with_capacity(subjects.len());
+    let mut transaction = begin_transaction(pool).await?;
+    for &subject in subjects {
+        let failures = login_failure::count_attempt(
+            subject,
+            lockout.reset_after,
+            &mut transaction,
+        )
+        .await?;
+        let Some(failures) = failures else {
+            // the attempts counted so far are rolled back
+            let retry_after =
+                login_failure::retry_after(subjects, &mut transaction)
+                    .await?
+                    .unwrap_or_default();
+            return Err(Error::TooManyAttempts { retry_after })
+                .map_err(telemetry::warn);
+        };
+        let threshold = match subject {
+            Subject::Account(_) => lockout.account_threshold,
+            Subject::Ip(_) => lockout.ip_threshold,
+        };
+        let duration = lockout.duration(failures, threshold);
+        if let Some(duration) = duration {
+            login_failure::lock(subject, duration, &mut transaction).await?;
+        }
+        lockouts.push((subject, duration));
+    }
+    commit(transaction).await?;
+    Ok(Attempt { lockouts })
+}
+
+impl Attempt<'_> {
+    /// Returns `error` unless the attempt locked any of the subjects out.
+    pub fn fail(self, error: Error) -> Error {
+        let retry_after = self.lockouts.iter().filter_map(|(_, d)| *d).max();
+        match retry_after {
+            Some(retry_after) => Error::TooManyAttempts { retry_after },
+            None => error,
+        }
+    }
+
+    /// Takes the attempt back once it succeeded. Earlier failures
+    /// of the account are left for the caller to clear,
+    /// so that they keep counting while a second factor is pending.
+    pub async fn release(self, pool: &Pool) -> crate::Result<()> {
+        let mut transaction = begin_transaction(pool).await?;
+        for (subject, duration) in self.lockouts {
+            login_failure::release(
+                subject,
+                duration.is_some(),
+                &mut transaction,
+            )
+            .await?;
+        }
+        commit(transaction).await?;
+        Ok(())
+    }
 }
@@ -1,3 +1,4 @@
 crate::api::router! {
     post,
+    /token,
 }
@@ -1,13 +1,21 @@
-use anyhow::Context;
 use axum::{extract::State, http::StatusCode};
-use secrecy::{ExposeSecret, Secret};
+use secrecy::Secret;
 use tower_cookies::Cookies;
 use tracing::{field::display, Span};
 
 use crate::{
-    database::Executor,
+    api::auth::{ensure_transport, SessionTokens},
+    config::auth::{self, TokenTransport},
+    database::{
+        begin_transaction, commit, permission, refresh_token,
+        revoked_access_token, role, session,
+    },
     error::Error,
-    services::{cookie::CookieService, token::TokenService},
+    extractors::ClientInfo,
+    services::{
+        cookie::CookieService, denylist::AccessTokenDenylist,
+        token::TokenService,
+    },
     telemetry, Pool,
 };
 
@@ -20,46 +28,193 @@ use crate::{
 )]
 pub async fn handler(
     cookies: Cookies,
+    client: ClientInfo,
+    State(auth_config): State<auth::Config>,
     State(pool): State<Pool>,
     State(token_service): State<TokenService>,
     State(cookie_service): State<CookieService>,
+    State(access_token_denylist): State<AccessTokenDenylist>,
 ) -> crate::Result<StatusCode> {
+    ensure_transport(
+        auth_config.token_transport,
+        TokenTransport::accepts_cookie,
+    )?;
     let refresh_token = cookie_service
         .get_refresh_token(&cookies)
         .ok_or(Error::NoRefreshToken)?;
-    let user_id = get_user_id(&refresh_token, &pool)
-        .await?
-        .ok_or(Error::InvalidRefreshToken)?;
-    Span::current().record("user_id", &display(user_id));
-    let access_token = telemetry::instrument_blocking_task(move || {
-        token_service.generate_access_token(user_id)
-    })
-    .await??;
-    cookie_service.set_access_token(&cookies, access_token);
-    cookie_service.set_refresh_token(&cookies, refresh_token);
+    let tokens = rotate_tokens(
+        &refresh_token,
+        None,
+        &client,
+        &pool,
+        token_service,
+        &access_token_denylist,
+    )
+    .await?;
+    cookie_service.set_access_token(&cookies, tokens.access_token);
+    cookie_service.set_refresh_token(&cookies, tokens.refresh_token);
     Ok(StatusCode::OK)
 }
 
-#[tracing::instrument(
-    name = "Get user id"
-    skip_all,
-    err(Debug),
-)]
-async fn get_user_id<'e, E: Executor<'e>>(
+/// Exchanges a refresh token for a new token pair,
+/// revoking the whole session if the token was already exchanged.
+/// Requesting `scopes` narrows the session to them for good.
+pub async fn rotate_tokens(
     refresh_token: &Secret<String>,
-    executor: E,
-) -> anyhow::Result<Option<i64>> {
-    let id = sqlx::query!(
-        r#"
-        select id
-        from users
-        where refresh_token = $1;
-        "#,
-        refresh_token.expose_secret()
+    scopes: Option<&[String]>,
+    client: &ClientInfo,
+    pool: &Pool,
+    token_service: TokenService,
+    access_token_denylist: &AccessTokenDenylist,
+) -> crate::Result<SessionTokens> {
+    let mut transaction = begin_transaction(pool).await?;
+    let stored_token =
+        refresh_token::find_for_update(refresh_token, &mut transaction)
+            .await?
+            .ok_or(Error::InvalidRefreshToken)?;
+    Span::current().record("user_id", &display(stored_token.user_id));
+    if stored_token.rotated {
+        tracing::warn!("Refresh token reuse detected, revoking session");
+        let revoked = revoked_access_token::revoke_for_session(
+            &stored_token.session_id,
+            stored_token.user_id,
+            &mut transaction,
+        )
+        .await?;
+        session::delete(&stored_token.session_id, &mut transaction).await?;
+        commit(transaction).await?;
+        access_token_denylist.extend(&revoked);
+        return Err(Error::InvalidRefreshToken).map_err(telemetry::warn);
+    }
+    if stored_token.expired {
+        return Err(Error::InvalidRefreshToken).map_err(telemetry::warn);
+    }
+    let new_refresh_token = TokenService::generate_refresh_token();
+    let refresh_token_ttl = token_service.refresh_token_ttl();
+    let (user_id, session_id) = (stored_token.user_id, stored_token.session_id);
+    if let Some(scopes) = scopes {
+        session::narrow_scopes(&session_id, scopes, &mut transaction).await?;
+    }
+    // roles and permissions are read again,
+    // so that refreshed tokens reflect their changes
+    let roles = role::list_for_user(user_id, &mut transaction).await?;
+    let scopes =
+        permission::list_for_session(&session_id, user_id, &mut transaction)
+            .await?;
+    let (access_token, claims) =
+        telemetry::instrument_blocking_task(move || {
+            token_service
+                .generate_access_token(user_id, session_id, roles, scopes)
+        })
+        .await??;
+    refresh_token::mark_rotated(refresh_token, &mut transaction).await?;
+    refresh_token::insert(
+        stored_token.user_id,
+        &stored_token.session_id,
+        &new_refresh_token,
+        refresh_token_ttl,
+        &claims,
+        &mut transaction,
+    )
+    .await?;
+    session::touch(
+        &stored_token.session_id,
+        client,
+        refresh_token_ttl,
+        &mut transaction,
     )
-    .fetch_optional(executor)
-    .await
-    .context("Failed to select user id from database")?
-    .map(|r| r.id);
-    Ok(id)
+    .await?;
+    commit(transaction).await?;
+    Ok(SessionTokens {
+        access_token,
+        refresh_token: new_refresh_token,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_without_refresh_token(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rotates_refresh_token(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        let tokens = sqlx::query!(
+            r#"select count(*) as "count!" from refresh_tokens where rotated;"#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .count;
+        assert_eq!(tokens, 1);
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+    }
+
+    #[sqlx::test]
+    async fn revokes_access_tokens_on_reuse(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let rotated_token = server.cookie("refresh_token").unwrap();
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        let access_token = server.cookie("access_token").unwrap();
+        server.set_cookie("refresh_token", rotated_token);
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        server.clear_cookies();
+        server.set_cookie("access_token", access_token);
+        let req = Request::builder()
+            .method("GET")
+            .uri("/health_check/protected")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn revokes_session_on_reuse(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let rotated_token = server.cookie("refresh_token").unwrap();
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        let current_token = server.cookie("refresh_token").unwrap();
+        server.set_cookie("refresh_token", rotated_token);
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        server.set_cookie("refresh_token", current_token);
+        let res = server.call(request()).await;
+        assert_eq!(
+            res.status(),
+            StatusCode::UNAUTHORIZED,
+            "tokens from a revoked session must be rejected"
+        );
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/auth/refresh")
+            .body(Body::empty())
+            .unwrap()
+    }
 }
@@ -0,0 +1,160 @@
+use axum::{extract::State, Json};
+use secrecy::Secret;
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{
+    api::auth::{
+        ensure_transport, refresh::post::rotate_tokens, TokenResponse,
+    },
+    config::auth::{self, TokenTransport},
+    extractors::{validated::Form, ClientInfo},
+    services::{denylist::AccessTokenDenylist, token::TokenService},
+    Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    refresh_token: Secret<String>,
+    /// Space-delimited scopes to narrow the session to.
+    scope: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Refresh user's token pair for tokens",
+    skip_all,
+    fields(
+        user_id = tracing::field::Empty,
+    )
+)]
+pub async fn handler(
+    client: ClientInfo,
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(token_service): State<TokenService>,
+    State(access_token_denylist): State<AccessTokenDenylist>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<Json<TokenResponse>> {
+    ensure_transport(
+        auth_config.token_transport,
+        TokenTransport::accepts_bearer,
+    )?;
+    let scopes: Option<Vec<_>> = payload
+        .scope
+        .as_deref()
+        .map(|scope| scope.split_whitespace().map(str::to_owned).collect());
+    let tokens = rotate_tokens(
+        &payload.refresh_token,
+        scopes.as_deref(),
+        &client,
+        &pool,
+        token_service.clone(),
+        &access_token_denylist,
+    )
+    .await?;
+    Ok(tokens.into_json(&token_service))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE},
+            Request, StatusCode,
+        },
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn rotates_refresh_token(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login_for_tokens(&mut server).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let refresh_token = body["refreshToken"].as_str().unwrap();
+        let res = server.call(request(refresh_token)).await;
+        assert!(res.status().is_success());
+        let body = read_json::<serde_json::Value>(res).await;
+        assert!(body["accessToken"].is_string());
+        let new_refresh_token = body["refreshToken"].as_str().unwrap();
+        assert_ne!(new_refresh_token, refresh_token);
+        let res = server.call(request(new_refresh_token)).await;
+        assert!(res.status().is_success());
+        let res = server.call(request(refresh_token)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn fails_on_unknown_refresh_token(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.call(request("unknown")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn narrows_scopes_for_good(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let user_id = TestUser::grant_role(&mut server, &pool, "admin").await;
+        let res = TestUser::login_for_tokens(&mut server).await;
+        server.clear_cookies();
+        let body = read_json::<serde_json::Value>(res).await;
+        let refresh_token = body["refreshToken"].as_str().unwrap();
+        let res = server
+            .call(request_with_scope(refresh_token, Some("users:read")))
+            .await;
+        assert!(res.status().is_success());
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["accessToken"].as_str().unwrap();
+        let res = server.call(roles_request(user_id, access_token)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let refresh_token = body["refreshToken"].as_str().unwrap();
+        let res = server
+            .call(request_with_scope(refresh_token, Some("users:write")))
+            .await;
+        assert!(res.status().is_success());
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["accessToken"].as_str().unwrap();
+        let res = server.call(roles_request(user_id, access_token)).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let refresh_token = body["refreshToken"].as_str().unwrap();
+        let res = server.call(request(refresh_token)).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["accessToken"].as_str().unwrap();
+        let res = server.call(roles_request(user_id, access_token)).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    fn request(refresh_token: &str) -> Request<Body> {
+        request_with_scope(refresh_token, None)
+    }
+
+    fn request_with_scope(
+        refresh_token: &str,
+        scope: Option<&str>,
+    ) -> Request<Body> {
+        let body = [("refresh_token", Some(refresh_token)), ("scope", scope)];
+        let body = serde_urlencoded::to_string(body).unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/auth/refresh/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn roles_request(user_id: i64, access_token: &str) -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri(format!("/admin/users/{user_id}/roles"))
+            .header(AUTHORIZATION, format!("Bearer {access_token}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -0,0 +1,261 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{
+    database::{
+        api_key, begin_transaction, commit, revoked_access_token, session,
+        Executor,
+    },
+    domain::validated_password::{
+        ascii, at_least_8, at_most_32, digit, lowercase, uppercase, Password,
+    },
+    error::Error,
+    extractors::validated::Form,
+    services::{
+        denylist::AccessTokenDenylist, hash::PasswordHasher,
+        token::TokenService,
+    },
+    telemetry, Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    token: Secret<String>,
+    #[validate(
+        custom(
+            function = "at_least_8",
+            message = "must contain at least 8 characters"
+        ),
+        custom(
+            function = "at_most_32",
+            message = "must contain at most 32 characters"
+        ),
+        custom(
+            function = "ascii",
+            message = "must contain only latin letters, digits and special characters"
+        ),
+        custom(
+            function = "lowercase",
+            message = "must contain at least one lowercase letter"
+        ),
+        custom(
+            function = "uppercase",
+            message = "must contain at least one uppercase letter"
+        ),
+        custom(
+            function = "digit",
+            message = "must contain at least one digit"
+        )
+    )]
+    new_password: Password,
+}
+
+#[tracing::instrument(name = "Reset user's password", skip_all)]
+pub async fn handler(
+    State(password_hasher): State<PasswordHasher>,
+    State(access_token_denylist): State<AccessTokenDenylist>,
+    State(pool): State<Pool>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<StatusCode> {
+    // the token is checked first, so that guessing it costs no hashing
+    let mut transaction = begin_transaction(&pool).await?;
+    let user_id = consume_reset_token(&payload.token, &mut transaction)
+        .await?
+        .ok_or(Error::InvalidPasswordResetToken)
+        .map_err(telemetry::warn)?;
+    let new_password_hash = telemetry::instrument_blocking_task(move || {
+        password_hasher.hash_password(payload.new_password.as_ref())
+    })
+    .await??;
+    update_password_hash(user_id, new_password_hash, &mut transaction).await?;
+    let revoked =
+        revoked_access_token::revoke_for_user(user_id, None, &mut transaction)
+            .await?;
+    session::delete_all_for_user(user_id, None, &mut transaction).await?;
+    api_key::delete_all_for_user(user_id, None, &mut transaction).await?;
+    commit(transaction).await?;
+    access_token_denylist.extend(&revoked);
+    Ok(StatusCode::OK)
+}
+
+#[tracing::instrument(
+    name = "Consume password reset token",
+    skip_all,
+    err(Debug)
+)]
+async fn consume_reset_token<'e, E: Executor<'e>>(
+    token: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<Option<i64>> {
+    let user_id = sqlx::query!(
+        r#"
+        update password_reset_tokens
+        set consumed_at = now()
+        where token_hash = $1
+          and consumed_at is null
+          and expires_at > now()
+        returning user_id;
+        "#,
+        TokenService::hash_token(token)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to consume password reset token")?
+    .map(|r| r.user_id);
+    Ok(user_id)
+}
+
+async fn update_password_hash<'e, E: Executor<'e>>(
+    user_id: i64,
+    new_password_hash: Secret<String>,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update users
+        set password_hash = $1
+        where id = $2;
+        "#,
+        new_password_hash.expose_secret(),
+        user_id
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to update password hash in the database")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE},
+            Request, StatusCode,
+        },
+    };
+
+    use crate::{
+        test_helpers::{
+            assert_client_error, extract_email_link, TestServer, TestUser,
+        },
+        Pool,
+    };
+
+    const NEW_PASSWORD: &str = "XYZabc789";
+
+    #[sqlx::test]
+    async fn rejects_unknown_token(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.call(request("unknown", NEW_PASSWORD)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_invalid_password(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::signup(&mut server).await;
+        let token = request_reset_token(&mut server).await;
+        let res = server.call(request(&token, "short")).await;
+        assert_client_error(res.status(), "password is too short");
+        assert_ne!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn resets_password_and_revokes_sessions(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let token = request_reset_token(&mut server).await;
+        let res = server.call(request(&token, NEW_PASSWORD)).await;
+        assert!(res.status().is_success());
+        let sessions =
+            sqlx::query!(r#"select count(*) as "count!" from sessions;"#)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+                .count;
+        assert_eq!(sessions, 0);
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let body = serde_urlencoded::to_string([
+            ("email", TestUser::email()),
+            ("password", NEW_PASSWORD.into()),
+        ])
+        .unwrap();
+        let req = Request::builder()
+            .method("POST")
+            .uri("/auth/login")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap();
+        let res = server.call(req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[sqlx::test]
+    async fn revokes_api_keys(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let key = TestUser::create_api_key(&mut server, None).await;
+        server.clear_cookies();
+        let token = request_reset_token(&mut server).await;
+        let res = server.call(request(&token, NEW_PASSWORD)).await;
+        assert!(res.status().is_success());
+        let req = Request::builder()
+            .method("GET")
+            .uri("/health_check/protected")
+            .header(AUTHORIZATION, format!("Bearer {key}"))
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_used_token(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::signup(&mut server).await;
+        let token = request_reset_token(&mut server).await;
+        let res = server.call(request(&token, NEW_PASSWORD)).await;
+        assert!(res.status().is_success());
+        let res = server.call(request(&token, NEW_PASSWORD)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    async fn request_reset_token(server: &mut TestServer) -> String {
+        let body = serde_urlencoded::to_string([("email", TestUser::email())])
+            .unwrap();
+        let req = Request::builder()
+            .method("POST")
+            .uri("/auth/forgot_password")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap();
+        let res = server.call(req).await;
+        assert!(res.status().is_success());
+        // signup sent the first email
+        let email_requests = server.wait_for_emails(2).await;
+        let link = extract_email_link(email_requests.last().unwrap());
+        link.query_pairs()
+            .find(|(k, _)| k == "token")
+            .map(|(_, v)| v.to_string())
+            .unwrap()
+    }
+
+    fn request(token: &str, new_password: &str) -> Request<Body> {
+        let body = serde_urlencoded::to_string([
+            ("token", token),
+            ("new_password", new_password),
+        ])
+        .unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/auth/reset_password")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
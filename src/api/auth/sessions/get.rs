@@ -0,0 +1,66 @@
+use axum::{extract::State, Json};
+
+use crate::{
+    database::session::{self, Session},
+    extractors::User,
+    Pool,
+};
+
+#[tracing::instrument(
+    name = "List user's sessions",
+    skip_all,
+    fields(user_id = %user.id)
+)]
+pub async fn handler(
+    user: User,
+    State(pool): State<Pool>,
+) -> crate::Result<Json<Vec<Session>>> {
+    let sessions =
+        session::list(user.id, user.credential.session_id(), &pool).await?;
+    Ok(Json(sessions))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_logged_out_user(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn lists_session_per_login(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = TestUser::login(&mut server).await;
+        assert!(res.status().is_success());
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        let sessions = read_json::<Vec<serde_json::Value>>(res).await;
+        assert_eq!(sessions.len(), 2);
+        let current_sessions = sessions
+            .iter()
+            .filter(|session| session["current"] == true)
+            .count();
+        assert_eq!(current_sessions, 1);
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri("/auth/sessions")
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -0,0 +1,90 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use uuid::Uuid;
+
+use crate::{
+    database::{begin_transaction, commit, revoked_access_token, session},
+    error::Error,
+    extractors::User,
+    services::denylist::AccessTokenDenylist,
+    telemetry, Pool,
+};
+
+#[tracing::instrument(
+    name = "Revoke user's session",
+    skip_all,
+    fields(user_id = %user.id, session_id = %id)
+)]
+pub async fn handler(
+    user: User,
+    Path(id): Path<Uuid>,
+    State(pool): State<Pool>,
+    State(access_token_denylist): State<AccessTokenDenylist>,
+) -> crate::Result<StatusCode> {
+    let mut transaction = begin_transaction(&pool).await?;
+    let revoked =
+        revoked_access_token::revoke_for_session(&id, user.id, &mut transaction)
+            .await?;
+    if !session::delete_for_user(&id, user.id, &mut transaction).await? {
+        Err(Error::UnknownSession).map_err(telemetry::warn)?;
+    }
+    commit(transaction).await?;
+    access_token_denylist.extend(&revoked);
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use uuid::Uuid;
+
+    use crate::{
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_unknown_session(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request(&Uuid::new_v4())).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn revokes_session(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let id = sqlx::query!(r#"select id from sessions;"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .id;
+        let res = server.call(request(&id)).await;
+        assert!(res.status().is_success());
+        let refresh_request = Request::builder()
+            .method("POST")
+            .uri("/auth/refresh")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(refresh_request).await;
+        assert_eq!(
+            res.status(),
+            StatusCode::UNAUTHORIZED,
+            "refresh tokens of a revoked session must be rejected"
+        );
+    }
+
+    fn request(id: &Uuid) -> Request<Body> {
+        Request::builder()
+            .method("DELETE")
+            .uri(format!("/auth/sessions/{id}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -3,20 +3,18 @@ use axum::{extract::State, http::StatusCode};
 use reqwest::Url;
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
-use uuid::Uuid;
 use validator::Validate;
 
 use crate::{
+    api::auth::verify::start_verification,
+    config::auth,
     database::{begin_transaction, commit, Executor},
     domain::validated_password::{
         ascii, at_least_8, at_most_32, digit, lowercase, uppercase, Password,
     },
     error::Error,
     extractors::validated::Form,
-    services::{
-        email::{EmailClient, SendEmailRequest},
-        hash::PasswordHasher,
-    },
+    services::{email::EmailClient, hash::PasswordHasher},
     telemetry, Pool,
 };
 
@@ -72,30 +70,35 @@ pub struct Payload {
 )]
 pub async fn handler(
     State(base_url): State<Url>,
+    State(auth_config): State<auth::Config>,
     State(pool): State<Pool>,
     State(hasher): State<PasswordHasher>,
     State(email_client): State<EmailClient>,
     Form(payload): Form<Payload>,
 ) -> crate::Result<StatusCode> {
-    let password_hash = telemetry::instrument_blocking_task(move || {
-        hasher.hash_password(payload.password.as_ref())
-    })
-    .await??;
-    let verification_token = Uuid::new_v4();
+    let password_hash = {
+        let hasher = hasher.clone();
+        telemetry::instrument_blocking_task(move || {
+            hasher.hash_password(payload.password.as_ref())
+        })
+        .await??
+    };
     let mut transaction = begin_transaction(&pool).await?;
-    insert_user(
+    let user_id = insert_user(
         &payload.name,
         &payload.email,
         &password_hash,
-        &verification_token,
         &mut transaction,
     )
     .await?;
-    send_verification_email(
-        &email_client,
+    start_verification(
+        user_id,
         &payload.email,
         &base_url,
-        &verification_token,
+        &auth_config,
+        hasher,
+        &email_client,
+        &mut transaction,
     )
     .await?;
     commit(transaction).await?;
@@ -107,74 +110,46 @@ async fn insert_user<'e, E: Executor<'e>>(
     name: &str,
     email: &str,
     password_hash: &Secret<String>,
-    verification_token: &Uuid,
     executor: E,
-) -> crate::Result<()> {
+) -> crate::Result<i64> {
     match sqlx::query!(
         r#"
         insert into users (
           name,
           email,
-          password_hash,
-          verification_token
+          password_hash
         )
-        values ($1, $2, $3, $4)
-        on conflict do nothing;
+        values ($1, $2, $3)
+        on conflict do nothing
+        returning id;
         "#,
         name,
         email,
-        password_hash.expose_secret(),
-        verification_token
+        password_hash.expose_secret()
     )
-    .execute(executor)
+    .fetch_optional(executor)
     .await
     .context("Failed to insert user")
     .map_err(telemetry::error)?
-    .rows_affected()
     {
-        0 => Err(Error::EmailTaken).map_err(telemetry::warn),
-        1 => Ok(()),
-        _ => unreachable!(),
+        Some(user) => Ok(user.id),
+        None => Err(Error::EmailTaken).map_err(telemetry::warn),
     }
 }
 
-#[tracing::instrument(
-    name = "Send verification email",
-    skip(email_client, base_url)
-)]
-async fn send_verification_email(
-    email_client: &EmailClient,
-    recipient: &str,
-    base_url: &Url,
-    verification_token: &Uuid,
-) -> anyhow::Result<()> {
-    let mut verification_link = base_url.clone();
-    verification_link.set_path("auth/verify");
-    verification_link.set_query(Some(&format!("token={verification_token}")));
-
-    let request = SendEmailRequest {
-        recipient,
-        subject: "Account verification",
-        text_body: &format!("{verification_link}"),
-        html_body: &format!("<a>{verification_link}</a>"),
-    };
-    email_client
-        .send_email(&request)
-        .await
-        .context("Failed to send a verification email")
-}
-
 #[cfg(test)]
 mod tests {
     use axum::{
         body::Body,
         http::{header::CONTENT_TYPE, Request},
     };
+    use secrecy::Secret;
     use wiremock::ResponseTemplate;
 
     use crate::{
+        services::token::TokenService,
         test_helpers::{
-            assert_client_error, extract_verification_link,
+            assert_client_error, extract_email_link,
             when_sending_an_email, TestServer, TestUser,
         },
         Pool,
@@ -260,20 +235,21 @@ mod tests {
         let res = TestUser::signup(&mut server).await;
         assert!(res.status().is_success());
         let email_requests = server.received_emails().await;
-        let link = extract_verification_link(email_requests.first().unwrap());
-        let verification_token =
-            sqlx::query!(r#"select verification_token from users;"#)
+        let link = extract_email_link(email_requests.first().unwrap());
+        let token_hash =
+            sqlx::query!(r#"select token_hash from verification_tokens;"#)
                 .fetch_one(&pool)
                 .await
                 .unwrap()
-                .verification_token;
+                .token_hash;
         assert_eq!(link.path(), "/auth/verify");
-        let query = link
+        let (key, token) = link
             .query_pairs()
             .next()
             .map(|(k, v)| (k.to_string(), v.to_string()))
             .unwrap();
-        assert_eq!(query, ("token".into(), verification_token.to_string()));
+        assert_eq!(key, "token");
+        assert_eq!(TokenService::hash_token(&Secret::new(token)), token_hash);
     }
 
     #[sqlx::test]
@@ -0,0 +1,152 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{
+    database::Executor,
+    error::Error,
+    extractors::{validated::Form, User},
+    services::totp::TotpService,
+    telemetry, Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    code: String,
+}
+
+#[tracing::instrument(
+    name = "Confirm two-factor enrollment",
+    skip_all,
+    fields(user_id = %user.id)
+)]
+pub async fn handler(
+    user: User,
+    State(pool): State<Pool>,
+    State(totp_service): State<TotpService>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<StatusCode> {
+    let credential = match find_credential(user.id, &pool).await? {
+        Some(credential) => credential,
+        None => Err(Error::TwoFactorNotEnrolled).map_err(telemetry::warn)?,
+    };
+    if credential.confirmed {
+        Err(Error::TwoFactorAlreadyEnabled).map_err(telemetry::warn)?;
+    }
+    let (secret, _) =
+        totp_service.decrypt_secret(user.id, &credential.encrypted_secret)?;
+    let step = match TotpService::verify_code(&secret, &payload.code, None) {
+        Some(step) => step,
+        None => Err(Error::InvalidTwoFactorCode).map_err(telemetry::warn)?,
+    };
+    if !confirm_credential(user.id, step, &pool).await? {
+        Err(Error::TwoFactorAlreadyEnabled).map_err(telemetry::warn)?;
+    }
+    Ok(StatusCode::OK)
+}
+
+struct Credential {
+    encrypted_secret: Vec<u8>,
+    confirmed: bool,
+}
+
+async fn find_credential<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<Option<Credential>> {
+    sqlx::query_as!(
+        Credential,
+        r#"
+        select encrypted_secret, confirmed_at is not null as "confirmed!"
+        from totp_credentials
+        where user_id = $1;
+        "#,
+        user_id
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch TOTP credential")
+}
+
+#[tracing::instrument(
+    name = "Confirm TOTP credential",
+    skip(executor),
+    err(Debug)
+)]
+async fn confirm_credential<'e, E: Executor<'e>>(
+    user_id: i64,
+    step: i64,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        update totp_credentials
+        set confirmed_at = now(), last_used_step = $2
+        where user_id = $1 and confirmed_at is null;
+        "#,
+        user_id,
+        step
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() == 1)
+    .context("Failed to confirm TOTP credential")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn rejects_missing_enrollment(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request("123456")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn rejects_invalid_code(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let req = Request::builder()
+            .method("POST")
+            .uri("/auth/two_factor/enroll")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert!(res.status().is_success());
+        let res = server.call(request("not a code")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn enables_two_factor(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::enable_two_factor(&mut server).await;
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+    }
+
+    fn request(code: &str) -> Request<Body> {
+        let body = serde_urlencoded::to_string([("code", code)]).unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/auth/two_factor/confirm")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use secrecy::Secret;
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{
+    database::{begin_transaction, commit, Executor},
+    error::Error,
+    extractors::{validated::Form, User},
+    services::hash::PasswordHasher,
+    telemetry, Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    current_password: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Disable two-factor authentication",
+    skip_all,
+    fields(user_id = %user.id)
+)]
+pub async fn handler(
+    user: User,
+    State(pool): State<Pool>,
+    State(password_hasher): State<PasswordHasher>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<StatusCode> {
+    let expected_password_hash = get_password_hash(user.id, &pool)
+        .await?
+        .unwrap_or_else(|| password_hasher.mock_password_hash());
+    let is_password_valid = telemetry::instrument_blocking_task(move || {
+        password_hasher
+            .verify_password(&payload.current_password, &expected_password_hash)
+    })
+    .await??;
+    if !is_password_valid {
+        Err(Error::InvalidPassword).map_err(telemetry::warn)?;
+    }
+    let mut transaction = begin_transaction(&pool).await?;
+    if !delete_credential(user.id, &mut transaction).await? {
+        Err(Error::TwoFactorNotEnrolled).map_err(telemetry::warn)?;
+    }
+    delete_challenges(user.id, &mut transaction).await?;
+    commit(transaction).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn get_password_hash<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<Option<Secret<String>>> {
+    sqlx::query!(
+        r#"
+        select password_hash
+        from users
+        where id = $1;
+        "#,
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .map(|r| r.password_hash.map(Secret::new))
+    .context("Failed to get password hash from the database")
+}
+
+#[tracing::instrument(name = "Delete TOTP credential", skip(executor), err(Debug))]
+async fn delete_credential<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        delete from totp_credentials
+        where user_id = $1;
+        "#,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() == 1)
+    .context("Failed to delete TOTP credential")
+}
+
+#[tracing::instrument(
+    name = "Delete pending two-factor challenges",
+    skip(executor),
+    err(Debug)
+)]
+async fn delete_challenges<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        delete from two_factor_challenges
+        where user_id = $1;
+        "#,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to delete two-factor challenges")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn rejects_invalid_password(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::enable_two_factor(&mut server).await;
+        let mut invalid_password = TestUser::password();
+        invalid_password.push('\0');
+        let res = server.call(request(&invalid_password)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_disabled_two_factor(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request(&TestUser::password())).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn disables_two_factor(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::enable_two_factor(&mut server).await;
+        let res = server.call(request(&TestUser::password())).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    fn request(password: &str) -> Request<Body> {
+        let body = serde_urlencoded::to_string([("current_password", password)])
+            .unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/auth/two_factor/disable")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::Context;
+use axum::{extract::State, Json};
+use serde::Serialize;
+
+use crate::{
+    api::auth::two_factor::replace_recovery_codes,
+    database::{begin_transaction, commit, Executor},
+    error::Error,
+    extractors::User,
+    services::{hash::PasswordHasher, totp::TotpService},
+    telemetry, Pool,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Enrollment {
+    secret: String,
+    provisioning_uri: String,
+    recovery_codes: Vec<String>,
+}
+
+#[tracing::instrument(
+    name = "Enroll in two-factor authentication",
+    skip_all,
+    fields(user_id = %user.id)
+)]
+pub async fn handler(
+    user: User,
+    State(pool): State<Pool>,
+    State(totp_service): State<TotpService>,
+    State(password_hasher): State<PasswordHasher>,
+) -> crate::Result<Json<Enrollment>> {
+    let account = find_account(user.id, &pool).await?;
+    if !account.has_password {
+        Err(Error::PasswordNotSet).map_err(telemetry::warn)?;
+    }
+    let secret = TotpService::generate_secret();
+    let encrypted_secret = totp_service.encrypt_secret(user.id, &secret)?;
+    let mut transaction = begin_transaction(&pool).await?;
+    if !upsert_pending_credential(user.id, &encrypted_secret, &mut transaction)
+        .await?
+    {
+        Err(Error::TwoFactorAlreadyEnabled).map_err(telemetry::warn)?;
+    }
+    let recovery_codes =
+        replace_recovery_codes(user.id, password_hasher, &mut transaction)
+            .await?;
+    commit(transaction).await?;
+    let account_name = account.email.unwrap_or_else(|| user.id.to_string());
+    Ok(Json(Enrollment {
+        provisioning_uri: totp_service.provisioning_uri(&account_name, &secret),
+        secret: TotpService::encode_secret(&secret),
+        recovery_codes,
+    }))
+}
+
+struct Account {
+    email: Option<String>,
+    has_password: bool,
+}
+
+async fn find_account<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<Account> {
+    sqlx::query_as!(
+        Account,
+        r#"
+        select email, password_hash is not null as "has_password!"
+        from users
+        where id = $1;
+        "#,
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to fetch user")
+}
+
+/// Replaces a pending enrollment, but never a confirmed one.
+#[tracing::instrument(
+    name = "Save pending TOTP credential",
+    skip(encrypted_secret, executor),
+    err(Debug)
+)]
+async fn upsert_pending_credential<'e, E: Executor<'e>>(
+    user_id: i64,
+    encrypted_secret: &[u8],
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        insert into totp_credentials (user_id, encrypted_secret)
+        values ($1, $2)
+        on conflict (user_id) do update
+        set encrypted_secret = excluded.encrypted_secret
+        where totp_credentials.confirmed_at is null;
+        "#,
+        user_id,
+        encrypted_secret
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() == 1)
+    .context("Failed to save pending TOTP credential")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use reqwest::Url;
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn rejects_anonymous_user(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn returns_provisioning_uri(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        let body = read_json::<serde_json::Value>(res).await;
+        let secret = body["secret"].as_str().unwrap();
+        let uri = Url::parse(body["provisioningUri"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(uri.scheme(), "otpauth");
+        assert!(uri.path().ends_with(&TestUser::email()));
+        assert!(uri
+            .query_pairs()
+            .any(|(key, value)| key == "secret" && value == secret));
+    }
+
+    #[sqlx::test]
+    async fn returns_recovery_codes(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        let body = read_json::<serde_json::Value>(res).await;
+        let codes = body["recoveryCodes"].as_array().unwrap();
+        assert_eq!(codes.len(), 10);
+    }
+
+    #[sqlx::test]
+    async fn rejects_enabled_two_factor(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::enable_two_factor(&mut server).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/auth/two_factor/enroll")
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use serde::Serialize;
+
+use crate::{
+    database::Executor,
+    services::{hash::PasswordHasher, token::TokenService, totp::TotpService},
+    telemetry, Pool,
+};
+
+crate::api::router! {
+    /enroll,
+    /confirm,
+    /disable,
+    /recovery_codes,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TwoFactorChallenge {
+    challenge: String,
+}
+
+/// Returns a challenge for the second login step
+/// if the user has two-factor authentication enabled.
+pub async fn issue_challenge_if_enabled(
+    user_id: i64,
+    device_label: Option<&str>,
+    ttl: Duration,
+    pool: &Pool,
+) -> anyhow::Result<Option<TwoFactorChallenge>> {
+    if !is_two_factor_enabled(user_id, pool).await? {
+        return Ok(None);
+    }
+    let challenge = TokenService::generate_two_factor_challenge();
+    insert_two_factor_challenge(user_id, &challenge, device_label, ttl, pool)
+        .await?;
+    Ok(Some(TwoFactorChallenge {
+        challenge: challenge.expose_secret().to_owned(),
+    }))
+}
+
+/// Invalidates the previous set of recovery codes
+/// and returns the new one in plain text.
+#[tracing::instrument(
+    name = "Replace recovery codes",
+    skip(password_hasher, executor),
+    err(Debug)
+)]
+pub async fn replace_recovery_codes<'e, E: Executor<'e>>(
+    user_id: i64,
+    password_hasher: PasswordHasher,
+    executor: E,
+) -> anyhow::Result<Vec<String>> {
+    let codes = TotpService::generate_recovery_codes();
+    let normalized_codes = codes
+        .iter()
+        .filter_map(|c| TotpService::normalize_recovery_code(c.expose_secret()))
+        .collect::<Vec<_>>();
+    let code_hashes = telemetry::instrument_blocking_task(move || {
+        normalized_codes
+            .iter()
+            .map(|c| {
+                password_hasher
+                    .hash_password(c)
+                    .map(|h| h.expose_secret().to_owned())
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+    })
+    .await??;
+    sqlx::query!(
+        r#"
+        with deleted as (
+            delete from recovery_codes
+            where user_id = $1
+        )
+        insert into recovery_codes (user_id, code_hash)
+        select $1, unnest($2::varchar[]);
+        "#,
+        user_id,
+        &code_hashes
+    )
+    .execute(executor)
+    .await
+    .context("Failed to replace recovery codes")?;
+    Ok(codes.into_iter().map(|c| c.expose_secret().to_owned()).collect())
+}
+
+#[tracing::instrument(
+    name = "Check if two-factor authentication is enabled",
+    skip(executor),
+    err(Debug)
+)]
+async fn is_two_factor_enabled<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        select exists (
+            select 1
+            from totp_credentials
+            where user_id = $1 and confirmed_at is not null
+        ) as "enabled!";
+        "#,
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .map(|r| r.enabled)
+    .context("Failed to check if two-factor authentication is enabled")
+}
+
+#[tracing::instrument(
+    name = "Save two-factor challenge",
+    skip(challenge, executor),
+    err(Debug)
+)]
+async fn insert_two_factor_challenge<'e, E: Executor<'e>>(
+    user_id: i64,
+    challenge: &Secret<String>,
+    device_label: Option<&str>,
+    ttl: Duration,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        insert into two_factor_challenges
+            (token_hash, user_id, device_label, expires_at)
+        values ($1, $2, $3, now() + make_interval(secs => $4));
+        "#,
+        TokenService::hash_token(challenge),
+        user_id,
+        device_label,
+        ttl.as_secs_f64()
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to insert two-factor challenge")
+}
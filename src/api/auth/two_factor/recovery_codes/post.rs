@@ -0,0 +1,157 @@
+use anyhow::Context;
+use axum::{extract::State, Json};
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    api::auth::two_factor::replace_recovery_codes,
+    database::{begin_transaction, commit, Executor},
+    error::Error,
+    extractors::{validated::Form, User},
+    services::hash::PasswordHasher,
+    telemetry, Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    current_password: Secret<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+#[tracing::instrument(
+    name = "Regenerate recovery codes",
+    skip_all,
+    fields(user_id = %user.id)
+)]
+pub async fn handler(
+    user: User,
+    State(pool): State<Pool>,
+    State(password_hasher): State<PasswordHasher>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<Json<RecoveryCodes>> {
+    let expected_password_hash = get_password_hash(user.id, &pool)
+        .await?
+        .unwrap_or_else(|| password_hasher.mock_password_hash());
+    let moved_password_hasher = password_hasher.clone();
+    let is_password_valid = telemetry::instrument_blocking_task(move || {
+        moved_password_hasher
+            .verify_password(&payload.current_password, &expected_password_hash)
+    })
+    .await??;
+    if !is_password_valid {
+        Err(Error::InvalidPassword).map_err(telemetry::warn)?;
+    }
+    let mut transaction = begin_transaction(&pool).await?;
+    if !lock_enabled_credential(user.id, &mut transaction).await? {
+        Err(Error::TwoFactorNotEnrolled).map_err(telemetry::warn)?;
+    }
+    let recovery_codes =
+        replace_recovery_codes(user.id, password_hasher, &mut transaction)
+            .await?;
+    commit(transaction).await?;
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+async fn get_password_hash<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<Option<Secret<String>>> {
+    sqlx::query!(
+        r#"
+        select password_hash
+        from users
+        where id = $1;
+        "#,
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .map(|r| r.password_hash.map(Secret::new))
+    .context("Failed to get password hash from the database")
+}
+
+async fn lock_enabled_credential<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        select user_id
+        from totp_credentials
+        where user_id = $1 and confirmed_at is not null
+        for update;
+        "#,
+        user_id
+    )
+    .fetch_optional(executor)
+    .await
+    .map(|r| r.is_some())
+    .context("Failed to fetch TOTP credential")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn rejects_invalid_password(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::enable_two_factor(&mut server).await;
+        let mut invalid_password = TestUser::password();
+        invalid_password.push('\0');
+        let res = server.call(request(&invalid_password)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_disabled_two_factor(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request(&TestUser::password())).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn invalidates_previous_codes(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let two_factor = TestUser::enable_two_factor(&mut server).await;
+        let res = server.call(request(&TestUser::password())).await;
+        assert!(res.status().is_success());
+        let body = read_json::<serde_json::Value>(res).await;
+        let codes = body["recoveryCodes"].as_array().unwrap();
+        assert_eq!(codes.len(), 10);
+        let old_code = &two_factor.recovery_codes[0];
+        let res = TestUser::complete_login(&mut server, old_code).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let new_code = codes[0].as_str().unwrap();
+        let res = TestUser::complete_login(&mut server, new_code).await;
+        assert!(res.status().is_success());
+    }
+
+    fn request(password: &str) -> Request<Body> {
+        let body = serde_urlencoded::to_string([("current_password", password)])
+            .unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/auth/two_factor/recovery_codes")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use secrecy::Secret;
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{
+    api::auth::email_code::consume_email_code,
+    config::auth,
+    database::{email_code::Purpose, Executor},
+    extractors::{validated::Form, ClientInfo},
+    services::hash::PasswordHasher,
+    Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    #[validate(email(message = "is not a valid email"))]
+    email: String,
+    code: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Verify a user with email code",
+    skip_all,
+    fields(email = %payload.email)
+)]
+pub async fn handler(
+    client: ClientInfo,
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(password_hasher): State<PasswordHasher>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<StatusCode> {
+    let user_id = consume_email_code(
+        &payload.email,
+        Purpose::Verification,
+        &payload.code,
+        client.ip_address,
+        &auth_config,
+        password_hasher,
+        &pool,
+    )
+    .await?;
+    verify_user(user_id, &pool).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn verify_user<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update users
+        set verified = true
+        where id = $1;
+        "#,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to verify user")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+
+    use crate::{
+        config::auth::VerificationMode,
+        test_helpers::{extract_email_code, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn verifies_user_with_code_from_signup(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.require_verified_email = true;
+            config.auth.verification_mode = VerificationMode::Code;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let code = extract_email_code(&server.received_emails().await[0]);
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = server.call(request(&code)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = TestUser::login(&mut server).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn does_not_accept_login_code(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        TestUser::request_email_code(&mut server).await;
+        let emails = server.received_emails().await;
+        let code = extract_email_code(emails.last().unwrap());
+        let res = server.call(request(&code)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    fn request(code: &str) -> Request<Body> {
+        let body = serde_urlencoded::to_string([
+            ("email", TestUser::email()),
+            ("code", code.into()),
+        ])
+        .unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/auth/verify/code")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
@@ -3,52 +3,190 @@ use axum::{
     extract::{Query, State},
     http::StatusCode,
 };
+use secrecy::Secret;
 use serde::Deserialize;
-use uuid::Uuid;
 
-use crate::{database::Executor, error::Error, telemetry, Pool};
+use crate::{
+    database::{begin_transaction, commit, Executor},
+    error::Error,
+    services::token::TokenService,
+    telemetry, Pool,
+};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Params {
-    token: Uuid,
+    token: Secret<String>,
 }
 
-#[tracing::instrument(
-    name = "Verify a user",
-    skip_all,
-    fields(
-        token = %params.token,
-    )
-)]
+#[tracing::instrument(name = "Verify a user", skip_all)]
 pub async fn handler(
     Query(params): Query<Params>,
     State(pool): State<Pool>,
 ) -> crate::Result<StatusCode> {
-    verify_user(&params.token, &pool).await?;
+    let mut transaction = begin_transaction(&pool).await?;
+    let token = find_verification_token(&params.token, &mut transaction)
+        .await?
+        .ok_or(Error::UnknownVerificationToken)
+        .map_err(telemetry::warn)?;
+    if token.consumed {
+        Err(Error::UsedVerificationToken).map_err(telemetry::warn)?;
+    }
+    if token.expired {
+        Err(Error::ExpiredVerificationToken).map_err(telemetry::warn)?;
+    }
+    consume_verification_token(&params.token, &mut transaction).await?;
+    verify_user(token.user_id, &mut transaction).await?;
+    commit(transaction).await?;
     Ok(StatusCode::OK)
 }
 
+struct VerificationToken {
+    user_id: i64,
+    expired: bool,
+    consumed: bool,
+}
+
+#[tracing::instrument(name = "Find verification token", skip_all, err(Debug))]
+async fn find_verification_token<'e, E: Executor<'e>>(
+    verification_token: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<Option<VerificationToken>> {
+    let token = sqlx::query!(
+        r#"
+        select
+          user_id,
+          expires_at <= now() as "expired!",
+          consumed_at is not null as "consumed!"
+        from verification_tokens
+        where token_hash = $1
+        for update;
+        "#,
+        TokenService::hash_token(verification_token)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to select verification token")?
+    .map(|r| VerificationToken {
+        user_id: r.user_id,
+        expired: r.expired,
+        consumed: r.consumed,
+    });
+    Ok(token)
+}
+
+#[tracing::instrument(
+    name = "Consume verification token",
+    skip_all,
+    err(Debug)
+)]
+async fn consume_verification_token<'e, E: Executor<'e>>(
+    verification_token: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update verification_tokens
+        set consumed_at = now()
+        where token_hash = $1;
+        "#,
+        TokenService::hash_token(verification_token)
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to consume verification token")
+}
+
 #[tracing::instrument(name = "Update user verification status", skip(executor))]
 async fn verify_user<'e, E: Executor<'e>>(
-    verification_token: &Uuid,
+    user_id: i64,
     executor: E,
-) -> crate::Result<()> {
-    match sqlx::query!(
+) -> anyhow::Result<()> {
+    sqlx::query!(
         r#"
         update users
         set verified = true
-        where verification_token = $1;
+        where id = $1;
         "#,
-        verification_token
+        user_id
     )
     .execute(executor)
     .await
+    .map(|_| ())
     .context("Failed to update user verification status")
-    .map_err(telemetry::error)?
-    .rows_affected()
-    {
-        0 => Err(Error::UnknownVerificationToken).map_err(telemetry::warn),
-        1 => Ok(()),
-        _ => unreachable!(),
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{extract_email_link, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn rejects_unknown_token(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.call(request("/auth/verify?token=unknown")).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn verifies_user(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::signup(&mut server).await;
+        let res = server.call(verification_request(&server).await).await;
+        assert!(res.status().is_success());
+        let verified = sqlx::query!(r#"select verified from users;"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .verified;
+        assert!(verified);
+    }
+
+    #[sqlx::test]
+    async fn rejects_used_token(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::signup(&mut server).await;
+        let res = server.call(verification_request(&server).await).await;
+        assert!(res.status().is_success());
+        let res = server.call(verification_request(&server).await).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test]
+    async fn rejects_expired_token(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::signup(&mut server).await;
+        sqlx::query!(
+            r#"
+            update verification_tokens
+            set expires_at = now() - interval '1 second';
+            "#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let res = server.call(verification_request(&server).await).await;
+        assert_eq!(res.status(), StatusCode::GONE);
+    }
+
+    async fn verification_request(server: &TestServer) -> Request<Body> {
+        let emails = server.received_emails().await;
+        let link = extract_email_link(emails.last().unwrap());
+        request(&format!("{}?{}", link.path(), link.query().unwrap()))
+    }
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
     }
 }
@@ -1,3 +1,122 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{
+    api::auth::email_code::issue_email_code,
+    config::auth::{self, VerificationMode},
+    database::{email_code::Purpose, Executor},
+    services::{
+        email::{EmailClient, SendEmailRequest},
+        hash::PasswordHasher,
+        token::TokenService,
+    },
+};
+
 crate::api::router! {
     get,
+    /resend,
+    /code,
+}
+
+/// Sends either a verification link or a code,
+/// depending on the configured verification mode.
+pub async fn start_verification<'e, E: Executor<'e>>(
+    user_id: i64,
+    recipient: &str,
+    base_url: &Url,
+    auth_config: &auth::Config,
+    password_hasher: PasswordHasher,
+    email_client: &EmailClient,
+    executor: E,
+) -> anyhow::Result<()> {
+    match auth_config.verification_mode {
+        VerificationMode::Link => {
+            let verification_token =
+                TokenService::generate_verification_token();
+            insert_verification_token(
+                user_id,
+                &verification_token,
+                auth_config.verification_token_ttl,
+                executor,
+            )
+            .await?;
+            send_verification_email(
+                email_client,
+                recipient,
+                base_url,
+                &verification_token,
+            )
+            .await
+        }
+        VerificationMode::Code => {
+            issue_email_code(
+                user_id,
+                Purpose::Verification,
+                recipient,
+                auth_config,
+                password_hasher,
+                email_client,
+                executor,
+            )
+            .await
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Save verification token",
+    skip(verification_token, executor),
+    err(Debug)
+)]
+async fn insert_verification_token<'e, E: Executor<'e>>(
+    user_id: i64,
+    verification_token: &Secret<String>,
+    ttl: Duration,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        insert into verification_tokens (token_hash, user_id, expires_at)
+        values ($1, $2, now() + make_interval(secs => $3));
+        "#,
+        TokenService::hash_token(verification_token),
+        user_id,
+        ttl.as_secs_f64()
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to insert verification token")
+}
+
+#[tracing::instrument(
+    name = "Send verification email",
+    skip(email_client, base_url, verification_token)
+)]
+async fn send_verification_email(
+    email_client: &EmailClient,
+    recipient: &str,
+    base_url: &Url,
+    verification_token: &Secret<String>,
+) -> anyhow::Result<()> {
+    let mut verification_link = base_url.clone();
+    verification_link.set_path("auth/verify");
+    verification_link.set_query(Some(&format!(
+        "token={}",
+        verification_token.expose_secret()
+    )));
+
+    let request = SendEmailRequest {
+        recipient,
+        subject: "Account verification",
+        text_body: &format!("{verification_link}"),
+        html_body: &format!("<a>{verification_link}</a>"),
+    };
+    email_client
+        .send_email(&request)
+        .await
+        .context("Failed to send a verification email")
 }
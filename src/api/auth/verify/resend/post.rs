@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use reqwest::Url;
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{
+    api::auth::verify::start_verification,
+    config::auth,
+    database::{begin_transaction, commit, Executor},
+    extractors::validated::Form,
+    services::{email::EmailClient, hash::PasswordHasher},
+    Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    #[validate(email(message = "is not a valid email"))]
+    email: String,
+}
+
+#[tracing::instrument(
+    name = "Resend verification email",
+    skip_all,
+    fields(email = %payload.email)
+)]
+pub async fn handler(
+    State(base_url): State<Url>,
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(password_hasher): State<PasswordHasher>,
+    State(email_client): State<EmailClient>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<StatusCode> {
+    let mut transaction = begin_transaction(&pool).await?;
+    let user = find_user(
+        &payload.email,
+        auth_config.verification_email_cooldown,
+        &mut transaction,
+    )
+    .await?;
+    // every email gets the same response, so that it does not tell
+    // which accounts exist, the cooldown is applied silently
+    let user_id = match user {
+        Some(user) if !user.verified && !user.on_cooldown => user.id,
+        _ => return Ok(StatusCode::ACCEPTED),
+    };
+    delete_verification_tokens(user_id, &mut transaction).await?;
+    start_verification(
+        user_id,
+        &payload.email,
+        &base_url,
+        &auth_config,
+        password_hasher,
+        &email_client,
+        &mut transaction,
+    )
+    .await?;
+    commit(transaction).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+struct User {
+    id: i64,
+    verified: bool,
+    on_cooldown: bool,
+}
+
+#[tracing::instrument(
+    name = "Find user by email",
+    skip(cooldown, executor),
+    err(Debug)
+)]
+async fn find_user<'e, E: Executor<'e>>(
+    email: &str,
+    cooldown: Duration,
+    executor: E,
+) -> anyhow::Result<Option<User>> {
+    let user = sqlx::query!(
+        r#"
+        select
+          id,
+          verified,
+          exists(
+            select 1
+            from verification_tokens
+            where user_id = users.id
+              and created_at > now() - make_interval(secs => $2)
+          ) or exists(
+            select 1
+            from email_codes
+            where user_id = users.id
+              and purpose = 'verification'
+              and created_at > now() - make_interval(secs => $2)
+          ) as "on_cooldown!"
+        from users
+        where email = $1
+        for update;
+        "#,
+        email,
+        cooldown.as_secs_f64()
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to select user from database")?
+    .map(|r| User {
+        id: r.id,
+        verified: r.verified,
+        on_cooldown: r.on_cooldown,
+    });
+    Ok(user)
+}
+
+#[tracing::instrument(
+    name = "Delete previous verification tokens",
+    skip(executor),
+    err(Debug)
+)]
+async fn delete_verification_tokens<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        delete from verification_tokens
+        where user_id = $1 and consumed_at is null;
+        "#,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to delete verification tokens")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+    use reqwest::Url;
+    use wiremock::ResponseTemplate;
+
+    use crate::{
+        test_helpers::{
+            extract_email_link, when_sending_an_email, TestServer, TestUser,
+        },
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn does_not_reveal_unknown_email(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let mock = when_sending_an_email()
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0);
+        server.mount_mock(mock).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+    }
+
+    #[sqlx::test]
+    async fn ignores_requests_during_cooldown(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::signup(&mut server).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert_eq!(server.received_emails().await.len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn does_nothing_for_verified_user(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::signup(&mut server).await;
+        let link = extract_email_link(&server.received_emails().await[0]);
+        let res = server.call(get(&link)).await;
+        assert!(res.status().is_success());
+        skip_cooldown(&pool).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert_eq!(server.received_emails().await.len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn replaces_verification_token(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::signup(&mut server).await;
+        skip_cooldown(&pool).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let emails = server.received_emails().await;
+        assert_eq!(emails.len(), 2);
+        let old_link = extract_email_link(&emails[0]);
+        let new_link = extract_email_link(&emails[1]);
+        let res = server.call(get(&old_link)).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = server.call(get(&new_link)).await;
+        assert!(res.status().is_success());
+    }
+
+    async fn skip_cooldown(pool: &Pool) {
+        sqlx::query!(
+            r#"
+            update verification_tokens
+            set created_at = now() - interval '1 day';
+            "#
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    fn get(link: &Url) -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri(format!("{}?{}", link.path(), link.query().unwrap()))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn request() -> Request<Body> {
+        let body = serde_urlencoded::to_string([("email", TestUser::email())])
+            .unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/auth/verify/resend")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
@@ -0,0 +1,47 @@
+use axum::{extract::State, Json};
+
+use crate::{
+    config::auth,
+    database::webauthn_challenge::{self, Ceremony},
+    services::webauthn::{RequestOptions, WebauthnService},
+    Pool,
+};
+
+/// Passkeys are discoverable, so the user is not known at this point.
+#[tracing::instrument(name = "Start passkey authentication", skip_all)]
+pub async fn handler(
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(webauthn_service): State<WebauthnService>,
+) -> crate::Result<Json<RequestOptions>> {
+    let challenge = WebauthnService::generate_challenge();
+    webauthn_challenge::insert(
+        None,
+        &challenge,
+        Ceremony::Authentication,
+        auth_config.webauthn_challenge_ttl,
+        &pool,
+    )
+    .await?;
+    Ok(Json(webauthn_service.request_options(challenge)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_helpers::{read_json, TestServer},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn returns_challenge(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server
+            .post_json("/auth/webauthn/authenticate/options", "")
+            .await;
+        assert!(res.status().is_success());
+        let options = read_json::<serde_json::Value>(res).await;
+        assert!(options["challenge"].is_string());
+        assert_eq!(options["rpId"], "localhost");
+    }
+}
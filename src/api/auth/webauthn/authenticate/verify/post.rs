@@ -0,0 +1,273 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use serde::Deserialize;
+use tower_cookies::Cookies;
+use validator::Validate;
+
+use crate::{
+    api::auth::{ensure_transport, start_session},
+    config::auth::{self, TokenTransport},
+    database::{
+        begin_transaction, commit,
+        webauthn_challenge::{self, Ceremony},
+        Executor,
+    },
+    error::Error,
+    extractors::{validated::Json, ClientInfo},
+    services::{
+        cookie::CookieService,
+        token::TokenService,
+        webauthn::{
+            AuthenticationCredential, StoredCredential, WebauthnService,
+        },
+    },
+    telemetry, Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    #[serde(flatten)]
+    credential: AuthenticationCredential,
+}
+
+#[tracing::instrument(name = "Log in with passkey", skip_all)]
+#[allow(clippy::too_many_arguments)]
+pub async fn handler(
+    cookies: Cookies,
+    client: ClientInfo,
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(webauthn_service): State<WebauthnService>,
+    State(token_service): State<TokenService>,
+    State(cookie_service): State<CookieService>,
+    Json(payload): Json<Payload>,
+) -> crate::Result<StatusCode> {
+    ensure_transport(
+        auth_config.token_transport,
+        TokenTransport::accepts_cookie,
+    )?;
+    let credential = &payload.credential;
+    // the lock keeps concurrent logins from passing the same sign count
+    let mut transaction = begin_transaction(&pool).await?;
+    let stored =
+        match find_credential_for_update(&credential.id.0, &mut transaction)
+            .await?
+        {
+            Some(stored) => stored,
+            None => {
+                Err(Error::InvalidWebauthnResponse).map_err(telemetry::warn)?
+            }
+        };
+    let user_handle = WebauthnService::user_handle(stored.user_id);
+    if let Some(handle) = &credential.response.user_handle {
+        if handle.0 != user_handle {
+            Err(Error::InvalidWebauthnResponse).map_err(telemetry::warn)?;
+        }
+    }
+    let assertion = webauthn_service
+        .verify_assertion(credential, &stored.credential)
+        .map_err(telemetry::warn)
+        .map_err(|_| Error::InvalidWebauthnResponse)?;
+    let challenge_user_id = webauthn_challenge::consume(
+        &assertion.challenge,
+        Ceremony::Authentication,
+        &pool,
+    )
+    .await?;
+    if challenge_user_id != Some(None) {
+        Err(Error::InvalidWebauthnResponse).map_err(telemetry::warn)?;
+    }
+    update_sign_count(&credential.id.0, assertion.sign_count, &mut transaction)
+        .await?;
+    let tokens = start_session(
+        stored.user_id,
+        stored.name.as_deref(),
+        &client,
+        &auth_config,
+        &token_service,
+        &mut transaction,
+    )
+    .await?;
+    commit(transaction).await?;
+    cookie_service.set_access_token(&cookies, tokens.access_token);
+    cookie_service.set_refresh_token(&cookies, tokens.refresh_token);
+    Ok(StatusCode::OK)
+}
+
+struct Credential {
+    user_id: i64,
+    name: Option<String>,
+    credential: StoredCredential,
+}
+
+async fn find_credential_for_update<'e, E: Executor<'e>>(
+    id: &[u8],
+    executor: E,
+) -> anyhow::Result<Option<Credential>> {
+    sqlx::query!(
+        r#"
+        select c.user_id, c.public_key, c.algorithm, c.sign_count, c.name
+        from webauthn_credentials c
+        where c.id = $1
+        for update of c;
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch WebAuthn credential")?
+    .map(|r| {
+        Ok(Credential {
+            user_id: r.user_id,
+            name: r.name,
+            credential: StoredCredential {
+                public_key: r.public_key,
+                algorithm: r.algorithm,
+                sign_count: r.sign_count.try_into()?,
+            },
+        })
+    })
+    .transpose()
+}
+
+async fn update_sign_count<'e, E: Executor<'e>>(
+    id: &[u8],
+    sign_count: u32,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update webauthn_credentials
+        set sign_count = $2, last_used_at = now()
+        where id = $1;
+        "#,
+        id,
+        i64::from(sign_count)
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to update WebAuthn credential")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        config::auth::TokenTransport,
+        test_helpers::{
+            read_json, SoftwareAuthenticator, TestServer, TestUser,
+        },
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn logs_user_in(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let mut authenticator = TestUser::register_passkey(&mut server).await;
+        server.clear_cookies();
+        let options = options(&mut server).await;
+        let assertion = authenticator.authenticate(&options).to_string();
+        let res = server.post_json(URI, &assertion).await;
+        assert!(res.status().is_success());
+        let req = Request::builder()
+            .method("GET")
+            .uri("/health_check/protected")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert!(res.status().is_success());
+    }
+
+    #[sqlx::test]
+    async fn rejects_replayed_assertion(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let mut authenticator = TestUser::register_passkey(&mut server).await;
+        let options = options(&mut server).await;
+        let assertion = authenticator.authenticate(&options).to_string();
+        let res = server.post_json(URI, &assertion).await;
+        assert!(res.status().is_success());
+        let res = server.post_json(URI, &assertion).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn requires_user_verification(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let mut authenticator = TestUser::register_passkey(&mut server).await;
+        server.clear_cookies();
+        authenticator.verifies_user = false;
+        let options = options(&mut server).await;
+        assert_eq!(options["userVerification"], "required");
+        let assertion = authenticator.authenticate(&options).to_string();
+        let res = server.post_json(URI, &assertion).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_invalid_signature(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let mut authenticator = TestUser::register_passkey(&mut server).await;
+        let options = options(&mut server).await;
+        let mut assertion = authenticator.authenticate(&options);
+        let other = SoftwareAuthenticator::new().authenticate(&options);
+        assertion["response"]["signature"] =
+            other["response"]["signature"].clone();
+        let res = server.post_json(URI, &assertion.to_string()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_unknown_credential(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let options = options(&mut server).await;
+        let assertion = SoftwareAuthenticator::new().authenticate(&options);
+        let res = server.post_json(URI, &assertion.to_string()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_unverified_email_if_required(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let mut authenticator = TestUser::register_passkey(&mut server).await;
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.require_verified_email = true;
+        })
+        .await;
+        let options = options(&mut server).await;
+        let assertion = authenticator.authenticate(&options).to_string();
+        let res = server.post_json(URI, &assertion).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn fails_if_cookie_transport_is_disabled(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.token_transport = TokenTransport::Bearer;
+        })
+        .await;
+        let options = options(&mut server).await;
+        let assertion = SoftwareAuthenticator::new().authenticate(&options);
+        let res = server.post_json(URI, &assertion.to_string()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    const URI: &str = "/auth/webauthn/authenticate/verify";
+
+    async fn options(server: &mut TestServer) -> serde_json::Value {
+        let res = server
+            .post_json("/auth/webauthn/authenticate/options", "")
+            .await;
+        assert!(res.status().is_success());
+        read_json(res).await
+    }
+}
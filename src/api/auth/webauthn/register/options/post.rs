@@ -0,0 +1,120 @@
+use anyhow::Context;
+use axum::{extract::State, Json};
+
+use crate::{
+    config::auth,
+    database::{
+        webauthn_challenge::{self, Ceremony},
+        Executor,
+    },
+    extractors::User,
+    services::webauthn::{CreationOptions, WebauthnService},
+    Pool,
+};
+
+#[tracing::instrument(
+    name = "Start passkey registration",
+    skip_all,
+    fields(user_id = %user.id)
+)]
+pub async fn handler(
+    user: User,
+    State(auth_config): State<auth::Config>,
+    State(pool): State<Pool>,
+    State(webauthn_service): State<WebauthnService>,
+) -> crate::Result<Json<CreationOptions>> {
+    let account = find_account(user.id, &pool).await?;
+    let existing_credentials = find_credential_ids(user.id, &pool).await?;
+    let challenge = WebauthnService::generate_challenge();
+    webauthn_challenge::insert(
+        Some(user.id),
+        &challenge,
+        Ceremony::Registration,
+        auth_config.webauthn_challenge_ttl,
+        &pool,
+    )
+    .await?;
+    Ok(Json(webauthn_service.creation_options(
+        challenge,
+        user.id,
+        account.email.unwrap_or_else(|| account.name.clone()),
+        account.name,
+        existing_credentials,
+    )))
+}
+
+struct Account {
+    name: String,
+    email: Option<String>,
+}
+
+async fn find_account<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<Account> {
+    sqlx::query_as!(
+        Account,
+        r#"
+        select name, email
+        from users
+        where id = $1;
+        "#,
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to fetch user")
+}
+
+async fn find_credential_ids<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    sqlx::query!(
+        r#"
+        select id
+        from webauthn_credentials
+        where user_id = $1;
+        "#,
+        user_id
+    )
+    .fetch_all(executor)
+    .await
+    .map(|rows| rows.into_iter().map(|r| r.id).collect())
+    .context("Failed to fetch user's WebAuthn credentials")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn rejects_anonymous_user(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.post_json("/auth/webauthn/register/options", "").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn excludes_registered_credentials(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.post_json("/auth/webauthn/register/options", "").await;
+        assert!(res.status().is_success());
+        let options = read_json::<serde_json::Value>(res).await;
+        assert!(options["challenge"].is_string());
+        assert_eq!(options["rp"]["id"], "localhost");
+        assert_eq!(options["excludeCredentials"].as_array().unwrap().len(), 0);
+        let authenticator = TestUser::register_passkey(&mut server).await;
+        let res = server.post_json("/auth/webauthn/register/options", "").await;
+        let options = read_json::<serde_json::Value>(res).await;
+        let excluded = options["excludeCredentials"].as_array().unwrap();
+        assert_eq!(excluded.len(), 1);
+        assert_eq!(excluded[0]["id"], authenticator.credential_id());
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{
+    database::{
+        webauthn_challenge::{self, Ceremony},
+        Executor,
+    },
+    error::Error,
+    extractors::{validated::Json, User},
+    services::webauthn::{
+        NewCredential, RegistrationCredential, WebauthnService,
+    },
+    telemetry, Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    #[validate(length(
+        max = 100,
+        message = "cannot be longer than 100 characters"
+    ))]
+    name: Option<String>,
+    #[serde(flatten)]
+    credential: RegistrationCredential,
+}
+
+#[tracing::instrument(
+    name = "Finish passkey registration",
+    skip_all,
+    fields(user_id = %user.id)
+)]
+pub async fn handler(
+    user: User,
+    State(pool): State<Pool>,
+    State(webauthn_service): State<WebauthnService>,
+    Json(payload): Json<Payload>,
+) -> crate::Result<StatusCode> {
+    let credential = webauthn_service
+        .verify_registration(&payload.credential)
+        .map_err(telemetry::warn)
+        .map_err(|_| Error::InvalidWebauthnResponse)?;
+    let challenge_user_id = webauthn_challenge::consume(
+        &credential.challenge,
+        Ceremony::Registration,
+        &pool,
+    )
+    .await?;
+    if challenge_user_id != Some(Some(user.id)) {
+        Err(Error::InvalidWebauthnResponse).map_err(telemetry::warn)?;
+    }
+    let name = payload.name.as_deref();
+    if !insert_credential(user.id, &credential, name, &pool).await? {
+        Err(Error::InvalidWebauthnResponse).map_err(telemetry::warn)?;
+    }
+    Ok(StatusCode::CREATED)
+}
+
+#[tracing::instrument(
+    name = "Save WebAuthn credential",
+    skip(credential, executor),
+    err(Debug)
+)]
+async fn insert_credential<'e, E: Executor<'e>>(
+    user_id: i64,
+    credential: &NewCredential,
+    name: Option<&str>,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        insert into webauthn_credentials
+            (id, user_id, public_key, algorithm, sign_count, name)
+        values ($1, $2, $3, $4, $5, $6)
+        on conflict do nothing;
+        "#,
+        credential.id,
+        user_id,
+        credential.public_key,
+        credential.algorithm,
+        i64::from(credential.sign_count),
+        name
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() == 1)
+    .context("Failed to insert WebAuthn credential")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use crate::{
+        test_helpers::{read_json, SoftwareAuthenticator, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn registers_credential(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        TestUser::register_passkey(&mut server).await;
+        let count = sqlx::query!(
+            r#"select count(*) as "count!" from webauthn_credentials;"#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .count;
+        assert_eq!(count, 1);
+    }
+
+    #[sqlx::test]
+    async fn rejects_replayed_response(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let options = options(&mut server).await;
+        let mut authenticator = SoftwareAuthenticator::new();
+        let credential = authenticator.register(&options).to_string();
+        let res = server.post_json(URI, &credential).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let res = server.post_json(URI, &credential).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_foreign_origin(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let options = options(&mut server).await;
+        let mut authenticator =
+            SoftwareAuthenticator::with_origin("https://evil.com");
+        let credential = authenticator.register(&options).to_string();
+        let res = server.post_json(URI, &credential).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn rejects_challenge_of_another_user(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server
+            .post_json("/auth/webauthn/authenticate/options", "")
+            .await;
+        let options = read_json::<serde_json::Value>(res).await;
+        TestUser::enter_session(&mut server).await;
+        let mut authenticator = SoftwareAuthenticator::new();
+        let credential = authenticator.register(&options).to_string();
+        let res = server.post_json(URI, &credential).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    const URI: &str = "/auth/webauthn/register/verify";
+
+    async fn options(server: &mut TestServer) -> serde_json::Value {
+        let res = server.post_json("/auth/webauthn/register/options", "").await;
+        assert!(res.status().is_success());
+        read_json(res).await
+    }
+}
@@ -1,4 +1,5 @@
 crate::api::router! {
     get,
     /protected,
+    /verified,
 }
@@ -8,9 +8,25 @@ pub async fn handler(_user: User) -> StatusCode {
 
 #[cfg(test)]
 mod tests {
-    use axum::{body::Body, http::Request, http::StatusCode};
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+    };
 
-    use crate::{test_helpers::TestServer, Pool};
+    use jsonwebtoken::{
+        Algorithm, DecodingKey, EncodingKey, Header, Validation,
+    };
+    use secrecy::{ExposeSecret, Secret};
+    use tower_cookies::{
+        cookie::{Cookie, CookieJar},
+        Key,
+    };
+
+    use crate::{
+        config::{auth::TokenTransport, Config},
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
 
     #[sqlx::test]
     async fn fails_for_logged_out_user(pool: Pool) {
@@ -20,6 +36,192 @@ mod tests {
         assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
     }
 
+    #[sqlx::test]
+    async fn ignores_cookies_if_only_bearer_is_accepted(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let access_token = server.cookie("access_token").unwrap();
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.token_transport = TokenTransport::Bearer;
+        })
+        .await;
+        server.set_cookie("access_token", access_token);
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn ignores_bearer_token_if_only_cookies_are_accepted(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login_for_tokens(&mut server).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["accessToken"].as_str().unwrap();
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.token_transport = TokenTransport::Cookie;
+        })
+        .await;
+        let mut req = request();
+        req.headers_mut().insert(
+            AUTHORIZATION,
+            format!("Bearer {access_token}").parse().unwrap(),
+        );
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn accepts_api_key_if_only_cookies_are_accepted(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.auth.token_transport = TokenTransport::Cookie;
+        })
+        .await;
+        TestUser::enter_session(&mut server).await;
+        let key = TestUser::create_api_key(&mut server, None).await;
+        server.clear_cookies();
+        let res = server.call(request_with_api_key(&key)).await;
+        assert!(res.status().is_success());
+    }
+
+    #[sqlx::test]
+    async fn rejects_expired_api_key(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let key = TestUser::create_api_key(&mut server, None).await;
+        server.clear_cookies();
+        sqlx::query!("update api_keys set expires_at = now();")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let res = server.call(request_with_api_key(&key)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn accepts_cookies_from_retired_key(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let access_token = server.cookie("access_token").unwrap();
+        let mut server =
+            TestServer::with_config(pool, |config| rotate_keyring(config))
+                .await;
+        server.set_cookie("access_token", access_token.clone());
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        let reencrypted_token = server.cookie("access_token").unwrap();
+        assert_ne!(reencrypted_token, access_token);
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+    }
+
+    #[sqlx::test]
+    async fn rejects_cookies_from_removed_key(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let access_token = server.cookie("access_token").unwrap();
+        let mut server = TestServer::with_config(pool, |config| {
+            rotate_keyring(config);
+            config.keyring.secrets.retain(|id, _| id == "2");
+        })
+        .await;
+        server.set_cookie("access_token", access_token);
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn accepts_cookies_encrypted_with_legacy_key(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login_for_tokens(&mut server).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["accessToken"].as_str().unwrap();
+        let config = Config::new().unwrap();
+        let secret = &config.keyring.secrets[&config.keyring.primary];
+        let legacy_key = Key::from(secret.expose_secret().as_bytes());
+        let mut jar = CookieJar::new();
+        jar.private_mut(&legacy_key)
+            .add(Cookie::new("access_token", access_token.to_owned()));
+        let legacy_cookie = jar.get("access_token").unwrap().value();
+        server.set_cookie("access_token", legacy_cookie.to_owned());
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        assert_ne!(server.cookie("access_token").unwrap(), legacy_cookie);
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+    }
+
+    #[sqlx::test]
+    async fn rejects_legacy_cookies_once_legacy_keys_are_off(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.server.accept_legacy_keys = false;
+        })
+        .await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login_for_tokens(&mut server).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["accessToken"].as_str().unwrap();
+        let config = Config::new().unwrap();
+        let secret = &config.keyring.secrets[&config.keyring.primary];
+        let legacy_key = Key::from(secret.expose_secret().as_bytes());
+        let mut jar = CookieJar::new();
+        jar.private_mut(&legacy_key)
+            .add(Cookie::new("access_token", access_token.to_owned()));
+        let legacy_cookie = jar.get("access_token").unwrap().value();
+        server.set_cookie("access_token", legacy_cookie.to_owned());
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn accepts_cookies_and_tokens_from_before_keyring(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login_for_tokens(&mut server).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["accessToken"].as_str().unwrap();
+        // sign the claims again without a key id, as before the keyring
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.insecure_disable_signature_validation();
+        let claims = jsonwebtoken::decode::<serde_json::Value>(
+            access_token,
+            &DecodingKey::from_secret(&[]),
+            &validation,
+        )
+        .unwrap()
+        .claims;
+        let config = Config::new().unwrap();
+        let hmac_secret = config.server.hmac_secret.expose_secret().as_bytes();
+        let access_token = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(hmac_secret),
+        )
+        .unwrap();
+        let res = server.call(request_with_api_key(&access_token)).await;
+        assert!(res.status().is_success());
+        let mut jar = CookieJar::new();
+        jar.private_mut(&Key::from(hmac_secret))
+            .add(Cookie::new("access_token", access_token));
+        let legacy_cookie = jar.get("access_token").unwrap().value();
+        server.set_cookie("access_token", legacy_cookie.to_owned());
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+        assert_ne!(server.cookie("access_token").unwrap(), legacy_cookie);
+    }
+
+    fn rotate_keyring(config: &mut Config) {
+        config.keyring.primary = "2".into();
+        config
+            .keyring
+            .secrets
+            .insert("2".into(), Secret::new("new secret ".repeat(8)));
+    }
+
     fn request() -> Request<Body> {
         Request::builder()
             .method("GET")
@@ -27,4 +229,11 @@ mod tests {
             .body(Body::empty())
             .unwrap()
     }
+
+    fn request_with_api_key(key: &str) -> Request<Body> {
+        let mut req = request();
+        req.headers_mut()
+            .insert(AUTHORIZATION, format!("Bearer {key}").parse().unwrap());
+        req
+    }
 }
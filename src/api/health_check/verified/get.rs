@@ -0,0 +1,48 @@
+use axum::http::StatusCode;
+
+use crate::extractors::VerifiedUser;
+
+#[tracing::instrument(
+    name = "Check verified user",
+    skip_all,
+    fields(user_id = %user.id)
+)]
+pub async fn handler(user: VerifiedUser) -> StatusCode {
+    StatusCode::OK
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, http::StatusCode};
+
+    use crate::{
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn fails_for_unverified_user(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn succeeds_for_verified_user(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        TestUser::enter_session(&mut server).await;
+        let res = TestUser::verify(&mut server).await;
+        assert!(res.status().is_success());
+        let res = server.call(request()).await;
+        assert!(res.status().is_success());
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri("/health_check/verified")
+            .body(Body::empty())
+            .unwrap()
+    }
+}
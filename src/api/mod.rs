@@ -1,15 +1,18 @@
-use macros::router;
+use macros::{router, segment_path};
 
 router! {
+    /admin,
     /auth,
     /health_check,
+    /oauth,
+    /well_known = ".well-known",
 }
 
 mod macros {
     macro_rules! router {
     (
         $($method:ident,)*
-        $(/$segment:ident,)*
+        $(/$segment:ident $(= $path:literal)?,)*
         $(/:$param:ident,)*
     ) => {
         $( mod $method; )*
@@ -19,11 +22,20 @@ mod macros {
         pub fn router() -> ::axum::Router<$crate::server::ServerState> {
             ::axum::Router::new()
             $( .route("/", ::axum::routing::$method($method::handler)) )*
-            $( .nest(&format!("/{}", stringify!($segment)), $segment::router()) )*
+            $( .nest($crate::api::segment_path!($segment $(= $path)?), $segment::router()) )*
             $( .nest(&format!("/:{}", stringify!($param)), $param::router()) )*
         }
     };
     }
 
-    pub(super) use router;
+    macro_rules! segment_path {
+        ($segment:ident) => {
+            concat!("/", stringify!($segment))
+        };
+        ($segment:ident = $path:literal) => {
+            concat!("/", $path)
+        };
+    }
+
+    pub(super) use {router, segment_path};
 }
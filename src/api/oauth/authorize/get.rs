@@ -0,0 +1,277 @@
+use axum::{
+    extract::{Query, RawQuery, State},
+    http::header::{CACHE_CONTROL, CONTENT_SECURITY_POLICY, X_FRAME_OPTIONS},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use reqwest::Url;
+use tracing::{field::display, Span};
+
+use crate::{
+    api::oauth::{authorize::AuthorizationRequest, ensure_enabled, issuer},
+    config::auth,
+    database::{oauth_client::OauthClient, oauth_consent},
+    error::Error,
+    extractors::User,
+    telemetry, Pool,
+};
+
+/// Starts the authorization code flow, which browsers navigate to.
+/// Users who are not logged in are sent to the login page first,
+/// clients the user has already consented to get the code right away,
+/// and otherwise the consent screen posts the user's decision back
+/// along with the same parameters.
+#[tracing::instrument(
+    name = "Authorize OAuth client",
+    skip_all,
+    fields(client_id = %request.client_id, user_id = tracing::field::Empty)
+)]
+pub async fn handler(
+    user: Result<User, Error>,
+    RawQuery(query): RawQuery,
+    State(base_url): State<Url>,
+    State(pool): State<Pool>,
+    State(auth_config): State<auth::Config>,
+    Query(request): Query<AuthorizationRequest>,
+) -> crate::Result<Response> {
+    ensure_enabled(&auth_config)?;
+    let client = request.client(&pool).await?;
+    if let Some(error) = request.error() {
+        return Ok(request.redirect(&[("error", error)])?.into_response());
+    }
+    let user = match user {
+        Ok(user) => user,
+        Err(Error::NoAccessToken | Error::InvalidAccessToken) => {
+            let login_url = &auth_config.openid_connect.login_url;
+            let return_to = format!(
+                "{}/oauth/authorize?{}",
+                issuer(&base_url),
+                query.unwrap_or_default()
+            );
+            let mut login_url = login_url.clone();
+            login_url
+                .query_pairs_mut()
+                .append_pair("return_to", &return_to);
+            return Ok(Redirect::to(login_url.as_str()).into_response());
+        }
+        Err(e) => return Err(e),
+    };
+    Span::current().record("user_id", &display(user.id));
+    // consent can only be given by the user in person, not with an API key
+    let Some(session_id) = user.credential.session_id() else {
+        return Err(Error::Forbidden).map_err(telemetry::warn);
+    };
+    if oauth_consent::covers(user.id, client.id, &request.scopes(), &pool)
+        .await?
+    {
+        let redirect = request
+            .issue_code(
+                &client,
+                user.id,
+                session_id,
+                auth_config.openid_connect.authorization_code_ttl,
+                &pool,
+            )
+            .await?;
+        return Ok(redirect.into_response());
+    }
+    let page = consent_page(&client, &request, &base_url);
+    let headers = [
+        (CACHE_CONTROL, "no-store"),
+        (X_FRAME_OPTIONS, "DENY"),
+        (
+            CONTENT_SECURITY_POLICY,
+            "default-src 'none'; style-src 'unsafe-inline'; \
+             frame-ancestors 'none'",
+        ),
+    ];
+    Ok((headers, page).into_response())
+}
+
+/// Renders what the client asks the user to share.
+/// Framing is denied so that the buttons cannot be clickjacked.
+fn consent_page(
+    client: &OauthClient,
+    request: &AuthorizationRequest,
+    base_url: &Url,
+) -> Html<String> {
+    let scopes = request
+        .scopes()
+        .iter()
+        .map(|scope| {
+            let description = match scope.as_str() {
+                "openid" => "Sign you in with your account",
+                "profile" => "See your name",
+                "email" => "See your email address",
+                _ => scope,
+            };
+            format!("      <li>{}</li>\n", escape(description))
+        })
+        .collect::<String>();
+    let fields = request
+        .params()
+        .into_iter()
+        .map(|(name, value)| {
+            format!(
+                "      <input type=\"hidden\" name=\"{name}\" value=\"{}\">\n",
+                escape(value)
+            )
+        })
+        .collect::<String>();
+    // request parameters go in last, so they cannot inject placeholders
+    let page = include_str!("consent.html")
+        .replace("{action}", &format!("{}/oauth/authorize", issuer(base_url)))
+        .replace("{scopes}\n", &scopes)
+        .replace("{client_name}", &escape(&client.name))
+        .replace("{fields}\n", &fields);
+    Html(page)
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        .fold(String::with_capacity(text.len()), |mut s, c| {
+            match c {
+                '&' => s.push_str("&amp;"),
+                '<' => s.push_str("&lt;"),
+                '>' => s.push_str("&gt;"),
+                '"' => s.push_str("&quot;"),
+                '\'' => s.push_str("&#39;"),
+                c => s.push(c),
+            }
+            s
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::LOCATION, Request, StatusCode},
+    };
+    use reqwest::Url;
+
+    use crate::{
+        test_helpers::{read_body, TestOauthClient, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn asks_for_consent_once(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let client = TestOauthClient::register(&pool).await;
+        let res = server.call(request(&client.authorization_query())).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-frame-options"], "DENY");
+        let page = read_body(res).await;
+        assert!(page.contains("<h1>Authorize app</h1>"));
+        assert!(page.contains("<li>See your email address</li>"));
+        assert!(page
+            .contains(r#"<input type="hidden" name="nonce" value="nonce">"#));
+        client.authorize(&mut server).await;
+        let res = server.call(request(&client.authorization_query())).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let location = res.headers()[LOCATION].to_str().unwrap();
+        assert!(location.starts_with(&client.redirect_uri));
+        assert!(location.contains("code="));
+        assert!(location.ends_with("&state=state"));
+    }
+
+    #[sqlx::test]
+    async fn escapes_request_on_consent_page(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let client = TestOauthClient::register(&pool).await;
+        let query = client
+            .authorization_query()
+            .replace("state=state", "state=%22%3E%3Cscript%3E");
+        let res = server.call(request(&query)).await;
+        let page = read_body(res).await;
+        assert!(!page.contains("<script>"));
+        assert!(page.contains(r#"value="&quot;&gt;&lt;script&gt;""#));
+    }
+
+    #[sqlx::test]
+    async fn redirects_to_login_without_session(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        let client = TestOauthClient::register(&pool).await;
+        let query = client.authorization_query();
+        let res = server.call(request(&query)).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let location = res.headers()[LOCATION].to_str().unwrap();
+        let location = Url::parse(location).unwrap();
+        assert_eq!(location.path(), "/login");
+        let (_, return_to) = location.query_pairs().next().unwrap();
+        assert_eq!(
+            return_to,
+            format!("http://localhost:8080/oauth/authorize?{query}")
+        );
+    }
+
+    #[sqlx::test]
+    async fn does_not_redirect_to_unregistered_uri(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let mut client = TestOauthClient::register(&pool).await;
+        client.redirect_uri = "https://attacker.example.com/callback".into();
+        let res = server.call(request(&client.authorization_query())).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn redirects_with_error_without_code_challenge(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let client = TestOauthClient::register(&pool).await;
+        let query = client.authorization_query().replace(
+            "code_challenge_method=S256",
+            "code_challenge_method=plain",
+        );
+        let res = server.call(request(&query)).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let location = res.headers()[LOCATION].to_str().unwrap();
+        assert_eq!(
+            location,
+            format!(
+                "{}?error=invalid_request&state=state",
+                client.redirect_uri
+            )
+        );
+    }
+
+    #[sqlx::test]
+    async fn redirects_with_error_on_overlong_code_challenge(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let client = TestOauthClient::register(&pool).await;
+        let query = client.authorization_query();
+        let params =
+            serde_urlencoded::from_str::<Vec<(String, String)>>(&query)
+                .unwrap();
+        let (_, code_challenge) = params
+            .iter()
+            .find(|(name, _)| name == "code_challenge")
+            .unwrap();
+        let query = query.replace(code_challenge, &"a".repeat(129));
+        let res = server.call(request(&query)).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let location = res.headers()[LOCATION].to_str().unwrap();
+        assert!(location.contains("error=invalid_request"));
+    }
+
+    #[sqlx::test]
+    async fn fails_when_disabled(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let client = TestOauthClient::register(&pool).await;
+        let res = server.call(request(&client.authorization_query())).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn request(query: &str) -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri(format!("/oauth/authorize?{query}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::response::Redirect;
+use reqwest::Url;
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    api::oauth::SUPPORTED_SCOPES,
+    database::{
+        oauth_client::{self, OauthClient},
+        oauth_token::{self, NewAuthorizationCode},
+    },
+    error::Error,
+    services::token::TokenService,
+    telemetry, Pool,
+};
+
+crate::api::router! {
+    get,
+    post,
+}
+
+/// Authorization request of the authorization code flow,
+/// which client applications have to protect with PKCE.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AuthorizationRequest {
+    response_type: String,
+    client_id: String,
+    redirect_uri: String,
+    #[serde(default)]
+    scope: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+}
+
+impl AuthorizationRequest {
+    /// Finds the client the redirect URI is registered for.
+    /// Failing that, the error is reported to the user instead,
+    /// as redirecting to an unknown URI could leak the code.
+    pub async fn client(&self, pool: &Pool) -> crate::Result<OauthClient> {
+        let client = oauth_client::find(&self.client_id, pool)
+            .await?
+            .filter(|client| client.redirect_uris.contains(&self.redirect_uri))
+            .ok_or(Error::InvalidRequest)
+            .map_err(telemetry::warn)?;
+        Ok(client)
+    }
+
+    pub fn scopes(&self) -> Vec<String> {
+        let mut scopes = self
+            .scope
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        scopes.sort_unstable();
+        scopes.dedup();
+        scopes
+    }
+
+    /// Error code from RFC 6749 to redirect back with, if any.
+    pub fn error(&self) -> Option<&'static str> {
+        if self.response_type != "code" {
+            return Some("unsupported_response_type");
+        }
+        let scopes = self.scopes();
+        if !scopes.iter().any(|scope| scope == "openid")
+            || !scopes
+                .iter()
+                .all(|scope| SUPPORTED_SCOPES.contains(&scope.as_str()))
+        {
+            return Some("invalid_scope");
+        }
+        // the plain method would expose the verifier along with the request
+        match (&self.code_challenge, self.code_challenge_method.as_deref()) {
+            (Some(challenge), Some("S256")) if is_code_challenge(challenge) => {
+                None
+            }
+            _ => Some("invalid_request"),
+        }
+    }
+
+    /// Parameters to carry over from the consent screen.
+    pub fn params(&self) -> Vec<(&'static str, &str)> {
+        let optional = [
+            ("state", &self.state),
+            ("nonce", &self.nonce),
+            ("code_challenge", &self.code_challenge),
+            ("code_challenge_method", &self.code_challenge_method),
+        ];
+        [
+            ("response_type", self.response_type.as_str()),
+            ("client_id", &self.client_id),
+            ("redirect_uri", &self.redirect_uri),
+            ("scope", &self.scope),
+        ]
+        .into_iter()
+        .chain(
+            optional
+                .into_iter()
+                .filter_map(|(name, value)| Some((name, value.as_deref()?))),
+        )
+        .collect()
+    }
+
+    /// Redirects back to the client with `params` and the state.
+    pub fn redirect(&self, params: &[(&str, &str)]) -> crate::Result<Redirect> {
+        let mut url = Url::parse(&self.redirect_uri)
+            .context("Failed to parse redirect URI")?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.extend_pairs(params);
+            if let Some(state) = &self.state {
+                query.append_pair("state", state);
+            }
+        }
+        Ok(Redirect::to(url.as_str()))
+    }
+
+    /// Issues an authorization code and redirects back with it.
+    pub async fn issue_code(
+        &self,
+        client: &OauthClient,
+        user_id: i64,
+        session_id: &Uuid,
+        ttl: Duration,
+        pool: &Pool,
+    ) -> crate::Result<Redirect> {
+        let code = TokenService::generate_authorization_code();
+        let scopes = self.scopes();
+        let new_code = NewAuthorizationCode {
+            oauth_client_id: client.id,
+            user_id,
+            session_id,
+            redirect_uri: &self.redirect_uri,
+            scopes: &scopes,
+            nonce: self.nonce.as_deref(),
+            code_challenge: self.code_challenge.as_deref().unwrap_or_default(),
+        };
+        oauth_token::insert_code(&code, &new_code, ttl, pool).await?;
+        self.redirect(&[("code", code.expose_secret())])
+    }
+}
+
+/// RFC 7636 allows 43 to 128 characters, of which S256 challenges,
+/// being base64url encoded digests, only use the URL-safe alphabet.
+fn is_code_challenge(challenge: &str) -> bool {
+    (43..=128).contains(&challenge.len())
+        && challenge
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
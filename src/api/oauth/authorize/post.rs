@@ -0,0 +1,114 @@
+use axum::{extract::State, response::Redirect};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::{
+    api::oauth::{authorize::AuthorizationRequest, ensure_enabled},
+    config::auth,
+    database::oauth_consent,
+    error::Error,
+    extractors::{validated::Form, User},
+    telemetry, Pool,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Consent {
+    Allow,
+    Deny,
+}
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    consent: Consent,
+    #[serde(flatten)]
+    request: AuthorizationRequest,
+}
+
+/// Records the user's decision on the consent screen
+/// and redirects back to the client either with a code or an error.
+#[tracing::instrument(
+    name = "Consent to OAuth client",
+    skip_all,
+    fields(
+        user_id = %user.id,
+        client_id = %payload.request.client_id,
+        consent = ?payload.consent
+    )
+)]
+pub async fn handler(
+    user: User,
+    State(pool): State<Pool>,
+    State(auth_config): State<auth::Config>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<Redirect> {
+    ensure_enabled(&auth_config)?;
+    let Some(session_id) = user.credential.session_id() else {
+        return Err(Error::Forbidden).map_err(telemetry::warn);
+    };
+    let request = payload.request;
+    let client = request.client(&pool).await?;
+    if let Some(error) = request.error() {
+        return request.redirect(&[("error", error)]);
+    }
+    if payload.consent == Consent::Deny {
+        return request.redirect(&[("error", "access_denied")]);
+    }
+    oauth_consent::grant(user.id, client.id, &request.scopes(), &pool).await?;
+    request
+        .issue_code(
+            &client,
+            user.id,
+            session_id,
+            auth_config.openid_connect.authorization_code_ttl,
+            &pool,
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE},
+            Request, StatusCode,
+        },
+    };
+
+    use crate::{
+        test_helpers::{TestOauthClient, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn redirects_with_error_on_denial(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let client = TestOauthClient::register(&pool).await;
+        let redirect_uri = client.consent(&mut server, "deny").await;
+        assert_eq!(
+            redirect_uri.query(),
+            Some("error=access_denied&state=state")
+        );
+    }
+
+    #[sqlx::test]
+    async fn fails_with_api_key(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let api_key = TestUser::create_api_key(&mut server, None).await;
+        server.clear_cookies();
+        let client = TestOauthClient::register(&pool).await;
+        let body = format!("{}&consent=allow", client.authorization_query());
+        let req = Request::builder()
+            .method("POST")
+            .uri("/oauth/authorize")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(AUTHORIZATION, format!("Bearer {api_key}"))
+            .body(Body::from(body))
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+}
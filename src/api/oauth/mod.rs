@@ -0,0 +1,56 @@
+use anyhow::Context;
+use reqwest::Url;
+
+use crate::{
+    config::auth, error::Error, services::token::UserInfo, telemetry, Pool,
+};
+
+crate::api::router! {
+    /authorize,
+    /token,
+    /userinfo,
+}
+
+/// OpenID Connect scopes that client applications can request.
+pub const SUPPORTED_SCOPES: [&str; 3] = ["openid", "profile", "email"];
+
+/// Issuer of ID tokens, which clients compare
+/// with the one in the discovery document verbatim.
+pub fn issuer(base_url: &Url) -> String {
+    base_url.as_str().trim_end_matches('/').to_owned()
+}
+
+/// Endpoints of the provider are only served once it is enabled.
+pub fn ensure_enabled(auth_config: &auth::Config) -> crate::Result<()> {
+    if !auth_config.openid_connect.enabled {
+        Err(Error::OpenIdConnectDisabled).map_err(telemetry::warn)?;
+    }
+    Ok(())
+}
+
+/// Claims about the user disclosed under `scopes`.
+pub async fn user_info(
+    user_id: i64,
+    scopes: &[String],
+    pool: &Pool,
+) -> anyhow::Result<UserInfo> {
+    let user = sqlx::query!(
+        r#"
+        select name, email, verified
+        from users
+        where id = $1;
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to select user")?;
+    let has_scope = |scope: &str| scopes.iter().any(|s| s == scope);
+    let email = user.email.filter(|_| has_scope("email"));
+    Ok(UserInfo {
+        sub: format!("user-{user_id}"),
+        name: has_scope("profile").then_some(user.name),
+        email_verified: email.is_some().then_some(user.verified),
+        email,
+    })
+}
@@ -0,0 +1,520 @@
+use axum::{
+    extract::State,
+    http::{
+        header::{AUTHORIZATION, CACHE_CONTROL},
+        HeaderMap,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{field::display, Span};
+use validator::Validate;
+
+use crate::{
+    api::oauth::{issuer, user_info},
+    config::auth,
+    database::{
+        begin_transaction, commit, oauth_client, oauth_token, service_account,
+    },
+    error::Error,
+    extractors::validated::Form,
+    services::token::TokenService,
+    telemetry, Pool,
+};
+
+#[derive(Clone, Debug, Deserialize, Validate)]
+pub struct Payload {
+    grant_type: String,
+    client_id: Option<String>,
+    client_secret: Option<Secret<String>>,
+    scope: Option<String>,
+    code: Option<Secret<String>>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<Secret<String>>,
+}
+
+/// Successful response as laid out in RFC 6749, hence in snake case.
+#[derive(Serialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    scope: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id_token: Option<String>,
+}
+
+/// Issues tokens through the OAuth 2.0 client credentials grant
+/// to service accounts, and through the authorization code grant
+/// to client applications signing users in with OpenID Connect.
+/// Clients authenticate either with HTTP Basic or in the request body.
+#[tracing::instrument(
+    name = "Issue OAuth token",
+    skip_all,
+    fields(
+        grant_type = %payload.grant_type,
+        client_id = tracing::field::Empty
+    )
+)]
+pub async fn handler(
+    headers: HeaderMap,
+    State(base_url): State<Url>,
+    State(pool): State<Pool>,
+    State(auth_config): State<auth::Config>,
+    State(token_service): State<TokenService>,
+    Form(payload): Form<Payload>,
+) -> crate::Result<Response> {
+    let grant_types = if auth_config.openid_connect.enabled {
+        &["client_credentials", "authorization_code"][..]
+    } else {
+        &["client_credentials"][..]
+    };
+    if !grant_types.contains(&payload.grant_type.as_str()) {
+        Err(Error::UnsupportedGrantType).map_err(telemetry::warn)?;
+    }
+    let (client_id, client_secret) = basic_credentials(&headers)
+        .map(|(client_id, client_secret)| (client_id, Some(client_secret)))
+        .or_else(|| {
+            Some((payload.client_id.clone()?, payload.client_secret.clone()))
+        })
+        .ok_or(Error::InvalidClient)
+        .map_err(telemetry::warn)?;
+    Span::current().record("client_id", &display(&client_id));
+    let response = if payload.grant_type == "client_credentials" {
+        let client_secret = client_secret
+            .ok_or(Error::InvalidClient)
+            .map_err(telemetry::warn)?;
+        issue_service_token(
+            &client_id,
+            &client_secret,
+            payload,
+            &pool,
+            token_service,
+        )
+        .await?
+    } else {
+        exchange_authorization_code(
+            &client_id,
+            client_secret.as_ref(),
+            payload,
+            &base_url,
+            &pool,
+            token_service,
+        )
+        .await?
+    };
+    Ok(([(CACHE_CONTROL, "no-store")], Json(response)).into_response())
+}
+
+async fn issue_service_token(
+    client_id: &str,
+    client_secret: &Secret<String>,
+    payload: Payload,
+    pool: &Pool,
+    token_service: TokenService,
+) -> crate::Result<TokenResponse> {
+    let service_account =
+        service_account::authenticate(client_id, client_secret, pool)
+            .await?
+            .ok_or(Error::InvalidClient)
+            .map_err(telemetry::warn)?;
+    let scopes = match payload.scope {
+        Some(scope) => {
+            let scopes = scope
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+            if !scopes
+                .iter()
+                .all(|scope| service_account.permissions.contains(scope))
+            {
+                Err(Error::InvalidScope).map_err(telemetry::warn)?;
+            }
+            scopes
+        }
+        None => service_account.permissions,
+    };
+    let expires_in = token_service.service_token_ttl().as_secs();
+    let (access_token, claims) =
+        telemetry::instrument_blocking_task(move || {
+            token_service.generate_service_token(
+                service_account.id,
+                service_account.client_id,
+                scopes,
+            )
+        })
+        .await??;
+    Ok(TokenResponse {
+        access_token: access_token.expose_secret().to_owned(),
+        token_type: "Bearer",
+        expires_in,
+        scope: claims.scopes.join(" "),
+        id_token: None,
+    })
+}
+
+/// Exchanges a code for an ID token along with an access token
+/// for the userinfo endpoint. The code verifier proves that the client
+/// started the flow, which is all public clients have to authenticate with.
+async fn exchange_authorization_code(
+    client_id: &str,
+    client_secret: Option<&Secret<String>>,
+    payload: Payload,
+    base_url: &Url,
+    pool: &Pool,
+    token_service: TokenService,
+) -> crate::Result<TokenResponse> {
+    let client = oauth_client::authenticate(client_id, client_secret, pool)
+        .await?
+        .ok_or(Error::InvalidClient)
+        .map_err(telemetry::warn)?;
+    let (Some(code), Some(redirect_uri), Some(code_verifier)) =
+        (payload.code, payload.redirect_uri, payload.code_verifier)
+    else {
+        return Err(Error::InvalidRequest).map_err(telemetry::warn);
+    };
+    // the code stays locked until the access token is stored,
+    // so a concurrent replay cannot miss the token it revokes
+    let mut transaction = begin_transaction(pool).await?;
+    let Some(authorization_code) =
+        oauth_token::consume_code(&code, &mut transaction).await?
+    else {
+        return Err(Error::InvalidGrant).map_err(telemetry::warn);
+    };
+    if authorization_code.replayed {
+        oauth_token::revoke_for_code(&code, &mut transaction).await?;
+        commit(transaction).await?;
+        return Err(Error::InvalidGrant).map_err(telemetry::warn);
+    }
+    if authorization_code.oauth_client_id != client.id
+        || authorization_code.redirect_uri != redirect_uri
+        || authorization_code.expires_at <= OffsetDateTime::now_utc()
+        || !TokenService::verify_code_challenge(
+            &code_verifier,
+            &authorization_code.code_challenge,
+        )
+    {
+        // the code is used up all the same
+        commit(transaction).await?;
+        return Err(Error::InvalidGrant).map_err(telemetry::warn);
+    }
+    let access_token = TokenService::generate_oauth_access_token();
+    let ttl = token_service.access_token_ttl();
+    oauth_token::insert_access_token(
+        &access_token,
+        &code,
+        &authorization_code,
+        ttl,
+        &mut transaction,
+    )
+    .await?;
+    commit(transaction).await?;
+    let code = authorization_code;
+    let user_info = user_info(code.user_id, &code.scopes, pool).await?;
+    let issuer = issuer(base_url);
+    let scope = code.scopes.join(" ");
+    let (id_token, _) = telemetry::instrument_blocking_task(move || {
+        token_service.generate_id_token(
+            issuer,
+            client.client_id,
+            code.nonce,
+            user_info,
+        )
+    })
+    .await??;
+    Ok(TokenResponse {
+        access_token: access_token.expose_secret().to_owned(),
+        token_type: "Bearer",
+        expires_in: ttl.as_secs(),
+        scope,
+        id_token: Some(id_token.expose_secret().to_owned()),
+    })
+}
+
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, Secret<String>)> {
+    let header = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, credentials) = header.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let credentials = STANDARD.decode(credentials.trim()).ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (client_id, client_secret) = credentials.split_once(':')?;
+    Some((client_id.to_owned(), Secret::new(client_secret.to_owned())))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE},
+            Request, StatusCode,
+        },
+    };
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use jsonwebtoken::{jwk::JwkSet, DecodingKey, Validation};
+    use secrecy::{ExposeSecret, Secret};
+
+    use crate::{
+        database::{oauth_client, service_account},
+        services::token::TokenService,
+        test_helpers::{read_json, TestOauthClient, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn issues_token_with_granted_scopes(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        let (client_id, client_secret) = create_service_account(&pool).await;
+        let body = [
+            ("grant_type", "client_credentials"),
+            ("client_id", &client_id),
+            ("client_secret", client_secret.expose_secret()),
+        ];
+        let res = server.call(request(&body)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["cache-control"], "no-store");
+        let body = read_json::<serde_json::Value>(res).await;
+        assert_eq!(body["token_type"], "Bearer");
+        assert_eq!(body["expires_in"], 300);
+        assert_eq!(body["scope"], "users:read");
+        assert!(body["access_token"].is_string());
+    }
+
+    #[sqlx::test]
+    async fn accepts_basic_credentials(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        let (client_id, client_secret) = create_service_account(&pool).await;
+        let credentials =
+            format!("{client_id}:{}", client_secret.expose_secret());
+        let mut req = request(&[("grant_type", "client_credentials")]);
+        req.headers_mut().insert(
+            AUTHORIZATION,
+            format!("Basic {}", STANDARD.encode(credentials))
+                .parse()
+                .unwrap(),
+        );
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn fails_on_invalid_client_secret(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        let (client_id, _) = create_service_account(&pool).await;
+        let body = [
+            ("grant_type", "client_credentials"),
+            ("client_id", &client_id),
+            ("client_secret", "invalid"),
+        ];
+        let res = server.call(request(&body)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert_eq!(body["error"], "invalid_client");
+    }
+
+    #[sqlx::test]
+    async fn fails_on_unsupported_grant_type(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.call(request(&[("grant_type", "password")])).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert_eq!(body["error"], "unsupported_grant_type");
+    }
+
+    #[sqlx::test]
+    async fn fails_on_scope_not_granted(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        let (client_id, client_secret) = create_service_account(&pool).await;
+        let body = [
+            ("grant_type", "client_credentials"),
+            ("client_id", &client_id),
+            ("client_secret", client_secret.expose_secret()),
+            ("scope", "users:read users:write"),
+        ];
+        let res = server.call(request(&body)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert_eq!(body["error"], "invalid_scope");
+    }
+
+    #[sqlx::test]
+    async fn token_is_only_accepted_where_services_are(pool: Pool) {
+        let mut server = TestServer::new(pool.clone()).await;
+        let (client_id, client_secret) = create_service_account(&pool).await;
+        let body = [
+            ("grant_type", "client_credentials"),
+            ("client_id", &client_id),
+            ("client_secret", client_secret.expose_secret()),
+        ];
+        let res = server.call(request(&body)).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["access_token"].as_str().unwrap();
+        let res = server
+            .call(authorized("/admin/users/1/roles", access_token))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server
+            .call(authorized("/health_check/protected", access_token))
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn exchanges_code_for_id_token(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let client = TestOauthClient::register(&pool).await;
+        let code = client.authorize(&mut server).await;
+        let res = client.exchange_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert_eq!(body["token_type"], "Bearer");
+        assert_eq!(body["scope"], "email openid profile");
+        assert!(body["access_token"].is_string());
+        let id_token = body["id_token"].as_str().unwrap();
+        let req = Request::builder()
+            .method("GET")
+            .uri("/.well-known/jwks.json")
+            .body(Body::empty())
+            .unwrap();
+        let jwks = read_json::<JwkSet>(server.call(req).await).await;
+        let kid = jsonwebtoken::decode_header(id_token).unwrap().kid.unwrap();
+        let jwk = jwks.find(&kid).unwrap();
+        let mut validation = Validation::new(jwk.common.algorithm.unwrap());
+        validation.set_audience(&[&client.client_id]);
+        let claims = jsonwebtoken::decode::<serde_json::Value>(
+            id_token,
+            &DecodingKey::from_jwk(jwk).unwrap(),
+            &validation,
+        )
+        .unwrap()
+        .claims;
+        assert_eq!(claims["iss"], "http://localhost:8080");
+        assert_eq!(claims["nonce"], "nonce");
+        assert_eq!(claims["email"], TestUser::email());
+        assert_eq!(claims["name"], TestUser::name());
+    }
+
+    #[sqlx::test]
+    async fn code_is_single_use(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let client = TestOauthClient::register(&pool).await;
+        let code = client.authorize(&mut server).await;
+        let res = client.exchange_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = client.exchange_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert_eq!(body["error"], "invalid_grant");
+    }
+
+    #[sqlx::test]
+    async fn replayed_code_revokes_access_token(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let client = TestOauthClient::register(&pool).await;
+        let code = client.authorize(&mut server).await;
+        let res = client.exchange_code(&mut server, &code).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["access_token"].as_str().unwrap();
+        let res = client.exchange_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        server.clear_cookies();
+        let res = server
+            .call(authorized("/oauth/userinfo", access_token))
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn public_client_fails_with_secret(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let mut client = TestOauthClient::register(&pool).await;
+        client.client_id = "public".into();
+        oauth_client::insert(
+            "public",
+            &client.client_id,
+            None,
+            std::slice::from_ref(&client.redirect_uri),
+            &pool,
+        )
+        .await
+        .unwrap();
+        let code = client.authorize(&mut server).await;
+        let res = client.exchange_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert_eq!(body["error"], "invalid_client");
+    }
+
+    #[sqlx::test]
+    async fn fails_on_code_grant_when_disabled(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let body = [
+            ("grant_type", "authorization_code"),
+            ("client_id", "app"),
+            ("code", "code"),
+        ];
+        let res = server.call(request(&body)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert_eq!(body["error"], "unsupported_grant_type");
+    }
+
+    #[sqlx::test]
+    async fn fails_on_wrong_code_verifier(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let mut client = TestOauthClient::register(&pool).await;
+        let code = client.authorize(&mut server).await;
+        client.code_verifier = "b".repeat(43);
+        let res = client.exchange_code(&mut server, &code).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert_eq!(body["error"], "invalid_grant");
+    }
+
+    async fn create_service_account(pool: &Pool) -> (String, Secret<String>) {
+        let (client_id, client_secret) =
+            TokenService::generate_client_credentials();
+        service_account::insert(
+            "service",
+            &client_id,
+            &client_secret,
+            &["users:read".into()],
+            pool,
+        )
+        .await
+        .unwrap();
+        (client_id, client_secret)
+    }
+
+    fn request(body: &[(&str, &str)]) -> Request<Body> {
+        let body = serde_urlencoded::to_string(body).unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/oauth/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn authorized(uri: &str, access_token: &str) -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri(uri)
+            .header(AUTHORIZATION, format!("Bearer {access_token}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
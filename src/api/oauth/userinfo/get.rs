@@ -0,0 +1,124 @@
+use axum::{extract::State, http::HeaderMap, Json};
+
+use crate::{
+    api::oauth::{ensure_enabled, user_info},
+    config::auth,
+    database::oauth_token,
+    error::Error,
+    extractors::bearer_token,
+    services::token::UserInfo,
+    telemetry, Pool,
+};
+
+/// Returns the claims about the user that the client was granted,
+/// accepting only access tokens issued through the authorization code grant.
+#[tracing::instrument(name = "Get user info", skip_all)]
+pub async fn handler(
+    headers: HeaderMap,
+    State(pool): State<Pool>,
+    State(auth_config): State<auth::Config>,
+) -> crate::Result<Json<UserInfo>> {
+    ensure_enabled(&auth_config)?;
+    let access_token = bearer_token(&headers).ok_or(Error::NoAccessToken)?;
+    let access_token =
+        oauth_token::authenticate_access_token(&access_token, &pool)
+            .await?
+            .ok_or(Error::InvalidAccessToken)
+            .map_err(telemetry::warn)?;
+    let user_info =
+        user_info(access_token.user_id, &access_token.scopes, &pool).await?;
+    Ok(Json(user_info))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestOauthClient, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn returns_claims_of_granted_scopes(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let client = TestOauthClient::register(&pool).await;
+        let code = client.authorize(&mut server).await;
+        let res = client.exchange_code(&mut server, &code).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["access_token"].as_str().unwrap();
+        server.clear_cookies();
+        let res = server.call(request(access_token)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert!(body["sub"].as_str().unwrap().starts_with("user-"));
+        assert_eq!(body["name"], TestUser::name());
+        assert_eq!(body["email"], TestUser::email());
+        assert_eq!(body["email_verified"], false);
+    }
+
+    #[sqlx::test]
+    async fn fails_with_user_access_token(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login_for_tokens(&mut server).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["accessToken"].as_str().unwrap();
+        let res = server.call(request(access_token)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn fails_after_logout(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let client = TestOauthClient::register(&pool).await;
+        let code = client.authorize(&mut server).await;
+        let res = client.exchange_code(&mut server, &code).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["access_token"].as_str().unwrap();
+        let req = Request::builder()
+            .method("POST")
+            .uri("/auth/logout")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server.call(request(access_token)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn fails_after_logout_everywhere(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool.clone()).await;
+        TestUser::enter_session(&mut server).await;
+        let client = TestOauthClient::register(&pool).await;
+        let code = client.authorize(&mut server).await;
+        let res = client.exchange_code(&mut server, &code).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["access_token"].as_str().unwrap();
+        let req = Request::builder()
+            .method("POST")
+            .uri("/auth/logout_all")
+            .body(Body::empty())
+            .unwrap();
+        let res = server.call(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = server.call(request(access_token)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    fn request(access_token: &str) -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri("/oauth/userinfo")
+            .header(AUTHORIZATION, format!("Bearer {access_token}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
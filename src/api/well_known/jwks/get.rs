@@ -0,0 +1,66 @@
+use axum::{extract::State, Json};
+use jsonwebtoken::jwk::JwkSet;
+
+use crate::services::token::TokenService;
+
+pub async fn handler(
+    State(token_service): State<TokenService>,
+) -> Json<JwkSet> {
+    Json(token_service.jwks())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use jsonwebtoken::{jwk::JwkSet, DecodingKey, Validation};
+
+    use crate::{
+        test_helpers::{read_json, TestServer, TestUser},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn publishes_key_that_verifies_access_tokens(pool: Pool) {
+        let mut server = TestServer::with_eddsa_signing_key(pool).await;
+        let res = TestUser::signup(&mut server).await;
+        assert!(res.status().is_success());
+        let res = TestUser::login_for_tokens(&mut server).await;
+        let body = read_json::<serde_json::Value>(res).await;
+        let access_token = body["accessToken"].as_str().unwrap();
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let jwks = read_json::<JwkSet>(res).await;
+        let [jwk] = jwks.keys.as_slice() else {
+            panic!("expected a single key");
+        };
+        assert_eq!(jwk.common.key_id.as_deref(), Some("1"));
+        let decoding_key = DecodingKey::from_jwk(jwk).unwrap();
+        let validation = Validation::new(jwk.common.algorithm.unwrap());
+        let token = jsonwebtoken::decode::<serde_json::Value>(
+            access_token,
+            &decoding_key,
+            &validation,
+        );
+        assert!(token.is_ok());
+    }
+
+    #[sqlx::test]
+    async fn keeps_hmac_secret_private(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let jwks = read_json::<JwkSet>(res).await;
+        assert!(jwks.keys.is_empty());
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri("/.well-known/jwks.json")
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -0,0 +1,4 @@
+crate::api::router! {
+    /jwks = "jwks.json",
+    /openid_configuration = "openid-configuration",
+}
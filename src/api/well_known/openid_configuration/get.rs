@@ -0,0 +1,116 @@
+use axum::{extract::State, Json};
+use jsonwebtoken::Algorithm;
+use reqwest::Url;
+use serde::Serialize;
+
+use crate::{
+    api::oauth::{ensure_enabled, issuer, SUPPORTED_SCOPES},
+    config::auth,
+    services::token::TokenService,
+};
+
+/// OpenID Connect discovery document, laid out in snake case by the spec.
+#[derive(Serialize)]
+pub struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+    jwks_uri: String,
+    response_types_supported: [&'static str; 1],
+    grant_types_supported: [&'static str; 2],
+    subject_types_supported: [&'static str; 1],
+    id_token_signing_alg_values_supported: [Algorithm; 1],
+    scopes_supported: [&'static str; 3],
+    claims_supported: [&'static str; 8],
+    token_endpoint_auth_methods_supported: [&'static str; 3],
+    code_challenge_methods_supported: [&'static str; 1],
+}
+
+pub async fn handler(
+    State(base_url): State<Url>,
+    State(auth_config): State<auth::Config>,
+    State(token_service): State<TokenService>,
+) -> crate::Result<Json<ProviderMetadata>> {
+    ensure_enabled(&auth_config)?;
+    let issuer = issuer(&base_url);
+    Ok(Json(ProviderMetadata {
+        authorization_endpoint: format!("{issuer}/oauth/authorize"),
+        token_endpoint: format!("{issuer}/oauth/token"),
+        userinfo_endpoint: format!("{issuer}/oauth/userinfo"),
+        jwks_uri: format!("{issuer}/.well-known/jwks.json"),
+        issuer,
+        response_types_supported: ["code"],
+        grant_types_supported: ["authorization_code", "client_credentials"],
+        subject_types_supported: ["public"],
+        id_token_signing_alg_values_supported: [
+            token_service.signing_algorithm()
+        ],
+        scopes_supported: SUPPORTED_SCOPES,
+        claims_supported: [
+            "iss",
+            "sub",
+            "aud",
+            "iat",
+            "exp",
+            "name",
+            "email",
+            "email_verified",
+        ],
+        token_endpoint_auth_methods_supported: [
+            "client_secret_basic",
+            "client_secret_post",
+            "none",
+        ],
+        code_challenge_methods_supported: ["S256"],
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    use crate::{
+        test_helpers::{read_json, TestServer},
+        Pool,
+    };
+
+    #[sqlx::test]
+    async fn points_to_endpoints_under_issuer(pool: Pool) {
+        let mut server = TestServer::with_openid_connect(pool).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = read_json::<serde_json::Value>(res).await;
+        assert_eq!(body["issuer"], "http://localhost:8080");
+        assert_eq!(
+            body["authorization_endpoint"],
+            "http://localhost:8080/oauth/authorize"
+        );
+        assert_eq!(
+            body["jwks_uri"],
+            "http://localhost:8080/.well-known/jwks.json"
+        );
+        assert_eq!(
+            body["id_token_signing_alg_values_supported"],
+            serde_json::json!(["EdDSA"])
+        );
+    }
+
+    #[sqlx::test]
+    async fn is_not_served_when_disabled(pool: Pool) {
+        let mut server = TestServer::new(pool).await;
+        let res = server.call(request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .method("GET")
+            .uri("/.well-known/openid-configuration")
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -1,36 +1,313 @@
-use std::time::Duration;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use oauth2::url::Host;
+use anyhow::Context;
+use oauth2::url::{Host, Url};
+use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
 
-use crate::services::{cookie::CookieService, token::TokenService};
+use crate::{
+    services::{
+        cookie::CookieService,
+        denylist::AccessTokenDenylist,
+        key_derivation::{derive_key, Purpose},
+        keyring::{Keyring, LEGACY_KEY_ID},
+        signing_key::SigningKey,
+        token::TokenService,
+        totp::TotpService,
+        webauthn::WebauthnService,
+    },
+    Pool,
+};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
     pub issuer: Host<String>,
     pub audience: Host<String>,
+    pub jwt_signing_key: JwtSigningKey,
     pub access_token_ttl: Duration,
     pub refresh_token_ttl: Duration,
+    pub service_token_ttl: Duration,
+    pub access_token_leeway: Duration,
+    pub denylist_sync_interval: Duration,
+    pub require_verified_email: bool,
+    pub verification_mode: VerificationMode,
+    pub token_transport: TokenTransport,
+    pub password_reset_url: Url,
+    pub password_reset_token_ttl: Duration,
+    pub verification_token_ttl: Duration,
+    pub verification_email_cooldown: Duration,
+    pub magic_link_ttl: Duration,
+    pub email_code_ttl: Duration,
+    pub email_code_cooldown: Duration,
+    pub email_code_max_failed_attempts: i32,
+    pub totp_issuer: String,
+    pub two_factor_challenge_ttl: Duration,
+    pub webauthn_rp_name: String,
+    pub webauthn_challenge_ttl: Duration,
+    pub lockout: Lockout,
+    pub openid_connect: OpenIdConnect,
+}
+
+/// How access tokens are signed. HS256 uses keys derived from the secrets
+/// of the keyring, so only asymmetric keys let other services verify them.
+/// Those are rotated on their own, with PEM files keyed by their ids.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "algorithm")]
+pub enum JwtSigningKey {
+    #[serde(rename = "HS256")]
+    Hs256,
+    #[serde(rename = "RS256")]
+    Rs256 {
+        primary: String,
+        private_key_paths: HashMap<String, PathBuf>,
+    },
+    #[serde(rename = "EdDSA")]
+    EdDsa {
+        primary: String,
+        private_key_paths: HashMap<String, PathBuf>,
+    },
+}
+
+/// Exponential back-off for failed password attempts.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Lockout {
+    pub account_threshold: i32,
+    pub ip_threshold: i32,
+    pub base_duration: Duration,
+    pub max_duration: Duration,
+    pub reset_after: Duration,
+}
+
+/// Acting as an OpenID Connect provider for other applications.
+/// Browsers reach the authorization endpoint by navigating there,
+/// so unauthenticated users are sent to `login_url`, which is expected
+/// to bring them back to the `return_to` query parameter once logged in.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OpenIdConnect {
+    pub enabled: bool,
+    pub login_url: Url,
+    pub authorization_code_ttl: Duration,
+}
+
+/// How new users prove the ownership of their email.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VerificationMode {
+    Link,
+    Code,
+}
+
+/// Where clients are allowed to keep their tokens:
+/// private cookies for browsers and the `Authorization` header
+/// with tokens returned in the response body for everything else.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenTransport {
+    Cookie,
+    Bearer,
+    Both,
+}
+
+impl TokenTransport {
+    pub fn accepts_cookie(self) -> bool {
+        matches!(self, Self::Cookie | Self::Both)
+    }
+
+    pub fn accepts_bearer(self) -> bool {
+        matches!(self, Self::Bearer | Self::Both)
+    }
+}
+
+impl Lockout {
+    /// Returns for how long to lock out after `failures` failed attempts:
+    /// not at all below `threshold`, then `base_duration`
+    /// doubling with every further failure up to `max_duration`.
+    pub fn duration(&self, failures: i32, threshold: i32) -> Option<Duration> {
+        let exponent = failures.checked_sub(threshold)?;
+        let exponent = u32::try_from(exponent).ok()?.min(31);
+        let duration = self
+            .base_duration
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_duration);
+        Some(duration.min(self.max_duration))
+    }
+}
+
+impl JwtSigningKey {
+    pub fn load(
+        &self,
+        secrets: &Keyring<Secret<String>>,
+    ) -> anyhow::Result<Keyring<SigningKey>> {
+        match self {
+            Self::Hs256 => secrets.try_map(|id, secret| {
+                let secret = secret.expose_secret().as_bytes();
+                // tokens without a key id were signed with the secret itself
+                if id == LEGACY_KEY_ID {
+                    return Ok(SigningKey::hs256(secret));
+                }
+                let key = derive_key(secret, Purpose::JwtSigning);
+                Ok(SigningKey::hs256(key.expose_secret()))
+            }),
+            Self::Rs256 {
+                primary,
+                private_key_paths,
+            } => Keyring::new(primary.clone(), private_key_paths.clone())?
+                .try_map(|_, path| {
+                    SigningKey::rs256_from_pem(&read_pem(path)?)
+                }),
+            Self::EdDsa {
+                primary,
+                private_key_paths,
+            } => Keyring::new(primary.clone(), private_key_paths.clone())?
+                .try_map(|_, path| {
+                    SigningKey::eddsa_from_pem(&read_pem(path)?)
+                }),
+        }
+    }
 }
 
 impl Config {
-    pub fn token_service(self, secret: &[u8]) -> TokenService {
-        TokenService::new(
+    /// Clients can only verify ID tokens with the public keys
+    /// served at /.well-known/jwks.json, while HS256 keys are never shared,
+    /// and browsers only carry the session along as a cookie.
+    pub fn validate_openid_connect(&self) -> anyhow::Result<()> {
+        if !self.openid_connect.enabled {
+            return Ok(());
+        }
+        anyhow::ensure!(
+            !matches!(self.jwt_signing_key, JwtSigningKey::Hs256),
+            "OpenID Connect requires an RS256 or EdDSA JWT signing key"
+        );
+        anyhow::ensure!(
+            self.token_transport.accepts_cookie(),
+            "OpenID Connect requires the cookie token transport"
+        );
+        Ok(())
+    }
+
+    pub fn token_service(
+        self,
+        secrets: &Keyring<Secret<String>>,
+    ) -> anyhow::Result<TokenService> {
+        let signing_keys = self.jwt_signing_key.load(secrets)?;
+        Ok(TokenService::new(
             self.issuer,
             self.audience,
             self.access_token_ttl,
-            secret,
-        )
+            self.refresh_token_ttl,
+            self.service_token_ttl,
+            self.access_token_leeway,
+            signing_keys,
+        ))
     }
 
     pub fn cookie_service(
         &self,
-        secret: &[u8],
+        secrets: &Keyring<Secret<String>>,
+        accept_legacy_keys: bool,
     ) -> anyhow::Result<CookieService> {
         CookieService::new(
-            secret,
+            secrets,
             self.access_token_ttl,
             self.refresh_token_ttl,
+            accept_legacy_keys,
         )
     }
+
+    pub fn access_token_denylist(
+        &self,
+        pool: Pool,
+    ) -> anyhow::Result<AccessTokenDenylist> {
+        anyhow::ensure!(
+            !self.denylist_sync_interval.is_zero(),
+            "Access token denylist sync interval must not be zero"
+        );
+        Ok(AccessTokenDenylist::new(
+            pool,
+            self.denylist_sync_interval,
+            self.access_token_leeway,
+        ))
+    }
+
+    pub fn totp_service(
+        &self,
+        secret: &[u8],
+        accept_legacy_keys: bool,
+    ) -> TotpService {
+        TotpService::new(self.totp_issuer.clone(), secret, accept_legacy_keys)
+    }
+
+    pub fn webauthn_service(
+        &self,
+        base_url: &Url,
+    ) -> anyhow::Result<WebauthnService> {
+        WebauthnService::new(
+            base_url,
+            self.webauthn_rp_name.clone(),
+            self.webauthn_challenge_ttl,
+        )
+    }
+}
+
+fn read_pem(path: &Path) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::{JwtSigningKey, Lockout, TokenTransport};
+
+    #[test]
+    fn lockout_doubles_up_to_max_duration() {
+        let lockout = Lockout {
+            account_threshold: 3,
+            ip_threshold: 10,
+            base_duration: Duration::from_secs(30),
+            max_duration: Duration::from_secs(100),
+            reset_after: Duration::from_secs(3600),
+        };
+        let durations = (1..=7)
+            .map(|failures| lockout.duration(failures, 3))
+            .map(|d| d.map(|d| d.as_secs()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            durations,
+            [
+                None,
+                None,
+                Some(30),
+                Some(60),
+                Some(100),
+                Some(100),
+                Some(100)
+            ]
+        );
+        assert_eq!(
+            lockout.duration(i32::MAX, 3),
+            Some(Duration::from_secs(100))
+        );
+    }
+
+    #[test]
+    fn openid_connect_requires_public_key_and_cookies() {
+        let mut config = crate::config::Config::new().unwrap().auth;
+        config.openid_connect.enabled = true;
+        config.jwt_signing_key = JwtSigningKey::Hs256;
+        assert!(config.validate_openid_connect().is_err());
+        config.jwt_signing_key = JwtSigningKey::EdDsa {
+            primary: "1".into(),
+            private_key_paths: HashMap::new(),
+        };
+        config.token_transport = TokenTransport::Both;
+        assert!(config.validate_openid_connect().is_ok());
+        config.token_transport = TokenTransport::Bearer;
+        assert!(config.validate_openid_connect().is_err());
+    }
 }
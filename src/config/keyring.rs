@@ -0,0 +1,35 @@
+use std::collections::HashMap;
+
+use secrecy::Secret;
+use serde::Deserialize;
+
+use crate::services::keyring::{Keyring, LEGACY_KEY_ID};
+
+/// Secrets that access tokens and cookies are protected with.
+/// Every secret has to be at least 64 bytes long.
+/// While `accept_legacy_keys` is set, `hmac_secret` is added as a retired key,
+/// so that cookies and tokens issued before the keyring keep working.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    pub primary: String,
+    pub secrets: HashMap<String, Secret<String>>,
+}
+
+impl Config {
+    pub fn keyring(
+        &self,
+        hmac_secret: &Secret<String>,
+        accept_legacy_keys: bool,
+    ) -> anyhow::Result<Keyring<Secret<String>>> {
+        anyhow::ensure!(
+            !self.secrets.contains_key(LEGACY_KEY_ID),
+            "Keyring id `{LEGACY_KEY_ID}` is reserved for hmac_secret"
+        );
+        let mut keyring =
+            Keyring::new(self.primary.clone(), self.secrets.clone())?;
+        if accept_legacy_keys {
+            keyring.insert(LEGACY_KEY_ID.into(), hmac_secret.clone());
+        }
+        Ok(keyring)
+    }
+}
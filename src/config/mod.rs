@@ -1,8 +1,10 @@
-mod auth;
+pub mod auth;
 mod database;
 mod email_client;
+mod keyring;
 mod oauth;
 mod password_hasher;
+pub mod rate_limit;
 mod server;
 
 use serde::Deserialize;
@@ -16,6 +18,8 @@ pub struct Config {
     pub database: database::Config,
     pub email_client: email_client::Config,
     pub password_hasher: password_hasher::Config,
+    pub keyring: keyring::Config,
+    pub rate_limit: rate_limit::Config,
 }
 
 impl Config {
@@ -10,7 +10,17 @@ pub struct Config {
 }
 
 impl Config {
-    pub fn hasher(self, secret: &[u8]) -> anyhow::Result<PasswordHasher> {
-        PasswordHasher::new(secret, self.m_cost, self.t_cost, self.p_cost)
+    pub fn hasher(
+        self,
+        secret: &[u8],
+        accept_legacy_keys: bool,
+    ) -> anyhow::Result<PasswordHasher> {
+        PasswordHasher::new(
+            secret,
+            self.m_cost,
+            self.t_cost,
+            self.p_cost,
+            accept_legacy_keys,
+        )
     }
 }
@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::{
+    config::auth::TokenTransport,
+    extractors::TrustedProxies,
+    middleware::rate_limit::{InMemoryStore, RateLimitLayer},
+    services::{cookie::CookieService, token::TokenService},
+};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    pub rules: Vec<Rule>,
+}
+
+/// A token bucket shared by every request whose path starts with `prefix`.
+/// Holds up to `capacity` requests and regains one every `refill_interval`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rule {
+    pub prefix: String,
+    pub key: Key,
+    pub capacity: u32,
+    pub refill_interval: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Key {
+    Ip,
+    /// Falls back to the client IP for anonymous requests.
+    User,
+}
+
+impl Config {
+    /// Empty buckets would reject every request and buckets
+    /// that never refill would reject every request after the first ones.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for rule in &self.rules {
+            anyhow::ensure!(
+                rule.capacity > 0,
+                "Rate limit capacity for `{}` must not be zero",
+                rule.prefix
+            );
+            anyhow::ensure!(
+                !rule.refill_interval.is_zero(),
+                "Rate limit refill interval for `{}` must not be zero",
+                rule.prefix
+            );
+        }
+        Ok(())
+    }
+
+    pub fn layer(
+        self,
+        token_transport: TokenTransport,
+        cookie_service: CookieService,
+        token_service: TokenService,
+        trusted_proxies: TrustedProxies,
+    ) -> RateLimitLayer {
+        RateLimitLayer::new(
+            self.rules,
+            InMemoryStore::default(),
+            token_transport,
+            cookie_service,
+            token_service,
+            trusted_proxies,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[test]
+    fn rejects_rules_that_never_let_requests_through() {
+        let config = crate::config::Config::new().unwrap().rate_limit;
+        assert!(config.validate().is_ok());
+        let mut empty = config.clone();
+        empty.rules[0].capacity = 0;
+        assert!(empty.validate().is_err());
+        let mut never_refilled = config;
+        never_refilled.rules[0].refill_interval = Duration::ZERO;
+        assert!(never_refilled.validate().is_err());
+    }
+}
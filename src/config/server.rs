@@ -1,9 +1,13 @@
+use std::net::IpAddr;
+
 use reqwest::Url;
 use secrecy::Secret;
 use serde::Deserialize;
 use serde_aux::field_attributes::deserialize_number_from_string;
 use serde_with::{serde_as, DisplayFromStr};
 
+use crate::extractors::TrustedProxies;
+
 #[serde_as]
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
@@ -13,4 +17,15 @@ pub struct Config {
     #[serde_as(as = "DisplayFromStr")]
     pub base_url: Url,
     pub hmac_secret: Secret<String>,
+    /// Keeps accepting what was protected with the secrets themselves
+    /// before keys were derived from them. Every use is logged,
+    /// so that it can be turned off once the warnings stop.
+    pub accept_legacy_keys: bool,
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Config {
+    pub fn trusted_proxies(&self) -> TrustedProxies {
+        TrustedProxies::new(self.trusted_proxies.clone())
+    }
 }
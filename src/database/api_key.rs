@@ -0,0 +1,221 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use secrecy::Secret;
+use serde::Serialize;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::Executor;
+use crate::services::token::TokenService;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Option<Vec<String>>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_used_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub expires_at: Option<OffsetDateTime>,
+    pub current: bool,
+}
+
+/// Owner of a valid API key along with the scopes the key can use.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedApiKey {
+    pub id: Uuid,
+    pub user_id: i64,
+    pub scopes: Vec<String>,
+}
+
+#[tracing::instrument(name = "Create API key", skip(key, executor), err(Debug))]
+pub async fn insert<'e, E: Executor<'e>>(
+    user_id: i64,
+    name: &str,
+    prefix: &str,
+    key: &Secret<String>,
+    scopes: Option<&[String]>,
+    ttl: Option<Duration>,
+    executor: E,
+) -> anyhow::Result<ApiKey> {
+    sqlx::query_as!(
+        ApiKey,
+        r#"
+        insert into api_keys (
+          id,
+          user_id,
+          name,
+          prefix,
+          token_hash,
+          scopes,
+          expires_at
+        )
+        values (
+          $1, $2, $3, $4, $5, $6,
+          now() + make_interval(secs => $7)
+        )
+        returning
+          id,
+          name,
+          prefix,
+          scopes,
+          created_at,
+          last_used_at,
+          expires_at,
+          false as "current!";
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        prefix,
+        TokenService::hash_token(key),
+        scopes,
+        ttl.map(|ttl| ttl.as_secs_f64())
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to insert API key")
+}
+
+#[tracing::instrument(
+    name = "List user's API keys",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn list<'e, E: Executor<'e>>(
+    user_id: i64,
+    current_api_key_id: Option<&Uuid>,
+    executor: E,
+) -> anyhow::Result<Vec<ApiKey>> {
+    sqlx::query_as!(
+        ApiKey,
+        r#"
+        select
+          id,
+          name,
+          prefix,
+          scopes,
+          created_at,
+          last_used_at,
+          expires_at,
+          id is not distinct from $2 as "current!"
+        from api_keys
+        where user_id = $1
+        order by created_at desc;
+        "#,
+        user_id,
+        current_api_key_id
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to select API keys")
+}
+
+/// Returns whether the user had the key.
+#[tracing::instrument(name = "Delete API key", skip(executor), err(Debug))]
+pub async fn delete_for_user<'e, E: Executor<'e>>(
+    id: &Uuid,
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        delete from api_keys
+        where id = $1 and user_id = $2;
+        "#,
+        id,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() > 0)
+    .context("Failed to delete API key")
+}
+
+#[tracing::instrument(
+    name = "Delete all user's API keys",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn delete_all_for_user<'e, E: Executor<'e>>(
+    user_id: i64,
+    except_api_key_id: Option<&Uuid>,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        delete from api_keys
+        where user_id = $1 and id is distinct from $2;
+        "#,
+        user_id,
+        except_api_key_id
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to delete user's API keys")
+}
+
+/// Finds an unexpired key of a user that is not disabled
+/// and records its use, at most once a minute so that busy keys
+/// do not turn every request into a write. The key is limited
+/// to the permissions its user has now, so that it never outlives them.
+#[tracing::instrument(name = "Authenticate API key", skip_all, err(Debug))]
+pub async fn authenticate<'e, E: Executor<'e>>(
+    key: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<Option<AuthenticatedApiKey>> {
+    sqlx::query_as!(
+        AuthenticatedApiKey,
+        r#"
+        with api_key as (
+          select id, user_id, scopes
+          from api_keys
+          where token_hash = $1
+            and (expires_at is null or expires_at > now())
+            and user_id in (select id from users where disabled_at is null)
+        ), used as (
+          update api_keys
+          set last_used_at = now()
+          where id in (select id from api_key)
+            and (
+              last_used_at is null
+              or last_used_at < now() - interval '1 minute'
+            )
+        )
+        select
+          api_key.id,
+          api_key.user_id,
+          array(
+            select permissions.name
+            from permissions
+            where (
+                api_key.scopes is null
+                or permissions.name = any(api_key.scopes)
+              )
+              and permissions.id in (
+                select role_permissions.permission_id
+                from role_permissions
+                join user_roles
+                  on user_roles.role_id = role_permissions.role_id
+                where user_roles.user_id = api_key.user_id
+                union
+                select permission_id
+                from user_permissions
+                where user_id = api_key.user_id
+              )
+            order by permissions.name
+          ) as "scopes!"
+        from api_key;
+        "#,
+        TokenService::hash_token(key)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to authenticate API key")
+}
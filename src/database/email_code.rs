@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+
+use super::Executor;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Purpose {
+    Verification,
+    Login,
+}
+
+impl Purpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Verification => "verification",
+            Self::Login => "login",
+        }
+    }
+}
+
+pub struct EmailCode {
+    pub user_id: i64,
+    pub code_hash: Secret<String>,
+    pub failed_attempts: i32,
+}
+
+/// Replaces the previous code issued for the same purpose, if any,
+/// unless it was issued less than `cooldown` ago. Returns whether it did.
+/// Failed attempts carry over until the previous code expires,
+/// so that requesting a new code does not grant more guesses.
+#[tracing::instrument(
+    name = "Save email code",
+    skip(code_hash, executor),
+    err(Debug)
+)]
+pub async fn upsert<'e, E: Executor<'e>>(
+    user_id: i64,
+    purpose: Purpose,
+    code_hash: &Secret<String>,
+    ttl: Duration,
+    cooldown: Duration,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        insert into email_codes (user_id, purpose, code_hash, expires_at)
+        values ($1, $2, $3, now() + make_interval(secs => $4))
+        on conflict (user_id, purpose) do update
+        set code_hash = excluded.code_hash,
+            failed_attempts = case
+              when email_codes.expires_at > now()
+              then email_codes.failed_attempts
+              else 0
+            end,
+            created_at = now(),
+            expires_at = excluded.expires_at
+        where email_codes.created_at <= now() - make_interval(secs => $5);
+        "#,
+        user_id,
+        purpose.as_str(),
+        code_hash.expose_secret(),
+        ttl.as_secs_f64(),
+        cooldown.as_secs_f64()
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() > 0)
+    .context("Failed to upsert email code")
+}
+
+#[tracing::instrument(
+    name = "Find active email code",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn find_for_update<'e, E: Executor<'e>>(
+    email: &str,
+    purpose: Purpose,
+    executor: E,
+) -> anyhow::Result<Option<EmailCode>> {
+    let code = sqlx::query!(
+        r#"
+        select c.user_id, c.code_hash, c.failed_attempts
+        from email_codes c
+        join users u on u.id = c.user_id
+        where u.email = $1
+          and c.purpose = $2
+          and c.expires_at > now()
+        for update of c;
+        "#,
+        email,
+        purpose.as_str()
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to select email code")?
+    .map(|r| EmailCode {
+        user_id: r.user_id,
+        code_hash: Secret::new(r.code_hash),
+        failed_attempts: r.failed_attempts,
+    });
+    Ok(code)
+}
+
+#[tracing::instrument(
+    name = "Record failed email code attempt",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn record_failed_attempt<'e, E: Executor<'e>>(
+    user_id: i64,
+    purpose: Purpose,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update email_codes
+        set failed_attempts = failed_attempts + 1
+        where user_id = $1 and purpose = $2;
+        "#,
+        user_id,
+        purpose.as_str()
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to record failed email code attempt")
+}
+
+#[tracing::instrument(name = "Delete email code", skip(executor), err(Debug))]
+pub async fn delete<'e, E: Executor<'e>>(
+    user_id: i64,
+    purpose: Purpose,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        delete from email_codes
+        where user_id = $1 and purpose = $2;
+        "#,
+        user_id,
+        purpose.as_str()
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to delete email code")
+}
@@ -0,0 +1,174 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::Context;
+
+use super::Executor;
+
+/// Failed password attempts are tracked both per account and per client,
+/// so that neither guessing one password from many addresses
+/// nor spraying many accounts from one address goes unnoticed.
+#[derive(Clone, Copy, Debug)]
+pub enum Subject<'a> {
+    /// Keyed by the submitted email rather than the user id,
+    /// so unknown emails are throttled exactly like existing ones.
+    Account(&'a str),
+    Ip(IpAddr),
+}
+
+impl<'a> Subject<'a> {
+    pub fn all(account: &'a str, ip_address: Option<IpAddr>) -> Vec<Self> {
+        let mut subjects = vec![Self::Account(account)];
+        subjects.extend(ip_address.map(Self::Ip));
+        subjects
+    }
+
+    fn scope(&self) -> &'static str {
+        match self {
+            Self::Account(_) => "account",
+            Self::Ip(_) => "ip",
+        }
+    }
+
+    fn key(&self) -> String {
+        match self {
+            Self::Account(email) => email.to_string(),
+            Self::Ip(ip) => ip.to_string(),
+        }
+    }
+}
+
+/// Returns how long the longest active lockout among `subjects` lasts.
+#[tracing::instrument(name = "Check lockouts", skip(executor), err(Debug))]
+pub async fn retry_after<'e, E: Executor<'e>>(
+    subjects: &[Subject<'_>],
+    executor: E,
+) -> anyhow::Result<Option<Duration>> {
+    let (scopes, keys): (Vec<_>, Vec<_>) = subjects
+        .iter()
+        .map(|s| (s.scope().to_owned(), s.key()))
+        .unzip();
+    let seconds = sqlx::query!(
+        r#"
+        select extract(epoch from max(locked_until) - now())::float8 as seconds
+        from login_failures
+        where (scope, key) in (
+            select * from unnest($1::varchar[], $2::varchar[])
+          )
+          and locked_until > now();
+        "#,
+        &scopes,
+        &keys
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to select lockouts")?
+    .seconds;
+    Ok(seconds.map(|s| Duration::from_secs(s.ceil() as u64)))
+}
+
+/// Counts an attempt as failed before it is checked and returns
+/// the number of failures since the last success, ignoring the ones
+/// older than `reset_after`, or `None` while the subject is locked out.
+/// The row stays locked until the transaction ends, so that concurrent
+/// attempts are counted one after another.
+#[tracing::instrument(name = "Count attempt", skip(executor), err(Debug))]
+pub async fn count_attempt<'e, E: Executor<'e>>(
+    subject: Subject<'_>,
+    reset_after: Duration,
+    executor: E,
+) -> anyhow::Result<Option<i32>> {
+    sqlx::query!(
+        r#"
+        insert into login_failures (scope, key, failures)
+        values ($1, $2, 1)
+        on conflict (scope, key) do update
+        set failures = case
+              when login_failures.last_failed_at
+                < now() - make_interval(secs => $3)
+              then 1
+              else login_failures.failures + 1
+            end,
+            last_failed_at = now()
+        where login_failures.locked_until is null
+          or login_failures.locked_until <= now()
+        returning failures;
+        "#,
+        subject.scope(),
+        subject.key(),
+        reset_after.as_secs_f64()
+    )
+    .fetch_optional(executor)
+    .await
+    .map(|r| r.map(|r| r.failures))
+    .context("Failed to count attempt")
+}
+
+/// Takes back an attempt that turned out to be successful,
+/// along with the lockout it started if `unlock` is set.
+#[tracing::instrument(name = "Release attempt", skip(executor), err(Debug))]
+pub async fn release<'e, E: Executor<'e>>(
+    subject: Subject<'_>,
+    unlock: bool,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update login_failures
+        set failures = greatest(failures - 1, 0),
+            locked_until = case when $3 then null else locked_until end
+        where scope = $1 and key = $2;
+        "#,
+        subject.scope(),
+        subject.key(),
+        unlock
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to release attempt")
+}
+
+#[tracing::instrument(name = "Lock out", skip(executor), err(Debug))]
+pub async fn lock<'e, E: Executor<'e>>(
+    subject: Subject<'_>,
+    duration: Duration,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update login_failures
+        set locked_until = now() + make_interval(secs => $3)
+        where scope = $1 and key = $2;
+        "#,
+        subject.scope(),
+        subject.key(),
+        duration.as_secs_f64()
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to lock out")
+}
+
+#[tracing::instrument(
+    name = "Clear failed attempts",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn clear<'e, E: Executor<'e>>(
+    subject: Subject<'_>,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        delete from login_failures
+        where scope = $1 and key = $2;
+        "#,
+        subject.scope(),
+        subject.key()
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to clear failed attempts")
+}
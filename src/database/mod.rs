@@ -1,3 +1,18 @@
+pub mod api_key;
+pub mod email_code;
+pub mod login_failure;
+pub mod oauth_client;
+pub mod oauth_consent;
+pub mod oauth_token;
+pub mod permission;
+pub mod refresh_token;
+pub mod revoked_access_token;
+pub mod role;
+pub mod service_account;
+pub mod session;
+pub mod user;
+pub mod webauthn_challenge;
+
 use anyhow::Context;
 use sqlx::{Postgres, Transaction};
 
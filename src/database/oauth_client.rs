@@ -0,0 +1,164 @@
+use anyhow::Context;
+use secrecy::Secret;
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use super::Executor;
+use crate::services::token::TokenService;
+
+/// Client application that signs users in through OpenID Connect.
+/// Confidential clients authenticate with their secret,
+/// public ones only have PKCE to rely on.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OauthClient {
+    pub id: i64,
+    pub name: String,
+    pub client_id: String,
+    pub redirect_uris: Vec<String>,
+    pub confidential: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+#[tracing::instrument(
+    name = "Create OAuth client",
+    skip(client_secret, executor),
+    err(Debug)
+)]
+pub async fn insert<'e, E: Executor<'e>>(
+    name: &str,
+    client_id: &str,
+    client_secret: Option<&Secret<String>>,
+    redirect_uris: &[String],
+    executor: E,
+) -> anyhow::Result<OauthClient> {
+    sqlx::query_as!(
+        OauthClient,
+        r#"
+        insert into oauth_clients (
+          name,
+          client_id,
+          client_secret_hash,
+          redirect_uris
+        )
+        values ($1, $2, $3, $4)
+        returning
+          id,
+          name,
+          client_id,
+          redirect_uris,
+          client_secret_hash is not null as "confidential!",
+          created_at;
+        "#,
+        name,
+        client_id,
+        client_secret.map(TokenService::hash_token),
+        redirect_uris
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to insert OAuth client")
+}
+
+#[tracing::instrument(name = "List OAuth clients", skip(executor), err(Debug))]
+pub async fn list<'e, E: Executor<'e>>(
+    executor: E,
+) -> anyhow::Result<Vec<OauthClient>> {
+    sqlx::query_as!(
+        OauthClient,
+        r#"
+        select
+          id,
+          name,
+          client_id,
+          redirect_uris,
+          client_secret_hash is not null as "confidential!",
+          created_at
+        from oauth_clients
+        order by created_at desc;
+        "#
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to select OAuth clients")
+}
+
+#[tracing::instrument(name = "Find OAuth client", skip(executor), err(Debug))]
+pub async fn find<'e, E: Executor<'e>>(
+    client_id: &str,
+    executor: E,
+) -> anyhow::Result<Option<OauthClient>> {
+    sqlx::query_as!(
+        OauthClient,
+        r#"
+        select
+          id,
+          name,
+          client_id,
+          redirect_uris,
+          client_secret_hash is not null as "confidential!",
+          created_at
+        from oauth_clients
+        where client_id = $1;
+        "#,
+        client_id
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to select OAuth client")
+}
+
+/// Confidential clients must present their secret,
+/// while public clients are known by their id alone
+/// and fail to authenticate when presenting a secret anyway.
+#[tracing::instrument(
+    name = "Authenticate OAuth client",
+    skip(client_secret, executor),
+    err(Debug)
+)]
+pub async fn authenticate<'e, E: Executor<'e>>(
+    client_id: &str,
+    client_secret: Option<&Secret<String>>,
+    executor: E,
+) -> anyhow::Result<Option<OauthClient>> {
+    sqlx::query_as!(
+        OauthClient,
+        r#"
+        select
+          id,
+          name,
+          client_id,
+          redirect_uris,
+          client_secret_hash is not null as "confidential!",
+          created_at
+        from oauth_clients
+        where client_id = $1
+          and client_secret_hash is not distinct from $2;
+        "#,
+        client_id,
+        client_secret.map(TokenService::hash_token)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to authenticate OAuth client")
+}
+
+/// Returns whether the client existed.
+#[tracing::instrument(name = "Delete OAuth client", skip(executor), err(Debug))]
+pub async fn delete<'e, E: Executor<'e>>(
+    id: i64,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        delete from oauth_clients
+        where id = $1;
+        "#,
+        id
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() > 0)
+    .context("Failed to delete OAuth client")
+}
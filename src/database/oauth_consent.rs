@@ -0,0 +1,59 @@
+use anyhow::Context;
+
+use super::Executor;
+
+/// Returns whether the user has already consented
+/// to share every one of `scopes` with the client.
+#[tracing::instrument(name = "Check OAuth consent", skip(executor), err(Debug))]
+pub async fn covers<'e, E: Executor<'e>>(
+    user_id: i64,
+    oauth_client_id: i64,
+    scopes: &[String],
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        select exists(
+          select 1
+          from oauth_consents
+          where user_id = $1 and oauth_client_id = $2 and scopes @> $3
+        ) as "covered!";
+        "#,
+        user_id,
+        oauth_client_id,
+        scopes
+    )
+    .fetch_one(executor)
+    .await
+    .map(|r| r.covered)
+    .context("Failed to select OAuth consent")
+}
+
+/// Adds `scopes` to those the user has consented to before.
+#[tracing::instrument(name = "Grant OAuth consent", skip(executor), err(Debug))]
+pub async fn grant<'e, E: Executor<'e>>(
+    user_id: i64,
+    oauth_client_id: i64,
+    scopes: &[String],
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        insert into oauth_consents (user_id, oauth_client_id, scopes)
+        values ($1, $2, $3)
+        on conflict (user_id, oauth_client_id) do update
+        set
+          scopes = array(
+            select distinct unnest(oauth_consents.scopes || excluded.scopes)
+          ),
+          granted_at = now();
+        "#,
+        user_id,
+        oauth_client_id,
+        scopes
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to insert OAuth consent")
+}
@@ -0,0 +1,226 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use secrecy::Secret;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::Executor;
+use crate::services::token::TokenService;
+
+/// What the authorization code was issued for,
+/// to be checked when the client exchanges it for tokens.
+#[derive(Clone, Debug)]
+pub struct AuthorizationCode {
+    pub oauth_client_id: i64,
+    pub user_id: i64,
+    pub session_id: Uuid,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: String,
+    pub expires_at: OffsetDateTime,
+    /// Whether the code had already been exchanged before.
+    pub replayed: bool,
+}
+
+/// Authorization code about to be issued to a client.
+#[derive(Clone, Copy, Debug)]
+pub struct NewAuthorizationCode<'a> {
+    pub oauth_client_id: i64,
+    pub user_id: i64,
+    pub session_id: &'a Uuid,
+    pub redirect_uri: &'a str,
+    pub scopes: &'a [String],
+    pub nonce: Option<&'a str>,
+    pub code_challenge: &'a str,
+}
+
+/// User and scopes an access token of a client grants access to.
+#[derive(Clone, Debug)]
+pub struct OauthAccessToken {
+    pub user_id: i64,
+    pub scopes: Vec<String>,
+}
+
+/// Expired codes are cleaned up along the way, a day after expiring,
+/// which outlasts the access tokens that could be issued from them.
+#[tracing::instrument(
+    name = "Create authorization code",
+    skip(code, executor),
+    err(Debug)
+)]
+pub async fn insert_code<'e, E: Executor<'e>>(
+    code: &Secret<String>,
+    new_code: &NewAuthorizationCode<'_>,
+    ttl: Duration,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        with expired as (
+          delete from oauth_authorization_codes
+          where expires_at <= now() - interval '1 day'
+        )
+        insert into oauth_authorization_codes (
+          code_hash,
+          oauth_client_id,
+          user_id,
+          session_id,
+          redirect_uri,
+          scopes,
+          nonce,
+          code_challenge,
+          expires_at
+        )
+        values (
+          $1, $2, $3, $4, $5, $6, $7, $8, now() + make_interval(secs => $9)
+        );
+        "#,
+        TokenService::hash_token(code),
+        new_code.oauth_client_id,
+        new_code.user_id,
+        new_code.session_id,
+        new_code.redirect_uri,
+        new_code.scopes,
+        new_code.nonce,
+        new_code.code_challenge,
+        ttl.as_secs_f64()
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to insert authorization code")
+}
+
+/// Codes are single-use, so the code is marked as used
+/// whether or not the exchange goes on to succeed.
+/// The row lock makes concurrent exchanges see each other.
+#[tracing::instrument(
+    name = "Consume authorization code",
+    skip_all,
+    err(Debug)
+)]
+pub async fn consume_code<'e, E: Executor<'e>>(
+    code: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<Option<AuthorizationCode>> {
+    sqlx::query_as!(
+        AuthorizationCode,
+        r#"
+        update oauth_authorization_codes as code
+        set used_at = now()
+        from (
+          select code_hash, used_at
+          from oauth_authorization_codes
+          where code_hash = $1
+          for update
+        ) as previous
+        where code.code_hash = previous.code_hash
+        returning
+          code.oauth_client_id,
+          code.user_id,
+          code.session_id,
+          code.redirect_uri,
+          code.scopes,
+          code.nonce,
+          code.code_challenge,
+          code.expires_at,
+          previous.used_at is not null as "replayed!";
+        "#,
+        TokenService::hash_token(code)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to update authorization code")
+}
+
+/// Revokes the access tokens issued from a code that was replayed,
+/// as it may have been stolen.
+#[tracing::instrument(
+    name = "Revoke OAuth access tokens by code",
+    skip_all,
+    err(Debug)
+)]
+pub async fn revoke_for_code<'e, E: Executor<'e>>(
+    code: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        delete from oauth_access_tokens
+        where authorization_code_hash = $1;
+        "#,
+        TokenService::hash_token(code)
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to delete OAuth access tokens")
+}
+
+#[tracing::instrument(
+    name = "Create OAuth access token",
+    skip(access_token, code, executor),
+    err(Debug)
+)]
+pub async fn insert_access_token<'e, E: Executor<'e>>(
+    access_token: &Secret<String>,
+    code: &Secret<String>,
+    authorization_code: &AuthorizationCode,
+    ttl: Duration,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        with expired as (
+          delete from oauth_access_tokens
+          where expires_at <= now()
+        )
+        insert into oauth_access_tokens (
+          token_hash,
+          authorization_code_hash,
+          oauth_client_id,
+          user_id,
+          session_id,
+          scopes,
+          expires_at
+        )
+        values ($1, $2, $3, $4, $5, $6, now() + make_interval(secs => $7));
+        "#,
+        TokenService::hash_token(access_token),
+        TokenService::hash_token(code),
+        authorization_code.oauth_client_id,
+        authorization_code.user_id,
+        authorization_code.session_id,
+        &authorization_code.scopes,
+        ttl.as_secs_f64()
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to insert OAuth access token")
+}
+
+#[tracing::instrument(
+    name = "Authenticate OAuth access token",
+    skip_all,
+    err(Debug)
+)]
+pub async fn authenticate_access_token<'e, E: Executor<'e>>(
+    access_token: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<Option<OauthAccessToken>> {
+    sqlx::query_as!(
+        OauthAccessToken,
+        r#"
+        select user_id, scopes
+        from oauth_access_tokens
+        where token_hash = $1 and expires_at > now();
+        "#,
+        TokenService::hash_token(access_token)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to select OAuth access token")
+}
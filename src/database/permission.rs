@@ -0,0 +1,180 @@
+use anyhow::Context;
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::Executor;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Permission {
+    pub name: String,
+    pub description: String,
+}
+
+#[tracing::instrument(name = "List permissions", skip(executor), err(Debug))]
+pub async fn list<'e, E: Executor<'e>>(
+    executor: E,
+) -> anyhow::Result<Vec<Permission>> {
+    sqlx::query_as!(
+        Permission,
+        r#"
+        select name, description
+        from permissions
+        order by name;
+        "#
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to select permissions")
+}
+
+/// Lists the permissions granted to the user through their roles
+/// together with the ones granted to them directly.
+#[tracing::instrument(
+    name = "List user's permissions",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn list_for_user<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<Vec<String>> {
+    sqlx::query!(
+        r#"
+        select permissions.name
+        from permissions
+        where permissions.id in (
+            select role_permissions.permission_id
+            from role_permissions
+            join user_roles on user_roles.role_id = role_permissions.role_id
+            where user_roles.user_id = $1
+            union
+            select permission_id
+            from user_permissions
+            where user_id = $1
+          )
+        order by permissions.name;
+        "#,
+        user_id
+    )
+    .fetch_all(executor)
+    .await
+    .map(|rows| rows.into_iter().map(|r| r.name).collect())
+    .context("Failed to select user's permissions")
+}
+
+/// Lists the user's permissions that the session is allowed to use,
+/// which become the scopes of its access tokens.
+#[tracing::instrument(
+    name = "List session's scopes",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn list_for_session<'e, E: Executor<'e>>(
+    session_id: &Uuid,
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<Vec<String>> {
+    sqlx::query!(
+        r#"
+        select permissions.name
+        from sessions
+        join permissions
+          on sessions.scopes is null
+          or permissions.name = any(sessions.scopes)
+        where sessions.id = $1
+          and permissions.id in (
+            select role_permissions.permission_id
+            from role_permissions
+            join user_roles on user_roles.role_id = role_permissions.role_id
+            where user_roles.user_id = $2
+            union
+            select permission_id
+            from user_permissions
+            where user_id = $2
+          )
+        order by permissions.name;
+        "#,
+        session_id,
+        user_id
+    )
+    .fetch_all(executor)
+    .await
+    .map(|rows| rows.into_iter().map(|r| r.name).collect())
+    .context("Failed to select session's scopes")
+}
+
+/// Returns whether every one of the names is a known permission.
+#[tracing::instrument(name = "Check permissions", skip(executor), err(Debug))]
+pub async fn all_exist<'e, E: Executor<'e>>(
+    names: &[String],
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        select not exists (
+          select 1
+          from unnest($1::varchar[]) as names(name)
+          where name not in (select name from permissions)
+        ) as "exist!";
+        "#,
+        names
+    )
+    .fetch_one(executor)
+    .await
+    .map(|r| r.exist)
+    .context("Failed to check permissions")
+}
+
+/// Returns whether both the user and the permission exist.
+/// Granting a permission the user already has directly is a no-op.
+#[tracing::instrument(name = "Grant permission", skip(executor), err(Debug))]
+pub async fn grant<'e, E: Executor<'e>>(
+    user_id: i64,
+    permission: &str,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        with target as (
+          select users.id as user_id, permissions.id as permission_id
+          from users, permissions
+          where users.id = $1 and permissions.name = $2
+        ), granted as (
+          insert into user_permissions (user_id, permission_id)
+          select user_id, permission_id
+          from target
+          on conflict do nothing
+        )
+        select exists(select 1 from target) as "found!";
+        "#,
+        user_id,
+        permission
+    )
+    .fetch_one(executor)
+    .await
+    .map(|r| r.found)
+    .context("Failed to grant permission")
+}
+
+/// Returns whether the user had the permission granted directly.
+/// Permissions granted through roles are left intact.
+#[tracing::instrument(name = "Revoke permission", skip(executor), err(Debug))]
+pub async fn revoke<'e, E: Executor<'e>>(
+    user_id: i64,
+    permission: &str,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        delete from user_permissions
+        where user_id = $1
+          and permission_id = (select id from permissions where name = $2);
+        "#,
+        user_id,
+        permission
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() > 0)
+    .context("Failed to revoke permission")
+}
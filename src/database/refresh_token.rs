@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use secrecy::Secret;
+use uuid::Uuid;
+
+use super::Executor;
+use crate::services::token::{Claims, TokenService};
+
+#[derive(Clone, Debug)]
+pub struct RefreshToken {
+    pub user_id: i64,
+    pub session_id: Uuid,
+    pub rotated: bool,
+    pub expired: bool,
+}
+
+#[tracing::instrument(
+    name = "Save refresh token",
+    skip(token, executor),
+    err(Debug)
+)]
+pub async fn insert<'e, E: Executor<'e>>(
+    user_id: i64,
+    session_id: &Uuid,
+    token: &Secret<String>,
+    ttl: Duration,
+    access_token: &Claims,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        insert into refresh_tokens (
+          token_hash,
+          session_id,
+          user_id,
+          expires_at,
+          access_token_jti,
+          access_token_expires_at
+        )
+        values (
+          $1, $2, $3,
+          now() + make_interval(secs => $4),
+          $5,
+          to_timestamp($6)
+        );
+        "#,
+        TokenService::hash_token(token),
+        session_id,
+        user_id,
+        ttl.as_secs_f64(),
+        access_token.jti,
+        access_token.exp as f64
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to insert refresh token")
+}
+
+#[tracing::instrument(name = "Find refresh token", skip_all, err(Debug))]
+pub async fn find_for_update<'e, E: Executor<'e>>(
+    token: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<Option<RefreshToken>> {
+    let token = sqlx::query!(
+        r#"
+        select
+          user_id,
+          session_id,
+          rotated,
+          expires_at <= now() as "expired!"
+        from refresh_tokens
+        where token_hash = $1
+        for update;
+        "#,
+        TokenService::hash_token(token)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to select refresh token")?
+    .map(|r| RefreshToken {
+        user_id: r.user_id,
+        session_id: r.session_id,
+        rotated: r.rotated,
+        expired: r.expired,
+    });
+    Ok(token)
+}
+
+#[tracing::instrument(
+    name = "Mark refresh token as rotated",
+    skip_all,
+    err(Debug)
+)]
+pub async fn mark_rotated<'e, E: Executor<'e>>(
+    token: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update refresh_tokens
+        set rotated = true
+        where token_hash = $1;
+        "#,
+        TokenService::hash_token(token)
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to mark refresh token as rotated")
+}
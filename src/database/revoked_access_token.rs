@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use secrecy::Secret;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::Executor;
+use crate::services::token::TokenService;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RevokedAccessToken {
+    pub jti: Uuid,
+    pub expires_at: OffsetDateTime,
+}
+
+#[tracing::instrument(
+    name = "Revoke session's access tokens",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn revoke_for_session<'e, E: Executor<'e>>(
+    session_id: &Uuid,
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<Vec<RevokedAccessToken>> {
+    sqlx::query_as!(
+        RevokedAccessToken,
+        r#"
+        insert into revoked_access_tokens (jti, expires_at)
+        select access_token_jti, access_token_expires_at
+        from refresh_tokens
+        where session_id = $1
+          and user_id = $2
+          and access_token_expires_at > now()
+        on conflict do nothing
+        returning jti, expires_at;
+        "#,
+        session_id,
+        user_id
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to revoke session's access tokens")
+}
+
+#[tracing::instrument(
+    name = "Revoke access tokens by refresh token",
+    skip_all,
+    err(Debug)
+)]
+pub async fn revoke_for_refresh_token<'e, E: Executor<'e>>(
+    refresh_token: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<Vec<RevokedAccessToken>> {
+    sqlx::query_as!(
+        RevokedAccessToken,
+        r#"
+        insert into revoked_access_tokens (jti, expires_at)
+        select access_token_jti, access_token_expires_at
+        from refresh_tokens
+        where session_id = (
+            select session_id
+            from refresh_tokens
+            where token_hash = $1
+          )
+          and access_token_expires_at > now()
+        on conflict do nothing
+        returning jti, expires_at;
+        "#,
+        TokenService::hash_token(refresh_token)
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to revoke access tokens by refresh token")
+}
+
+/// Revokes the access tokens of every user's session
+/// except for `except_session_id`, if any,
+/// along with those issued to OAuth clients in these sessions.
+#[tracing::instrument(
+    name = "Revoke user's access tokens",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn revoke_for_user<'e, E: Executor<'e>>(
+    user_id: i64,
+    except_session_id: Option<&Uuid>,
+    executor: E,
+) -> anyhow::Result<Vec<RevokedAccessToken>> {
+    sqlx::query_as!(
+        RevokedAccessToken,
+        r#"
+        with oauth_revoked as (
+          delete from oauth_access_tokens
+          where user_id = $1 and session_id is distinct from $2
+        )
+        insert into revoked_access_tokens (jti, expires_at)
+        select access_token_jti, access_token_expires_at
+        from refresh_tokens
+        where user_id = $1
+          and session_id is distinct from $2
+          and access_token_expires_at > now()
+        on conflict do nothing
+        returning jti, expires_at;
+        "#,
+        user_id,
+        except_session_id
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to revoke user's access tokens")
+}
+
+/// Lists tokens revoked within `leeway` after their expiry,
+/// as those would still pass the validation.
+#[tracing::instrument(
+    name = "List revoked access tokens",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn list<'e, E: Executor<'e>>(
+    leeway: Duration,
+    executor: E,
+) -> anyhow::Result<Vec<RevokedAccessToken>> {
+    sqlx::query_as!(
+        RevokedAccessToken,
+        r#"
+        select jti, expires_at
+        from revoked_access_tokens
+        where expires_at > now() - make_interval(secs => $1);
+        "#,
+        leeway.as_secs_f64()
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to select revoked access tokens")
+}
+
+#[tracing::instrument(
+    name = "Delete expired revoked access tokens",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn delete_expired<'e, E: Executor<'e>>(
+    leeway: Duration,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        delete from revoked_access_tokens
+        where expires_at <= now() - make_interval(secs => $1);
+        "#,
+        leeway.as_secs_f64()
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to delete expired revoked access tokens")
+}
@@ -0,0 +1,77 @@
+use anyhow::Context;
+
+use super::Executor;
+
+#[tracing::instrument(name = "List user's roles", skip(executor), err(Debug))]
+pub async fn list_for_user<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<Vec<String>> {
+    sqlx::query!(
+        r#"
+        select roles.name
+        from user_roles
+        join roles on roles.id = user_roles.role_id
+        where user_roles.user_id = $1
+        order by roles.name;
+        "#,
+        user_id
+    )
+    .fetch_all(executor)
+    .await
+    .map(|rows| rows.into_iter().map(|r| r.name).collect())
+    .context("Failed to select user's roles")
+}
+
+/// Returns whether both the user and the role exist.
+/// Granting a role the user already has is a no-op.
+#[tracing::instrument(name = "Grant role", skip(executor), err(Debug))]
+pub async fn grant<'e, E: Executor<'e>>(
+    user_id: i64,
+    role: &str,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        with target as (
+          select users.id as user_id, roles.id as role_id
+          from users, roles
+          where users.id = $1 and roles.name = $2
+        ), granted as (
+          insert into user_roles (user_id, role_id)
+          select user_id, role_id
+          from target
+          on conflict do nothing
+        )
+        select exists(select 1 from target) as "found!";
+        "#,
+        user_id,
+        role
+    )
+    .fetch_one(executor)
+    .await
+    .map(|r| r.found)
+    .context("Failed to grant role")
+}
+
+/// Returns whether the user had the role.
+#[tracing::instrument(name = "Revoke role", skip(executor), err(Debug))]
+pub async fn revoke<'e, E: Executor<'e>>(
+    user_id: i64,
+    role: &str,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        delete from user_roles
+        where user_id = $1
+          and role_id = (select id from roles where name = $2);
+        "#,
+        user_id,
+        role
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() > 0)
+    .context("Failed to revoke role")
+}
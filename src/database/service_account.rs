@@ -0,0 +1,172 @@
+use anyhow::Context;
+use secrecy::Secret;
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use super::Executor;
+use crate::services::token::TokenService;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceAccount {
+    pub id: i64,
+    pub name: String,
+    pub client_id: String,
+    pub permissions: Vec<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+/// Service account whose client credentials are valid.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedServiceAccount {
+    pub id: i64,
+    pub client_id: String,
+    pub permissions: Vec<String>,
+}
+
+/// Permissions missing from the catalog are not granted.
+#[tracing::instrument(
+    name = "Create service account",
+    skip(client_secret, executor),
+    err(Debug)
+)]
+pub async fn insert<'e, E: Executor<'e>>(
+    name: &str,
+    client_id: &str,
+    client_secret: &Secret<String>,
+    permissions: &[String],
+    executor: E,
+) -> anyhow::Result<ServiceAccount> {
+    sqlx::query_as!(
+        ServiceAccount,
+        r#"
+        with service_account as (
+          insert into service_accounts (name, client_id, client_secret_hash)
+          values ($1, $2, $3)
+          returning id, name, client_id, created_at
+        ), granted as (
+          insert into service_account_permissions (
+            service_account_id,
+            permission_id
+          )
+          select service_account.id, permissions.id
+          from service_account, permissions
+          where permissions.name = any($4)
+          returning permission_id
+        )
+        select
+          service_account.id,
+          service_account.name,
+          service_account.client_id,
+          array(
+            select permissions.name
+            from permissions
+            where permissions.id in (select permission_id from granted)
+            order by permissions.name
+          ) as "permissions!",
+          service_account.created_at
+        from service_account;
+        "#,
+        name,
+        client_id,
+        TokenService::hash_token(client_secret),
+        permissions
+    )
+    .fetch_one(executor)
+    .await
+    .context("Failed to insert service account")
+}
+
+#[tracing::instrument(
+    name = "List service accounts",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn list<'e, E: Executor<'e>>(
+    executor: E,
+) -> anyhow::Result<Vec<ServiceAccount>> {
+    sqlx::query_as!(
+        ServiceAccount,
+        r#"
+        select
+          id,
+          name,
+          client_id,
+          array(
+            select permissions.name
+            from service_account_permissions
+            join permissions
+              on permissions.id = service_account_permissions.permission_id
+            where service_account_permissions.service_account_id
+              = service_accounts.id
+            order by permissions.name
+          ) as "permissions!",
+          created_at
+        from service_accounts
+        order by created_at desc;
+        "#
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to select service accounts")
+}
+
+/// Returns whether the service account existed.
+#[tracing::instrument(
+    name = "Delete service account",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn delete<'e, E: Executor<'e>>(
+    id: i64,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        delete from service_accounts
+        where id = $1;
+        "#,
+        id
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() > 0)
+    .context("Failed to delete service account")
+}
+
+#[tracing::instrument(
+    name = "Authenticate service account",
+    skip(client_secret, executor),
+    err(Debug)
+)]
+pub async fn authenticate<'e, E: Executor<'e>>(
+    client_id: &str,
+    client_secret: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<Option<AuthenticatedServiceAccount>> {
+    sqlx::query_as!(
+        AuthenticatedServiceAccount,
+        r#"
+        select
+          id,
+          client_id,
+          array(
+            select permissions.name
+            from service_account_permissions
+            join permissions
+              on permissions.id = service_account_permissions.permission_id
+            where service_account_permissions.service_account_id
+              = service_accounts.id
+            order by permissions.name
+          ) as "permissions!"
+        from service_accounts
+        where client_id = $1 and client_secret_hash = $2;
+        "#,
+        client_id,
+        TokenService::hash_token(client_secret)
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to authenticate service account")
+}
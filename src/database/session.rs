@@ -0,0 +1,249 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use secrecy::Secret;
+use serde::Serialize;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::Executor;
+use crate::{extractors::ClientInfo, services::token::TokenService};
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub id: Uuid,
+    pub device_label: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_used_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+    pub current: bool,
+}
+
+/// Returns `None` if the user is disabled.
+#[tracing::instrument(name = "Create session", skip(executor), err(Debug))]
+pub async fn insert<'e, E: Executor<'e>>(
+    user_id: i64,
+    device_label: Option<&str>,
+    client: &ClientInfo,
+    ttl: Duration,
+    executor: E,
+) -> anyhow::Result<Option<Uuid>> {
+    let id = Uuid::new_v4();
+    let result = sqlx::query!(
+        r#"
+        insert into sessions (
+          id,
+          user_id,
+          device_label,
+          user_agent,
+          ip_address,
+          expires_at
+        )
+        select $1, id, $3, $4, $5, now() + make_interval(secs => $6)
+        from users
+        where id = $2 and disabled_at is null;
+        "#,
+        id,
+        user_id,
+        device_label,
+        client.user_agent,
+        client.ip_address.map(|ip| ip.to_string()),
+        ttl.as_secs_f64()
+    )
+    .execute(executor)
+    .await
+    .context("Failed to insert session")?;
+    Ok((result.rows_affected() > 0).then_some(id))
+}
+
+#[tracing::instrument(name = "Touch session", skip(executor), err(Debug))]
+pub async fn touch<'e, E: Executor<'e>>(
+    id: &Uuid,
+    client: &ClientInfo,
+    ttl: Duration,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update sessions
+        set
+          user_agent = coalesce($2, user_agent),
+          ip_address = coalesce($3, ip_address),
+          last_used_at = now(),
+          expires_at = now() + make_interval(secs => $4)
+        where id = $1;
+        "#,
+        id,
+        client.user_agent,
+        client.ip_address.map(|ip| ip.to_string()),
+        ttl.as_secs_f64()
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to touch session")
+}
+
+/// Limits the session to the scopes it already had among `scopes`,
+/// so that a session can narrow its scopes but never widen them.
+#[tracing::instrument(
+    name = "Narrow session's scopes",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn narrow_scopes<'e, E: Executor<'e>>(
+    id: &Uuid,
+    scopes: &[String],
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        update sessions
+        set scopes = array(
+            select scope
+            from unnest($2::text[]) as scope
+            where sessions.scopes is null or scope = any(sessions.scopes)
+          )
+        where id = $1;
+        "#,
+        id,
+        scopes
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to narrow session's scopes")
+}
+
+#[tracing::instrument(
+    name = "List user's sessions",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn list<'e, E: Executor<'e>>(
+    user_id: i64,
+    current_session_id: Option<&Uuid>,
+    executor: E,
+) -> anyhow::Result<Vec<Session>> {
+    sqlx::query_as!(
+        Session,
+        r#"
+        select
+          id,
+          device_label,
+          user_agent,
+          ip_address,
+          created_at,
+          last_used_at,
+          expires_at,
+          id is not distinct from $2 as "current!"
+        from sessions
+        where user_id = $1 and expires_at > now()
+        order by last_used_at desc;
+        "#,
+        user_id,
+        current_session_id
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to select sessions")
+}
+
+#[tracing::instrument(name = "Delete session", skip(executor), err(Debug))]
+pub async fn delete<'e, E: Executor<'e>>(
+    id: &Uuid,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        delete from sessions
+        where id = $1;
+        "#,
+        id
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to delete session")
+}
+
+#[tracing::instrument(
+    name = "Delete user's session",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn delete_for_user<'e, E: Executor<'e>>(
+    id: &Uuid,
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        delete from sessions
+        where id = $1 and user_id = $2;
+        "#,
+        id,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() > 0)
+    .context("Failed to delete session")
+}
+
+#[tracing::instrument(
+    name = "Delete session by refresh token",
+    skip_all,
+    err(Debug)
+)]
+pub async fn delete_by_refresh_token<'e, E: Executor<'e>>(
+    refresh_token: &Secret<String>,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        delete from sessions
+        where id = (
+          select session_id
+          from refresh_tokens
+          where token_hash = $1
+        );
+        "#,
+        TokenService::hash_token(refresh_token)
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to delete session by refresh token")
+}
+
+/// Deletes every user's session except for `except_session_id`, if any.
+#[tracing::instrument(
+    name = "Delete all user's sessions",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn delete_all_for_user<'e, E: Executor<'e>>(
+    user_id: i64,
+    except_session_id: Option<&Uuid>,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        delete from sessions
+        where user_id = $1 and id is distinct from $2;
+        "#,
+        user_id,
+        except_session_id
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to delete user's sessions")
+}
@@ -0,0 +1,67 @@
+use anyhow::Context;
+
+use super::Executor;
+
+/// Returns whether the user exists.
+/// Disabling a user that is already disabled is a no-op.
+#[tracing::instrument(name = "Disable user", skip(executor), err(Debug))]
+pub async fn disable<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        update users
+        set disabled_at = coalesce(disabled_at, now())
+        where id = $1;
+        "#,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() > 0)
+    .context("Failed to disable user")
+}
+
+/// Returns whether the user exists.
+#[tracing::instrument(name = "Enable user", skip(executor), err(Debug))]
+pub async fn enable<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        update users
+        set disabled_at = null
+        where id = $1;
+        "#,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() > 0)
+    .context("Failed to enable user")
+}
+
+#[tracing::instrument(
+    name = "Check if user is verified",
+    skip(executor),
+    err(Debug)
+)]
+pub async fn is_verified<'e, E: Executor<'e>>(
+    user_id: i64,
+    executor: E,
+) -> anyhow::Result<bool> {
+    sqlx::query!(
+        r#"
+        select verified
+        from users
+        where id = $1;
+        "#,
+        user_id
+    )
+    .fetch_optional(executor)
+    .await
+    .map(|r| r.is_some_and(|r| r.verified))
+    .context("Failed to check if user is verified")
+}
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+
+use super::Executor;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+impl Ceremony {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Registration => "registration",
+            Self::Authentication => "authentication",
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Save WebAuthn challenge",
+    skip(challenge, executor),
+    err(Debug)
+)]
+pub async fn insert<'e, E: Executor<'e>>(
+    user_id: Option<i64>,
+    challenge: &[u8],
+    ceremony: Ceremony,
+    ttl: Duration,
+    executor: E,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        insert into webauthn_challenges
+            (challenge_hash, user_id, ceremony, expires_at)
+        values ($1, $2, $3, now() + make_interval(secs => $4));
+        "#,
+        hash_challenge(challenge),
+        user_id,
+        ceremony.as_str(),
+        ttl.as_secs_f64()
+    )
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .context("Failed to insert WebAuthn challenge")
+}
+
+/// Deletes the challenge, so that it can be answered only once.
+/// Returns `None` if the challenge was not issued or has expired,
+/// otherwise the user it was issued for, if any.
+#[tracing::instrument(
+    name = "Consume WebAuthn challenge",
+    skip(challenge, executor),
+    err(Debug)
+)]
+pub async fn consume<'e, E: Executor<'e>>(
+    challenge: &[u8],
+    ceremony: Ceremony,
+    executor: E,
+) -> anyhow::Result<Option<Option<i64>>> {
+    sqlx::query!(
+        r#"
+        delete from webauthn_challenges
+        where challenge_hash = $1 and ceremony = $2
+        returning user_id, expires_at > now() as "active!";
+        "#,
+        hash_challenge(challenge),
+        ceremony.as_str()
+    )
+    .fetch_optional(executor)
+    .await
+    .map(|r| r.filter(|r| r.active).map(|r| r.user_id))
+    .context("Failed to consume WebAuthn challenge")
+}
+
+fn hash_challenge(challenge: &[u8]) -> String {
+    hex::encode(Sha256::digest(challenge))
+}
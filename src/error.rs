@@ -1,7 +1,10 @@
-use std::fmt;
+use std::{fmt, time::Duration};
 
 use axum::{
-    http::StatusCode,
+    http::{
+        header::{RETRY_AFTER, WWW_AUTHENTICATE},
+        HeaderValue, StatusCode,
+    },
     response::{IntoResponse, Response},
     Json,
 };
@@ -15,16 +18,81 @@ pub enum Error {
     NoAccessToken,
     #[error("invalid access token")]
     InvalidAccessToken,
+    #[error("invalid API key")]
+    InvalidApiKey,
+    // OAuth 2.0 errors are reported with the codes from RFC 6749
+    #[error("invalid_client")]
+    InvalidClient,
+    #[error("unsupported_grant_type")]
+    UnsupportedGrantType,
+    #[error("invalid_scope")]
+    InvalidScope,
+    #[error("invalid_request")]
+    InvalidRequest,
+    #[error("invalid_grant")]
+    InvalidGrant,
+    #[error("token transport is not enabled")]
+    UnsupportedTokenTransport,
+    #[error("OpenID Connect is not enabled")]
+    OpenIdConnectDisabled,
     #[error("missing refresh token")]
     NoRefreshToken,
     #[error("invalid refresh token")]
     InvalidRefreshToken,
+    #[error("invalid password reset token")]
+    InvalidPasswordResetToken,
+    #[error("invalid magic link")]
+    InvalidMagicLink,
+    #[error("invalid or expired code")]
+    InvalidEmailCode,
     #[error("invalid login or password")]
     InvalidCredentials,
     #[error("invalid password")]
     InvalidPassword,
+    #[error("email is not verified")]
+    UnverifiedEmail,
+    #[error("account is disabled")]
+    AccountDisabled,
     #[error("unknown verification token")]
     UnknownVerificationToken,
+    #[error("verification token has expired")]
+    ExpiredVerificationToken,
+    #[error("verification token was already used")]
+    UsedVerificationToken,
+    #[error("unknown session")]
+    UnknownSession,
+    #[error("unknown API key")]
+    UnknownApiKey,
+    #[error("unknown service account")]
+    UnknownServiceAccount,
+    #[error("unknown OAuth client")]
+    UnknownOauthClient,
+    #[error("unknown user")]
+    UnknownUser,
+    #[error("unknown user or role")]
+    UnknownUserOrRole,
+    #[error("unknown user or permission")]
+    UnknownUserOrPermission,
+    #[error("unknown scope")]
+    UnknownScope,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("account has no password")]
+    PasswordNotSet,
+    #[error("two-factor authentication is already enabled")]
+    TwoFactorAlreadyEnabled,
+    #[error("two-factor authentication is not set up")]
+    TwoFactorNotEnrolled,
+    #[error("invalid two-factor code")]
+    InvalidTwoFactorCode,
+    #[error("invalid two-factor challenge")]
+    InvalidTwoFactorChallenge,
+    #[error("invalid WebAuthn response")]
+    InvalidWebauthnResponse,
+    #[error("too many failed attempts")]
+    TooManyAttempts { retry_after: Duration },
+    #[error("too many requests")]
+    RateLimited { retry_after: Duration },
     #[error("an unexpected error occurred")]
     Unexpected(#[from] anyhow::Error),
 }
@@ -35,13 +103,49 @@ impl fmt::Debug for Error {
             Self::EmailTaken
             | Self::InvalidCredentials
             | Self::InvalidPassword
+            | Self::UnverifiedEmail
+            | Self::AccountDisabled
             | Self::NoAccessToken
             | Self::InvalidAccessToken
+            | Self::InvalidApiKey
+            | Self::InvalidClient
+            | Self::UnsupportedGrantType
+            | Self::InvalidScope
+            | Self::InvalidRequest
+            | Self::InvalidGrant
             | Self::NoRefreshToken
             | Self::InvalidRefreshToken
-            | Self::UnknownVerificationToken => {
+            | Self::InvalidPasswordResetToken
+            | Self::InvalidMagicLink
+            | Self::InvalidEmailCode
+            | Self::UnknownVerificationToken
+            | Self::ExpiredVerificationToken
+            | Self::UsedVerificationToken
+            | Self::UnknownSession
+            | Self::UnknownApiKey
+            | Self::UnknownServiceAccount
+            | Self::UnknownOauthClient
+            | Self::UnknownUser
+            | Self::UnknownUserOrRole
+            | Self::UnknownUserOrPermission
+            | Self::UnknownScope
+            | Self::Forbidden
+            | Self::PasswordNotSet
+            | Self::TwoFactorAlreadyEnabled
+            | Self::TwoFactorNotEnrolled
+            | Self::InvalidTwoFactorCode
+            | Self::InvalidTwoFactorChallenge
+            | Self::InvalidWebauthnResponse
+            | Self::UnsupportedTokenTransport
+            | Self::OpenIdConnectDisabled => {
                 write!(f, "{self}")
             }
+            Self::TooManyAttempts { retry_after } => {
+                write!(f, "{self}, locked out for {}s", retry_after.as_secs())
+            }
+            Self::RateLimited { retry_after } => {
+                write!(f, "{self}, retry after {}s", retry_after.as_secs())
+            }
             Self::Unexpected(e) => e.fmt(f),
         }
     }
@@ -51,13 +155,46 @@ impl Error {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::EmailTaken => StatusCode::CONFLICT,
+            Self::UnsupportedTokenTransport
+            | Self::UnsupportedGrantType
+            | Self::InvalidScope
+            | Self::InvalidRequest
+            | Self::InvalidGrant => StatusCode::BAD_REQUEST,
             Self::InvalidCredentials
             | Self::InvalidPassword
             | Self::NoAccessToken
             | Self::InvalidAccessToken
+            | Self::InvalidApiKey
+            | Self::InvalidClient
             | Self::NoRefreshToken
-            | Self::InvalidRefreshToken => StatusCode::UNAUTHORIZED,
-            Self::UnknownVerificationToken => StatusCode::NOT_FOUND,
+            | Self::InvalidRefreshToken
+            | Self::InvalidPasswordResetToken
+            | Self::InvalidMagicLink
+            | Self::InvalidEmailCode
+            | Self::InvalidTwoFactorCode
+            | Self::InvalidTwoFactorChallenge
+            | Self::InvalidWebauthnResponse => StatusCode::UNAUTHORIZED,
+            Self::UnknownVerificationToken
+            | Self::UnknownSession
+            | Self::UnknownApiKey
+            | Self::UnknownServiceAccount
+            | Self::UnknownOauthClient
+            | Self::UnknownUser
+            | Self::UnknownUserOrRole
+            | Self::UnknownUserOrPermission
+            | Self::TwoFactorNotEnrolled
+            | Self::OpenIdConnectDisabled => StatusCode::NOT_FOUND,
+            Self::UnverifiedEmail | Self::AccountDisabled | Self::Forbidden => {
+                StatusCode::FORBIDDEN
+            }
+            Self::UnknownScope => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::ExpiredVerificationToken => StatusCode::GONE,
+            Self::UsedVerificationToken
+            | Self::PasswordNotSet
+            | Self::TwoFactorAlreadyEnabled => StatusCode::CONFLICT,
+            Self::TooManyAttempts { .. } | Self::RateLimited { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
             Self::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -65,7 +202,22 @@ impl Error {
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        ErrorResponse::new(self.status_code(), self.to_string()).into_response()
+        let mut response =
+            ErrorResponse::new(self.status_code(), self.to_string())
+                .into_response();
+        if let Self::TooManyAttempts { retry_after }
+        | Self::RateLimited { retry_after } = self
+        {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, retry_after.as_secs().into());
+        }
+        if let Self::InvalidClient = self {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Basic"));
+        }
+        response
     }
 }
 
@@ -0,0 +1,137 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts, Extensions, HeaderMap},
+};
+
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+#[derive(Clone, Debug, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<IpAddr>,
+}
+
+/// Reverse proxies whose `X-Forwarded-For` entries are believed.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(Arc<[IpAddr]>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+    TrustedProxies: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .map(|ua| ua.chars().take(256).collect());
+        let ip_address = TrustedProxies::from_ref(state)
+            .client_ip(&parts.headers, &parts.extensions);
+        Ok(Self {
+            user_agent,
+            ip_address,
+        })
+    }
+}
+
+impl TrustedProxies {
+    pub fn new(proxies: Vec<IpAddr>) -> Self {
+        Self(proxies.into())
+    }
+
+    /// Prefers the address reported by a reverse proxy
+    /// over the address of the connected peer, but only if that peer
+    /// is a trusted proxy. As every proxy appends the address
+    /// it was connected from, the header is walked from the right
+    /// up to the first untrusted address, since anything left of it
+    /// could have been made up by the client.
+    pub fn client_ip(
+        &self,
+        headers: &HeaderMap,
+        extensions: &Extensions,
+    ) -> Option<IpAddr> {
+        let peer = extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let mut client = peer?;
+        let hops = headers
+            .get_all(FORWARDED_FOR)
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .collect::<Vec<_>>();
+        for hop in hops.into_iter().rev() {
+            if !self.0.contains(&client) {
+                break;
+            }
+            match hop.trim().parse() {
+                Ok(ip) => client = ip,
+                Err(_) => break,
+            }
+        }
+        Some(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use axum::{
+        extract::ConnectInfo,
+        http::{Extensions, HeaderMap},
+    };
+
+    use super::TrustedProxies;
+
+    #[test]
+    fn ignores_forwarded_for_from_untrusted_peer() {
+        let proxies = TrustedProxies::new(vec![ip("10.0.0.1")]);
+        let (headers, extensions) = request("192.0.2.1", "203.0.113.1");
+        let client = proxies.client_ip(&headers, &extensions);
+        assert_eq!(client, Some(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn takes_rightmost_untrusted_hop() {
+        let proxies = TrustedProxies::new(vec![ip("10.0.0.1"), ip("10.0.0.2")]);
+        let (headers, extensions) =
+            request("10.0.0.1", "198.51.100.1, 203.0.113.1, 10.0.0.2");
+        let client = proxies.client_ip(&headers, &extensions);
+        assert_eq!(client, Some(ip("203.0.113.1")));
+    }
+
+    #[test]
+    fn stops_at_malformed_hop() {
+        let proxies = TrustedProxies::new(vec![ip("10.0.0.1")]);
+        let (headers, extensions) = request("10.0.0.1", "203.0.113.1, junk");
+        let client = proxies.client_ip(&headers, &extensions);
+        assert_eq!(client, Some(ip("10.0.0.1")));
+    }
+
+    fn request(peer: &str, forwarded_for: &str) -> (HeaderMap, Extensions) {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", forwarded_for.parse().unwrap());
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo(SocketAddr::new(ip(peer), 443)));
+        (headers, extensions)
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+}
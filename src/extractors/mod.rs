@@ -1,7 +1,17 @@
+mod client;
+mod role;
+mod scope;
+mod service_account;
 mod user;
 pub mod validated;
 
-pub use user::User;
+pub use {
+    client::{ClientInfo, TrustedProxies},
+    role::{Admin, HasRole},
+    scope::{UsersRead, UsersWrite},
+    service_account::Principal,
+    user::{access_token, bearer_token, User, VerifiedUser},
+};
 
 use axum::{
     extract::rejection::{FormRejection, JsonRejection},
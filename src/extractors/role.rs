@@ -0,0 +1,46 @@
+use std::marker::PhantomData;
+
+use async_trait::async_trait;
+use axum::{extract::FromRequestParts, http::request::Parts};
+
+use super::scope::Scopes;
+use crate::{extractors::User, server::ServerState, telemetry, Error};
+
+/// Role that a [`HasRole`] guard requires, named as in the `roles` table.
+pub trait Role {
+    const NAME: &'static str;
+}
+
+pub struct Admin;
+
+impl Role for Admin {
+    const NAME: &'static str = "admin";
+}
+
+/// User whose access token carries the role `R` and the scopes `S`,
+/// rejected with 403 otherwise. Roles are read from the token,
+/// so that checking them needs no query. API keys carry no roles
+/// and service accounts are not users, so neither ever passes.
+pub struct HasRole<R, S = ()> {
+    pub user: User<S>,
+    role: PhantomData<fn() -> R>,
+}
+
+#[async_trait]
+impl<R: Role, S: Scopes> FromRequestParts<ServerState> for HasRole<R, S> {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState,
+    ) -> Result<Self, Self::Rejection> {
+        let user = User::<S>::from_request_parts(parts, state).await?;
+        if !user.credential.roles().iter().any(|role| role == R::NAME) {
+            Err(Error::Forbidden).map_err(telemetry::warn)?;
+        }
+        Ok(Self {
+            user,
+            role: PhantomData,
+        })
+    }
+}
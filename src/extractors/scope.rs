@@ -0,0 +1,36 @@
+use crate::{telemetry, Error};
+
+/// Scopes that a [`User`](super::User) or a
+/// [`ServiceAccount`](super::ServiceAccount) extractor requires,
+/// all of which the credential must carry.
+/// They are named as in the `permissions` table.
+pub trait Scopes {
+    const REQUIRED: &'static [&'static str];
+}
+
+/// Rejects with 403 if any of the scopes `S` requires is missing.
+pub fn ensure_scopes<S: Scopes>(scopes: &[String]) -> crate::Result<()> {
+    let has_scopes = S::REQUIRED
+        .iter()
+        .all(|required| scopes.iter().any(|scope| scope == required));
+    if !has_scopes {
+        Err(Error::Forbidden).map_err(telemetry::warn)?;
+    }
+    Ok(())
+}
+
+impl Scopes for () {
+    const REQUIRED: &'static [&'static str] = &[];
+}
+
+pub struct UsersRead;
+
+impl Scopes for UsersRead {
+    const REQUIRED: &'static [&'static str] = &["users:read"];
+}
+
+pub struct UsersWrite;
+
+impl Scopes for UsersWrite {
+    const REQUIRED: &'static [&'static str] = &["users:write"];
+}
@@ -0,0 +1,87 @@
+use std::{fmt, marker::PhantomData};
+
+use async_trait::async_trait;
+use axum::{extract::FromRequestParts, http::request::Parts};
+use secrecy::ExposeSecret;
+
+use super::{
+    scope::{ensure_scopes, Scopes},
+    user::bearer_token,
+    User,
+};
+use crate::{
+    server::ServerState, services::token::ServiceClaims, telemetry, Error,
+};
+
+/// Service account authenticated with an access token
+/// from the client credentials grant, always sent as a bearer token.
+/// Handlers declare the scopes they require with `S`, as with [`User`].
+#[derive(Clone, Debug)]
+pub struct ServiceAccount<S = ()> {
+    pub id: i64,
+    pub claims: ServiceClaims,
+    scopes: PhantomData<fn() -> S>,
+}
+
+#[async_trait]
+impl<S: Scopes> FromRequestParts<ServerState> for ServiceAccount<S> {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = bearer_token(&parts.headers).ok_or(Error::NoAccessToken)?;
+        let token_service = state.token_service.clone();
+        let claims = telemetry::instrument_blocking_task(move || {
+            token_service.decode_service_token(token.expose_secret())
+        })
+        .await?
+        .map_err(|_| Error::InvalidAccessToken)?;
+        ensure_scopes::<S>(&claims.scopes)?;
+        Ok(Self {
+            id: claims.service_account_id,
+            claims,
+            scopes: PhantomData,
+        })
+    }
+}
+
+/// Caller authenticated either as a user or as a service account,
+/// for handlers open to both. Displays as the subject of its token,
+/// along with the client id for service accounts.
+#[derive(Clone, Debug)]
+pub enum Principal<S = ()> {
+    User(User<S>),
+    ServiceAccount(ServiceAccount<S>),
+}
+
+#[async_trait]
+impl<S: Scopes> FromRequestParts<ServerState> for Principal<S> {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState,
+    ) -> Result<Self, Self::Rejection> {
+        match ServiceAccount::from_request_parts(parts, state).await {
+            Ok(service_account) => Ok(Self::ServiceAccount(service_account)),
+            Err(Error::Forbidden) => Err(Error::Forbidden),
+            Err(_) => {
+                User::from_request_parts(parts, state).await.map(Self::User)
+            }
+        }
+    }
+}
+
+impl<S> fmt::Display for Principal<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::User(user) => write!(f, "user-{}", user.id),
+            Self::ServiceAccount(service_account) => {
+                let ServiceAccount { id, claims, .. } = service_account;
+                write!(f, "service-account-{id} ({})", claims.client_id)
+            }
+        }
+    }
+}
@@ -1,37 +1,203 @@
+use std::marker::PhantomData;
+
+use anyhow::Context;
 use async_trait::async_trait;
-use axum::{extract::FromRequestParts, http::request::Parts, RequestPartsExt};
-use secrecy::ExposeSecret;
+use axum::{
+    extract::FromRequestParts,
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap},
+};
+use secrecy::{ExposeSecret, Secret};
 use tower_cookies::Cookies;
+use uuid::Uuid;
+
+use super::scope::{ensure_scopes, Scopes};
+use crate::{
+    config::auth::TokenTransport,
+    database::api_key,
+    server::ServerState,
+    services::{
+        cookie::CookieService,
+        token::{Claims, API_KEY_PREFIX},
+    },
+    telemetry, Error,
+};
+
+/// User authenticated with a valid access token or an API key.
+/// Handlers can inspect the session and the roles and scopes
+/// the user was authenticated with through their credential.
+/// Handlers declare the scopes they require with `S`,
+/// credentials missing any of them are rejected with 403.
+#[derive(Clone, Debug)]
+pub struct User<S = ()> {
+    pub id: i64,
+    pub credential: Credential,
+    scopes: PhantomData<fn() -> S>,
+}
+
+/// What the user authenticated with.
+#[derive(Clone, Debug)]
+pub enum Credential {
+    AccessToken(Claims),
+    ApiKey { id: Uuid, scopes: Vec<String> },
+}
+
+impl Credential {
+    /// Session the access token was issued for, API keys have none.
+    pub fn session_id(&self) -> Option<&Uuid> {
+        match self {
+            Self::AccessToken(claims) => Some(&claims.sid),
+            Self::ApiKey { .. } => None,
+        }
+    }
 
-use crate::{server::ServerState, telemetry, Error};
+    pub fn api_key_id(&self) -> Option<&Uuid> {
+        match self {
+            Self::AccessToken(_) => None,
+            Self::ApiKey { id, .. } => Some(id),
+        }
+    }
+
+    /// API keys carry no roles, only the scopes they were created with.
+    pub fn roles(&self) -> &[String] {
+        match self {
+            Self::AccessToken(claims) => &claims.roles,
+            Self::ApiKey { .. } => &[],
+        }
+    }
+
+    pub fn scopes(&self) -> &[String] {
+        match self {
+            Self::AccessToken(claims) => &claims.scopes,
+            Self::ApiKey { scopes, .. } => scopes,
+        }
+    }
+}
 
 #[derive(Clone, Copy, Debug, Default)]
-pub struct User {
+pub struct VerifiedUser {
     pub id: i64,
 }
 
 #[async_trait]
-impl FromRequestParts<ServerState> for User {
+impl<S: Scopes> FromRequestParts<ServerState> for User<S> {
     type Rejection = Error;
 
     async fn from_request_parts(
         parts: &mut Parts,
         state: &ServerState,
     ) -> Result<Self, Self::Rejection> {
-        let cookies = parts
-            .extract::<Cookies>()
-            .await
-            .map_err(|_| Error::NoAccessToken)?;
-        let access_token = state
-            .cookie_service
-            .get_access_token(&cookies)
-            .ok_or(Error::NoAccessToken)?;
-        let token_service = state.token_service.clone();
-        let id = telemetry::instrument_blocking_task(move || {
-            token_service.get_user_id(access_token.expose_secret())
+        // API keys are accepted whatever the token transport is
+        let (id, credential) = match api_key(&parts.headers) {
+            Some(api_key) => authenticate_api_key(&api_key, state).await?,
+            None => authenticate_access_token(parts, state).await?,
+        };
+        ensure_scopes::<S>(credential.scopes())?;
+        Ok(Self {
+            id,
+            credential,
+            scopes: PhantomData,
         })
+    }
+}
+
+async fn authenticate_access_token(
+    parts: &Parts,
+    state: &ServerState,
+) -> crate::Result<(i64, Credential)> {
+    let access_token = access_token(
+        &parts.headers,
+        parts.extensions.get::<Cookies>(),
+        state.auth_config.token_transport,
+        &state.cookie_service,
+    )
+    .ok_or(Error::NoAccessToken)?;
+    let token_service = state.token_service.clone();
+    let claims = telemetry::instrument_blocking_task(move || {
+        token_service.decode_access_token(access_token.expose_secret())
+    })
+    .await?
+    .map_err(|_| Error::InvalidAccessToken)?;
+    if state.access_token_denylist.is_revoked(&claims.jti) {
+        Err(Error::InvalidAccessToken).map_err(telemetry::warn)?;
+    }
+    Ok((claims.user_id, Credential::AccessToken(claims)))
+}
+
+async fn authenticate_api_key(
+    api_key: &Secret<String>,
+    state: &ServerState,
+) -> crate::Result<(i64, Credential)> {
+    let api_key = api_key::authenticate(api_key, &state.database_pool)
         .await?
-        .map_err(|_| Error::InvalidAccessToken)?;
+        .ok_or(Error::InvalidApiKey)
+        .map_err(telemetry::warn)?;
+    let credential = Credential::ApiKey {
+        id: api_key.id,
+        scopes: api_key.scopes,
+    };
+    Ok((api_key.user_id, credential))
+}
+
+#[async_trait]
+impl FromRequestParts<ServerState> for VerifiedUser {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState,
+    ) -> Result<Self, Self::Rejection> {
+        let User { id, .. } = <User>::from_request_parts(parts, state).await?;
+        let verified = sqlx::query!(
+            r#"
+            select verified
+            from users
+            where id = $1;
+            "#,
+            id
+        )
+        .fetch_optional(&state.database_pool)
+        .await
+        .context("Failed to select user verification status")?
+        .map(|r| r.verified)
+        .ok_or(Error::InvalidAccessToken)?;
+        if !verified {
+            return Err(Error::UnverifiedEmail);
+        }
         Ok(Self { id })
     }
 }
+
+/// Reads the access token from the `Authorization` header,
+/// falling back to the private cookie, as far as `transport` allows.
+pub fn access_token(
+    headers: &HeaderMap,
+    cookies: Option<&Cookies>,
+    transport: TokenTransport,
+    cookie_service: &CookieService,
+) -> Option<Secret<String>> {
+    let bearer_token = transport
+        .accepts_bearer()
+        .then(|| bearer_token(headers))
+        .flatten();
+    bearer_token.or_else(|| {
+        transport
+            .accepts_cookie()
+            .then(|| cookie_service.get_access_token(cookies?))
+            .flatten()
+    })
+}
+
+/// Reads an API key from the `Authorization` header,
+/// telling it apart from an access token by its prefix.
+fn api_key(headers: &HeaderMap) -> Option<Secret<String>> {
+    bearer_token(headers)
+        .filter(|token| token.expose_secret().starts_with(API_KEY_PREFIX))
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<Secret<String>> {
+    let header = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = header.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| Secret::new(token.trim().to_owned()))
+}
@@ -7,6 +7,7 @@ mod database;
 mod domain;
 mod error;
 mod extractors;
+mod middleware;
 mod server;
 mod services;
 
@@ -0,0 +1,396 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::OriginalUri,
+    http::Request,
+    response::{IntoResponse, Response},
+};
+use lru::LruCache;
+use secrecy::ExposeSecret;
+use tower::{Layer, Service};
+use tower_cookies::Cookies;
+
+use crate::{
+    config::{
+        auth::TokenTransport,
+        rate_limit::{Key, Rule},
+    },
+    error::Error,
+    extractors::{access_token, TrustedProxies},
+    services::{cookie::CookieService, token::TokenService},
+    telemetry,
+};
+
+/// Keeps the token buckets, so that they can live outside of the process
+/// when several instances of the server have to share the limits.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Takes a token from the bucket identified by `key`,
+    /// or returns how long to wait for the next one if the bucket is empty.
+    async fn acquire(
+        &self,
+        key: &str,
+        rule: &Rule,
+    ) -> anyhow::Result<Option<Duration>>;
+}
+
+/// Keeps a bounded number of buckets, dropping the least recently used one
+/// for a new key, so that neither memory nor the work under the lock
+/// grow with the number of clients.
+pub struct InMemoryStore {
+    buckets: Mutex<LruCache<String, Bucket>>,
+}
+
+const MAX_BUCKETS: usize = 10_000;
+
+impl InMemoryStore {
+    pub fn new(max_buckets: NonZeroUsize) -> Self {
+        Self {
+            buckets: Mutex::new(LruCache::new(max_buckets)),
+        }
+    }
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        Self::new(NonZeroUsize::new(MAX_BUCKETS).unwrap())
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn full(rule: &Rule, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(rule.capacity),
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, rule: &Rule, now: Instant) {
+        let elapsed = now.duration_since(self.updated_at);
+        let refilled =
+            elapsed.as_secs_f64() / rule.refill_interval.as_secs_f64();
+        self.tokens = (self.tokens + refilled).min(f64::from(rule.capacity));
+        self.updated_at = now;
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryStore {
+    async fn acquire(
+        &self,
+        key: &str,
+        rule: &Rule,
+    ) -> anyhow::Result<Option<Duration>> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets
+            .get_or_insert_mut(key.to_owned(), || Bucket::full(rule, now));
+        bucket.refill(rule, now);
+        if bucket.tokens >= 1.0 {
+            bucket.take();
+            return Ok(None);
+        }
+        let missing = 1.0 - bucket.tokens;
+        Ok(Some(rule.refill_interval.mul_f64(missing)))
+    }
+}
+
+/// Limits requests with the first rule whose prefix matches the path.
+/// Since it matches the original path, it can be attached
+/// to the router of any route group, not only to the root one.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiter: Arc<Limiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new<S: RateLimitStore + 'static>(
+        rules: Vec<Rule>,
+        store: S,
+        token_transport: TokenTransport,
+        cookie_service: CookieService,
+        token_service: TokenService,
+        trusted_proxies: TrustedProxies,
+    ) -> Self {
+        let limiter = Limiter {
+            rules,
+            store: Box::new(store),
+            token_transport,
+            cookie_service,
+            token_service,
+            trusted_proxies,
+        };
+        Self {
+            limiter: Arc::new(limiter),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    limiter: Arc<Limiter>,
+}
+
+impl<S, B> Service<Request<B>> for RateLimit<S>
+where
+    S: Service<Request<B>, Response = Response, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Infallible>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // the clone might not be ready, so the ready one is taken instead
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limiter = self.limiter.clone();
+        let bucket = limiter.bucket(&req);
+        Box::pin(async move {
+            let limited = match bucket {
+                Some((rule, key)) => limiter.acquire(rule, &key).await,
+                None => Ok(None),
+            };
+            match limited {
+                Ok(Some(retry_after)) => {
+                    let error =
+                        telemetry::warn(Error::RateLimited { retry_after });
+                    Ok(error.into_response())
+                }
+                Ok(None) => inner.call(req).await,
+                // an unavailable store should not take the whole API down
+                Err(e) => {
+                    tracing::error!("Skipping the rate limit: {e:?}");
+                    inner.call(req).await
+                }
+            }
+        })
+    }
+}
+
+struct Limiter {
+    rules: Vec<Rule>,
+    store: Box<dyn RateLimitStore>,
+    token_transport: TokenTransport,
+    cookie_service: CookieService,
+    token_service: TokenService,
+    trusted_proxies: TrustedProxies,
+}
+
+impl Limiter {
+    /// Returns the index of the matching rule and the key of the bucket.
+    fn bucket<B>(&self, req: &Request<B>) -> Option<(usize, String)> {
+        let path = match req.extensions().get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri.path(),
+            None => req.uri().path(),
+        };
+        let index = self.rules.iter().position(|r| matches(&r.prefix, path))?;
+        let rule = &self.rules[index];
+        // without a client key every client would share a single bucket
+        let client = self.client_key(rule.key, req)?;
+        Some((index, format!("{}:{client}", rule.prefix)))
+    }
+
+    async fn acquire(
+        &self,
+        rule: usize,
+        key: &str,
+    ) -> anyhow::Result<Option<Duration>> {
+        self.store.acquire(key, &self.rules[rule]).await
+    }
+
+    fn client_key<B>(&self, key: Key, req: &Request<B>) -> Option<String> {
+        let user_id = match key {
+            Key::User => self.user_id(req),
+            Key::Ip => None,
+        };
+        match user_id {
+            Some(id) => Some(format!("user:{id}")),
+            None => self
+                .trusted_proxies
+                .client_ip(req.headers(), req.extensions())
+                .map(|ip| format!("ip:{ip}")),
+        }
+    }
+
+    fn user_id<B>(&self, req: &Request<B>) -> Option<i64> {
+        let access_token = access_token(
+            req.headers(),
+            req.extensions().get::<Cookies>(),
+            self.token_transport,
+            &self.cookie_service,
+        )?;
+        self.token_service
+            .decode_access_token(access_token.expose_secret())
+            .map(|claims| claims.user_id)
+            .ok()
+    }
+}
+
+fn matches(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, num::NonZeroUsize, time::Duration};
+
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{header::RETRY_AFTER, Request, StatusCode},
+    };
+
+    use super::{matches, InMemoryStore, RateLimitStore};
+    use crate::{
+        config::rate_limit::{Key, Rule},
+        test_helpers::{TestServer, TestUser},
+        Pool,
+    };
+
+    #[test]
+    fn matches_whole_segments() {
+        assert!(matches("/auth/logout", "/auth/logout"));
+        assert!(matches("/auth/logout/", "/auth/logout"));
+        assert!(matches("/auth/login", "/auth/login/2fa"));
+        assert!(!matches("/auth/logout", "/auth/logout_all"));
+        assert!(!matches("/auth/logout", "/auth"));
+    }
+
+    #[tokio::test]
+    async fn refills_buckets_over_time() {
+        let store = InMemoryStore::default();
+        let rule = rule("/", Key::Ip, 2, Duration::from_millis(50));
+        assert_eq!(store.acquire("a", &rule).await.unwrap(), None);
+        assert_eq!(store.acquire("a", &rule).await.unwrap(), None);
+        let retry_after = store.acquire("a", &rule).await.unwrap().unwrap();
+        assert!(retry_after <= Duration::from_millis(50));
+        assert_eq!(store.acquire("b", &rule).await.unwrap(), None);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(store.acquire("a", &rule).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn drops_least_recently_used_bucket() {
+        let store = InMemoryStore::new(NonZeroUsize::new(2).unwrap());
+        let rule = rule("/", Key::Ip, 1, Duration::from_secs(60));
+        assert_eq!(store.acquire("a", &rule).await.unwrap(), None);
+        assert_eq!(store.acquire("b", &rule).await.unwrap(), None);
+        assert!(store.acquire("a", &rule).await.unwrap().is_some());
+        assert_eq!(store.acquire("c", &rule).await.unwrap(), None);
+        assert!(store.acquire("a", &rule).await.unwrap().is_some());
+        assert_eq!(store.acquire("b", &rule).await.unwrap(), None);
+    }
+
+    #[sqlx::test]
+    async fn limits_requests_by_ip(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.rate_limit.rules = vec![rule(
+                "/health_check",
+                Key::Ip,
+                1,
+                Duration::from_secs(60),
+            )];
+        })
+        .await;
+        let res = server.call(request("10.0.0.1")).await;
+        assert!(res.status().is_success());
+        let res = server.call(request("10.0.0.1")).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after = res.headers()[RETRY_AFTER].to_str().unwrap();
+        assert!(retry_after.parse::<u64>().unwrap() > 0);
+        let res = server.call(request("10.0.0.2")).await;
+        assert!(res.status().is_success());
+    }
+
+    #[sqlx::test]
+    async fn limits_requests_by_user(pool: Pool) {
+        let mut server = TestServer::with_config(pool, |config| {
+            config.rate_limit.rules = vec![rule(
+                "/health_check",
+                Key::User,
+                1,
+                Duration::from_secs(60),
+            )];
+        })
+        .await;
+        TestUser::enter_session(&mut server).await;
+        let res = server.call(request("10.0.0.1")).await;
+        assert!(res.status().is_success());
+        let res = server.call(request("10.0.0.2")).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        server.clear_cookies();
+        let res = server.call(request("10.0.0.2")).await;
+        assert!(res.status().is_success());
+    }
+
+    fn rule(
+        prefix: &str,
+        key: Key,
+        capacity: u32,
+        refill_interval: Duration,
+    ) -> Rule {
+        Rule {
+            prefix: prefix.into(),
+            key,
+            capacity,
+            refill_interval,
+        }
+    }
+
+    fn request(ip: &str) -> Request<Body> {
+        let peer = SocketAddr::new(ip.parse().unwrap(), 443);
+        Request::builder()
+            .method("GET")
+            .uri("/health_check")
+            .extension(ConnectInfo(peer))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
@@ -10,10 +10,12 @@ use tower_request_id::{RequestId, RequestIdLayer};
 
 use crate::{
     api,
-    config::Config,
+    config::{auth, Config},
+    extractors::TrustedProxies,
     services::{
-        cookie::CookieService, email::EmailClient, hash::PasswordHasher,
-        oauth::OauthClient, token::TokenService,
+        cookie::CookieService, denylist::AccessTokenDenylist,
+        email::EmailClient, hash::PasswordHasher, oauth::OauthClient,
+        token::TokenService, totp::TotpService, webauthn::WebauthnService,
     },
     Pool,
 };
@@ -21,12 +23,17 @@ use crate::{
 #[derive(Clone, FromRef)]
 pub struct ServerState {
     pub base_url: Url,
+    pub auth_config: auth::Config,
     pub oauth_client: OauthClient,
     pub token_service: TokenService,
+    pub totp_service: TotpService,
+    pub webauthn_service: WebauthnService,
     pub cookie_service: CookieService,
+    pub access_token_denylist: AccessTokenDenylist,
     pub database_pool: Pool,
     pub email_client: EmailClient,
     pub password_hasher: PasswordHasher,
+    pub trusted_proxies: TrustedProxies,
 }
 
 pub struct Server;
@@ -37,7 +44,7 @@ impl Server {
         let pool = Pool::connect_lazy_with(config.database.connect_options());
         let router = Self::router(config, pool)?;
         axum::Server::bind(&addr)
-            .serve(router.into_make_service())
+            .serve(router.into_make_service_with_connect_info::<SocketAddr>())
             .await
             .map_err(anyhow::Error::from)
     }
@@ -46,13 +53,36 @@ impl Server {
         config: Config,
         database_pool: Pool,
     ) -> anyhow::Result<Router> {
+        config.auth.validate_openid_connect()?;
+        config.rate_limit.validate()?;
         let hmac_secret = config.server.hmac_secret.expose_secret().as_bytes();
+        let accept_legacy_keys = config.server.accept_legacy_keys;
+        let trusted_proxies = config.server.trusted_proxies();
         let base_url = config.server.base_url;
+        let auth_config = config.auth.clone();
         let email_client = config.email_client.client();
-        let password_hasher = config.password_hasher.hasher(hmac_secret)?;
-        let cookie_service = config.auth.cookie_service(hmac_secret)?;
-        let token_service = config.auth.token_service(hmac_secret);
+        let password_hasher = config
+            .password_hasher
+            .hasher(hmac_secret, accept_legacy_keys)?;
+        let keyring = config
+            .keyring
+            .keyring(&config.server.hmac_secret, accept_legacy_keys)?;
+        let cookie_service =
+            config.auth.cookie_service(&keyring, accept_legacy_keys)?;
+        let totp_service =
+            config.auth.totp_service(hmac_secret, accept_legacy_keys);
+        let webauthn_service = config.auth.webauthn_service(&base_url)?;
+        let access_token_denylist =
+            config.auth.access_token_denylist(database_pool.clone())?;
+        access_token_denylist.spawn_sync();
+        let token_service = config.auth.token_service(&keyring)?;
         let oauth_client = config.oauth.oauth_client(&base_url)?;
+        let rate_limit_layer = config.rate_limit.layer(
+            auth_config.token_transport,
+            cookie_service.clone(),
+            token_service.clone(),
+            trusted_proxies.clone(),
+        );
 
         let trace_layer = TraceLayer::new_for_http().make_span_with(
             |request: &Request<Body>| {
@@ -72,18 +102,26 @@ impl Server {
 
         let state = ServerState {
             base_url,
+            auth_config,
             oauth_client,
             token_service,
+            totp_service,
+            webauthn_service,
             cookie_service,
+            access_token_denylist,
             database_pool,
             email_client,
             password_hasher,
+            trusted_proxies,
         };
         let mw = ServiceBuilder::new()
             .layer(CookieManagerLayer::new())
             .layer(RequestIdLayer)
             .layer(trace_layer);
 
-        Ok(api::router().with_state(state).layer(mw))
+        Ok(api::router()
+            .layer(rate_limit_layer)
+            .with_state(state)
+            .layer(mw))
     }
 }
@@ -1,52 +1,89 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
 use secrecy::{ExposeSecret, Secret};
-use tower_cookies::{cookie::time::Duration, Cookie, Cookies, Key};
+use tower_cookies::{
+    cookie::{time::Duration, SameSite},
+    Cookie, Cookies, Key,
+};
+
+use crate::services::{
+    key_derivation::{derive_key, Purpose},
+    keyring::Keyring,
+};
 
 const ACCESS_TOKEN_KEY: &str = "access_token";
 const REFRESH_TOKEN_KEY: &str = "refresh_token";
+const ACCESS_TOKEN_PATH: &str = "/";
+const REFRESH_TOKEN_PATH: &str = "/auth";
+const LEGACY_KEY_SUFFIX: &str = "/legacy";
 
+/// Cookies are encrypted with keys derived from the keyring secrets.
+/// While `accept_legacy_keys` is set, cookies encrypted with the secrets
+/// themselves are accepted like those of retired keys
+/// and encrypted again with the primary key.
+/// Cookies are `SameSite=Lax`, so that forms posted from other sites,
+/// like a forged OAuth consent, do not carry the session.
 #[derive(Clone)]
 pub struct CookieService {
-    key: Key,
+    keys: Keyring<Key>,
     access_token_ttl: Duration,
     refresh_token_ttl: Duration,
 }
 
 impl CookieService {
     pub fn new(
-        secret: &[u8],
+        secrets: &Keyring<Secret<String>>,
         access_token_ttl: std::time::Duration,
         refresh_token_ttl: std::time::Duration,
+        accept_legacy_keys: bool,
     ) -> anyhow::Result<Self> {
         let access_token_ttl =
             Duration::new(access_token_ttl.as_secs().try_into()?, 0);
         let refresh_token_ttl =
             Duration::new(refresh_token_ttl.as_secs().try_into()?, 0);
-        let key = Key::from(secret);
+        let mut keys = HashMap::new();
+        for (id, secret) in secrets.iter() {
+            let secret = secret.expose_secret().as_bytes();
+            let legacy_key = Key::try_from(secret)
+                .with_context(|| format!("Cookie key `{id}` is too short"))?;
+            let key = derive_key(secret, Purpose::CookieEncryption);
+            keys.insert(
+                id.to_owned(),
+                Key::from(key.expose_secret().as_slice()),
+            );
+            if accept_legacy_keys {
+                keys.insert(format!("{id}{LEGACY_KEY_SUFFIX}"), legacy_key);
+            }
+        }
+        let keys = Keyring::new(secrets.primary_id().to_owned(), keys)?;
         Ok(Self {
-            key,
+            keys,
             access_token_ttl,
             refresh_token_ttl,
         })
     }
 
     pub fn set_access_token(&self, cookies: &Cookies, token: Secret<String>) {
-        cookies.private(&self.key).add(
+        cookies.private(self.keys.primary()).add(
             Cookie::build(ACCESS_TOKEN_KEY, token.expose_secret().to_owned())
-                .path("/")
+                .path(ACCESS_TOKEN_PATH)
                 .max_age(self.access_token_ttl)
                 .http_only(true)
                 .secure(true)
+                .same_site(SameSite::Lax)
                 .finish(),
         );
     }
 
     pub fn set_refresh_token(&self, cookies: &Cookies, token: Secret<String>) {
-        cookies.private(&self.key).add(
+        cookies.private(self.keys.primary()).add(
             Cookie::build(REFRESH_TOKEN_KEY, token.expose_secret().to_owned())
-                .path("/auth/refresh")
+                .path(REFRESH_TOKEN_PATH)
                 .max_age(self.refresh_token_ttl)
                 .http_only(true)
                 .secure(true)
+                .same_site(SameSite::Lax)
                 .finish(),
         );
     }
@@ -55,21 +92,56 @@ impl CookieService {
         &self,
         cookies: &Cookies,
     ) -> Option<Secret<String>> {
-        cookies
-            .private(&self.key)
-            .get(ACCESS_TOKEN_KEY)
-            .map(|c| c.value().into())
-            .map(Secret::new)
+        let (token, is_stale) = self.get_private(cookies, ACCESS_TOKEN_KEY)?;
+        if is_stale {
+            self.set_access_token(cookies, token.clone());
+        }
+        Some(token)
     }
 
     pub fn get_refresh_token(
         &self,
         cookies: &Cookies,
     ) -> Option<Secret<String>> {
-        cookies
-            .private(&self.key)
-            .get(REFRESH_TOKEN_KEY)
-            .map(|c| c.value().into())
-            .map(Secret::new)
+        let (token, is_stale) = self.get_private(cookies, REFRESH_TOKEN_KEY)?;
+        if is_stale {
+            self.set_refresh_token(cookies, token.clone());
+        }
+        Some(token)
+    }
+
+    pub fn remove_access_token(&self, cookies: &Cookies) {
+        cookies.private(self.keys.primary()).remove(
+            Cookie::build(ACCESS_TOKEN_KEY, "")
+                .path(ACCESS_TOKEN_PATH)
+                .finish(),
+        );
+    }
+
+    pub fn remove_refresh_token(&self, cookies: &Cookies) {
+        cookies.private(self.keys.primary()).remove(
+            Cookie::build(REFRESH_TOKEN_KEY, "")
+                .path(REFRESH_TOKEN_PATH)
+                .finish(),
+        );
+    }
+
+    /// Decrypts the cookie with any key in the ring and tells
+    /// whether it has to be encrypted again with the primary key.
+    fn get_private(
+        &self,
+        cookies: &Cookies,
+        name: &str,
+    ) -> Option<(Secret<String>, bool)> {
+        if let Some(cookie) = cookies.private(self.keys.primary()).get(name) {
+            return Some((Secret::new(cookie.value().into()), false));
+        }
+        self.keys.retired().find_map(|(id, key)| {
+            let cookie = cookies.private(key).get(name)?;
+            if id.ends_with(LEGACY_KEY_SUFFIX) {
+                tracing::warn!("Decrypted {name} cookie with legacy key");
+            }
+            Some((Secret::new(cookie.value().into()), true))
+        })
     }
 }
@@ -0,0 +1,90 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use time::OffsetDateTime;
+use tokio::time::MissedTickBehavior;
+use uuid::Uuid;
+
+use crate::{
+    database::revoked_access_token::{self, RevokedAccessToken},
+    Pool,
+};
+
+/// Access tokens revoked before their expiry, identified by their `jti`.
+/// The database is the source of truth shared by every instance,
+/// while the copy kept in memory catches up with it every `sync_interval`
+/// in the background, so that requests never wait for the database.
+/// Entries are dropped from both once their tokens would expire anyway.
+#[derive(Clone)]
+pub struct AccessTokenDenylist {
+    pool: Pool,
+    sync_interval: Duration,
+    leeway: Duration,
+    entries: Arc<Mutex<HashMap<Uuid, OffsetDateTime>>>,
+}
+
+impl AccessTokenDenylist {
+    pub fn new(pool: Pool, sync_interval: Duration, leeway: Duration) -> Self {
+        Self {
+            pool,
+            sync_interval,
+            leeway,
+            entries: Arc::default(),
+        }
+    }
+
+    pub fn is_revoked(&self, jti: &Uuid) -> bool {
+        self.entries.lock().unwrap().contains_key(jti)
+    }
+
+    /// Makes the tokens revoked by this instance take effect immediately,
+    /// without waiting for the next sync.
+    pub fn extend(&self, revoked: &[RevokedAccessToken]) {
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .extend(revoked.iter().map(|token| (token.jti, token.expires_at)));
+    }
+
+    /// Starts syncing with the database right away and then
+    /// every `sync_interval`, until every copy of the denylist is dropped.
+    /// A failed sync keeps the entries there are and is retried next time.
+    pub fn spawn_sync(&self) {
+        let entries = Arc::downgrade(&self.entries);
+        let pool = self.pool.clone();
+        let leeway = self.leeway;
+        let mut interval = tokio::time::interval(self.sync_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+                let Some(entries) = entries.upgrade() else {
+                    break;
+                };
+                sync(&entries, leeway, &pool).await.ok();
+            }
+        });
+    }
+}
+
+#[tracing::instrument(
+    name = "Sync access token denylist",
+    skip_all,
+    err(Debug)
+)]
+async fn sync(
+    entries: &Mutex<HashMap<Uuid, OffsetDateTime>>,
+    leeway: Duration,
+    pool: &Pool,
+) -> anyhow::Result<()> {
+    revoked_access_token::delete_expired(leeway, pool).await?;
+    let revoked = revoked_access_token::list(leeway, pool).await?;
+    let expired_before = OffsetDateTime::now_utc() - leeway;
+    let mut entries = entries.lock().unwrap();
+    // revocations never get undone, so entries are only ever merged
+    entries.retain(|_, expires_at| *expires_at > expired_before);
+    entries.extend(revoked.iter().map(|token| (token.jti, token.expires_at)));
+    Ok(())
+}
@@ -1,16 +1,33 @@
 use anyhow::Context;
 use argon2::{
-    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash,
-    PasswordHasher as Hasher, PasswordVerifier, Version,
+    password_hash::SaltString, Algorithm, Argon2, Params, ParamsBuilder,
+    PasswordHash, PasswordHasher as Hasher, PasswordVerifier, Version,
 };
 use secrecy::{CloneableSecret, ExposeSecret, Secret, Zeroize};
 
+use crate::services::key_derivation::{derive_key, Purpose};
+
+/// Stored as the Argon2 `keyid` of new hashes,
+/// so that they can be told apart from the legacy ones.
+const PEPPER_KEY_ID: &[u8] = b"hkdf1";
+
 #[derive(Clone, Debug)]
 struct HmacKey(Vec<u8>);
 
+/// New hashes are peppered with a key derived from the secret
+/// and carry its id in their params.
+///
+/// Migration: hashes without a key id were peppered with the secret itself.
+/// While `accept_legacy_keys` is set they keep verifying with the legacy
+/// pepper, with a warning, and `needs_rehash` tells to replace them,
+/// which login does as soon as the password is known.
+/// Hashes that are still legacy can be found with
+/// `select id from users where password_hash not like '%keyid=%'`,
+/// once there are none the setting should be turned off.
 #[derive(Clone)]
 pub struct PasswordHasher {
-    hmac_secret: Secret<HmacKey>,
+    pepper: Secret<HmacKey>,
+    legacy_pepper: Option<Secret<HmacKey>>,
     params: Params,
 }
 
@@ -20,12 +37,24 @@ impl PasswordHasher {
         m_cost: u32,
         t_cost: u32,
         p_cost: u32,
+        accept_legacy_keys: bool,
     ) -> anyhow::Result<Self> {
-        let hmac_secret = Secret::new(HmacKey(secret.to_vec()));
-        let params = Params::new(m_cost, t_cost, p_cost, None)
+        let pepper = derive_key(secret, Purpose::PasswordPepper);
+        let pepper = Secret::new(HmacKey(pepper.expose_secret().clone()));
+        let legacy_pepper =
+            accept_legacy_keys.then(|| Secret::new(HmacKey(secret.to_vec())));
+        let mut params = ParamsBuilder::new();
+        params
+            .m_cost(m_cost)
+            .and_then(|p| p.t_cost(t_cost))
+            .and_then(|p| p.p_cost(p_cost))
+            .and_then(|p| p.keyid(PEPPER_KEY_ID))
             .context("Failed to create Argon2 params")?;
+        let params =
+            params.params().context("Failed to create Argon2 params")?;
         Ok(Self {
-            hmac_secret,
+            pepper,
+            legacy_pepper,
             params,
         })
     }
@@ -36,7 +65,7 @@ impl PasswordHasher {
         password: &Secret<String>,
     ) -> anyhow::Result<Secret<String>> {
         let salt = SaltString::generate(&mut rand::thread_rng());
-        self.hasher()?
+        self.hasher(&self.pepper)?
             .hash_password(password.expose_secret().as_bytes(), &salt)
             .map(|h| h.to_string())
             .map(Secret::new)
@@ -52,24 +81,52 @@ impl PasswordHasher {
         let password = password.expose_secret().as_bytes();
         let password_hash = PasswordHash::new(password_hash.expose_secret())
             .context("Failed to parse hash in PHC string format.")?;
-        match self.hasher()?.verify_password(password, &password_hash) {
+        let pepper = if is_legacy(&password_hash)? {
+            match &self.legacy_pepper {
+                Some(legacy_pepper) => {
+                    tracing::warn!("Verifying password with legacy pepper");
+                    legacy_pepper
+                }
+                None => return Ok(false),
+            }
+        } else {
+            &self.pepper
+        };
+        match self
+            .hasher(pepper)?
+            .verify_password(password, &password_hash)
+        {
             Ok(_) => Ok(true),
             Err(_) => Ok(false),
         }
     }
 
+    /// Tells whether the hash was peppered with the legacy pepper
+    /// and should be replaced the next time the password is known.
+    pub fn needs_rehash(
+        &self,
+        password_hash: &Secret<String>,
+    ) -> anyhow::Result<bool> {
+        let password_hash = PasswordHash::new(password_hash.expose_secret())
+            .context("Failed to parse hash in PHC string format.")?;
+        is_legacy(&password_hash)
+    }
+
     pub fn mock_password_hash(&self) -> Secret<String> {
         Secret::new(
-            "$argon2id$v=19$m=4096,t=3,p=1\
+            "$argon2id$v=19$m=4096,t=3,p=1,keyid=aGtkZjE\
                 $0000000000000000000000\
                 $0000000000000000000000000000000000000000000"
                 .to_owned(),
         )
     }
 
-    fn hasher(&self) -> anyhow::Result<Argon2<'_>> {
+    fn hasher<'k>(
+        &self,
+        pepper: &'k Secret<HmacKey>,
+    ) -> anyhow::Result<Argon2<'k>> {
         Argon2::new_with_secret(
-            &self.hmac_secret.expose_secret().0,
+            &pepper.expose_secret().0,
             Algorithm::default(),
             Version::default(),
             self.params.clone(),
@@ -78,9 +135,85 @@ impl PasswordHasher {
     }
 }
 
+fn is_legacy(password_hash: &PasswordHash) -> anyhow::Result<bool> {
+    Params::try_from(password_hash)
+        .map(|params| params.keyid().is_empty())
+        .context("Failed to read Argon2 params from the hash")
+}
+
 impl CloneableSecret for HmacKey {}
 impl Zeroize for HmacKey {
     fn zeroize(&mut self) {
         self.0.zeroize()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use argon2::{
+        password_hash::SaltString, Argon2, Params, PasswordHash,
+        PasswordHasher as _, PasswordVerifier,
+    };
+    use secrecy::{ExposeSecret, Secret};
+
+    use super::PasswordHasher;
+
+    const SECRET: &[u8] = b"secret";
+
+    #[test]
+    fn verifies_hashes_peppered_with_legacy_pepper() {
+        let hasher = hasher();
+        let password = Secret::new("password".to_owned());
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let legacy_hash = legacy_hasher()
+            .hash_password(password.expose_secret().as_bytes(), &salt)
+            .unwrap();
+        let legacy_hash = Secret::new(legacy_hash.to_string());
+        assert!(hasher.verify_password(&password, &legacy_hash).unwrap());
+        assert!(hasher.needs_rehash(&legacy_hash).unwrap());
+        let invalid_password = Secret::new("invalid".to_owned());
+        assert!(!hasher
+            .verify_password(&invalid_password, &legacy_hash)
+            .unwrap());
+    }
+
+    #[test]
+    fn rejects_legacy_hashes_once_legacy_keys_are_off() {
+        let hasher = PasswordHasher::new(SECRET, 4096, 3, 1, false).unwrap();
+        let password = Secret::new("password".to_owned());
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let legacy_hash = legacy_hasher()
+            .hash_password(password.expose_secret().as_bytes(), &salt)
+            .unwrap();
+        let legacy_hash = Secret::new(legacy_hash.to_string());
+        assert!(!hasher.verify_password(&password, &legacy_hash).unwrap());
+    }
+
+    #[test]
+    fn peppers_new_hashes_with_derived_key() {
+        let hasher = hasher();
+        let password = Secret::new("password".to_owned());
+        let hash = hasher.hash_password(&password).unwrap();
+        assert!(hasher.verify_password(&password, &hash).unwrap());
+        assert!(!hasher.needs_rehash(&hash).unwrap());
+        let hash = PasswordHash::new(hash.expose_secret()).unwrap();
+        assert!(legacy_hasher()
+            .verify_password(password.expose_secret().as_bytes(), &hash)
+            .is_err());
+    }
+
+    fn hasher() -> PasswordHasher {
+        PasswordHasher::new(SECRET, 4096, 3, 1, true).unwrap()
+    }
+
+    fn legacy_hasher() -> Argon2<'static> {
+        let params = Params::new(4096, 3, 1, None).unwrap();
+        Argon2::new_with_secret(
+            SECRET,
+            Default::default(),
+            Default::default(),
+            params,
+        )
+        .unwrap()
+    }
+}
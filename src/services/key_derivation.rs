@@ -0,0 +1,80 @@
+use hkdf::Hkdf;
+use secrecy::Secret;
+use sha2::Sha256;
+
+/// What a derived key is used for. Every purpose has its own label,
+/// so keys derived from the same secret are independent of each other
+/// and leaking one of them does not expose the rest or the secret.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Purpose {
+    PasswordPepper,
+    TotpEncryption,
+    CookieEncryption,
+    JwtSigning,
+}
+
+impl Purpose {
+    /// Changing a label changes the derived key,
+    /// so labels are versioned instead of being edited in place.
+    fn label(self) -> &'static [u8] {
+        match self {
+            Self::PasswordPepper => b"password-pepper/v1",
+            Self::TotpEncryption => b"totp-encryption/v1",
+            Self::CookieEncryption => b"cookie-encryption/v1",
+            Self::JwtSigning => b"jwt-signing/v1",
+        }
+    }
+
+    fn key_length(self) -> usize {
+        match self {
+            Self::CookieEncryption => 64,
+            Self::PasswordPepper | Self::TotpEncryption | Self::JwtSigning => {
+                32
+            }
+        }
+    }
+}
+
+/// Derives a subkey for `purpose` with HKDF-SHA256 (RFC 5869),
+/// using the purpose label as the info and no salt.
+pub fn derive_key(secret: &[u8], purpose: Purpose) -> Secret<Vec<u8>> {
+    Secret::new(hkdf(secret, purpose.label(), purpose.key_length()))
+}
+
+fn hkdf(ikm: &[u8], info: &[u8], length: usize) -> Vec<u8> {
+    let mut okm = vec![0; length];
+    Hkdf::<Sha256>::new(None, ikm)
+        .expand(info, &mut okm)
+        .expect("key length is within HKDF-SHA256 output limit");
+    okm
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::ExposeSecret;
+
+    use super::{derive_key, hkdf, Purpose};
+
+    #[test]
+    fn matches_rfc_5869_test_vector() {
+        let okm = hkdf(&[0x0b; 22], &[], 42);
+        assert_eq!(
+            hex::encode(okm),
+            "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d\
+             9d201395faa4b61a96c8"
+        );
+    }
+
+    #[test]
+    fn derives_different_keys_per_purpose() {
+        let secret = b"secret";
+        let pepper = derive_key(secret, Purpose::PasswordPepper);
+        let totp = derive_key(secret, Purpose::TotpEncryption);
+        let jwt = derive_key(secret, Purpose::JwtSigning);
+        assert_ne!(pepper.expose_secret(), totp.expose_secret());
+        assert_ne!(pepper.expose_secret(), jwt.expose_secret());
+        assert_ne!(totp.expose_secret(), jwt.expose_secret());
+        let cookie = derive_key(secret, Purpose::CookieEncryption);
+        assert_eq!(cookie.expose_secret().len(), 64);
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use anyhow::{ensure, Context};
+
+/// Id under which `hmac_secret` stays in the ring, as it protected
+/// cookies and signed tokens without a key id before the keyring.
+pub const LEGACY_KEY_ID: &str = "legacy";
+
+/// Keys identified by their ids, one of which is the primary one.
+/// New tokens and cookies are protected with the primary key,
+/// while the rest are only accepted until they are removed from the ring.
+#[derive(Clone, Debug)]
+pub struct Keyring<K> {
+    primary_id: String,
+    keys: HashMap<String, K>,
+}
+
+impl<K> Keyring<K> {
+    pub fn new(
+        primary_id: String,
+        keys: HashMap<String, K>,
+    ) -> anyhow::Result<Self> {
+        ensure!(
+            keys.contains_key(&primary_id),
+            "Primary key `{primary_id}` is missing from the keyring"
+        );
+        Ok(Self { primary_id, keys })
+    }
+
+    pub fn primary_id(&self) -> &str {
+        &self.primary_id
+    }
+
+    pub fn primary(&self) -> &K {
+        &self.keys[&self.primary_id]
+    }
+
+    /// Adds a retired key, which must not replace an existing one.
+    pub fn insert(&mut self, id: String, key: K) {
+        debug_assert!(!self.keys.contains_key(&id));
+        self.keys.insert(id, key);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&K> {
+        self.keys.get(id)
+    }
+
+    /// Iterates over the keys that are no longer used for new tokens.
+    pub fn retired(&self) -> impl Iterator<Item = (&str, &K)> {
+        self.iter().filter(|(id, _)| *id != self.primary_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &K)> {
+        self.keys.iter().map(|(id, key)| (id.as_str(), key))
+    }
+
+    pub fn try_map<T, F>(&self, mut f: F) -> anyhow::Result<Keyring<T>>
+    where
+        F: FnMut(&str, &K) -> anyhow::Result<T>,
+    {
+        let keys = self
+            .keys
+            .iter()
+            .map(|(id, key)| {
+                f(id, key)
+                    .with_context(|| format!("Failed to load key `{id}`"))
+                    .map(|key| (id.clone(), key))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Keyring {
+            primary_id: self.primary_id.clone(),
+            keys,
+        })
+    }
+}
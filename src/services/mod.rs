@@ -1,5 +1,11 @@
 pub mod cookie;
+pub mod denylist;
 pub mod email;
 pub mod hash;
+pub mod key_derivation;
+pub mod keyring;
 pub mod oauth;
+pub mod signing_key;
 pub mod token;
+pub mod totp;
+pub mod webauthn;
@@ -0,0 +1,150 @@
+use anyhow::{anyhow, Context};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+        RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey,
+};
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair};
+
+/// Key pair that access tokens are signed and verified with.
+/// Asymmetric keys also come with a public JWK
+/// for the services that only need to verify the tokens.
+#[derive(Clone)]
+pub struct SigningKey {
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    public_jwk: Option<Jwk>,
+}
+
+impl SigningKey {
+    pub fn hs256(secret: &[u8]) -> Self {
+        Self {
+            algorithm: Algorithm::HS256,
+            encoding_key: EncodingKey::from_secret(secret),
+            decoding_key: DecodingKey::from_secret(secret),
+            public_jwk: None,
+        }
+    }
+
+    /// Accepts both PKCS#1 and PKCS#8 private keys.
+    pub fn rs256_from_pem(pem: &[u8]) -> anyhow::Result<Self> {
+        let encoding_key = EncodingKey::from_rsa_pem(pem)
+            .context("Failed to parse RSA private key")?;
+        let der = pem_to_der(pem)?;
+        let key_pair = RsaKeyPair::from_pkcs8(&der)
+            .or_else(|_| RsaKeyPair::from_der(&der))
+            .map_err(|e| anyhow!("Failed to parse RSA private key: {e}"))?;
+        let public_key = key_pair.public_key();
+        let modulus = public_key.modulus();
+        let exponent = public_key.exponent();
+        let n = modulus.big_endian_without_leading_zero();
+        let e = exponent.big_endian_without_leading_zero();
+        let parameters = AlgorithmParameters::RSA(RSAKeyParameters {
+            key_type: RSAKeyType::RSA,
+            n: URL_SAFE_NO_PAD.encode(n),
+            e: URL_SAFE_NO_PAD.encode(e),
+        });
+        Ok(Self {
+            algorithm: Algorithm::RS256,
+            encoding_key,
+            decoding_key: DecodingKey::from_rsa_raw_components(n, e),
+            public_jwk: Some(public_jwk(Algorithm::RS256, parameters)),
+        })
+    }
+
+    /// Accepts PKCS#8 Ed25519 private keys,
+    /// as generated by `openssl genpkey -algorithm ed25519`.
+    pub fn eddsa_from_pem(pem: &[u8]) -> anyhow::Result<Self> {
+        let encoding_key = EncodingKey::from_ed_pem(pem)
+            .context("Failed to parse Ed25519 private key")?;
+        let der = pem_to_der(pem)?;
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+            .map_err(|e| anyhow!("Failed to parse Ed25519 private key: {e}"))?;
+        let x = URL_SAFE_NO_PAD.encode(key_pair.public_key());
+        let decoding_key = DecodingKey::from_ed_components(&x)
+            .context("Failed to build Ed25519 public key")?;
+        let parameters =
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x,
+            });
+        Ok(Self {
+            algorithm: Algorithm::EdDSA,
+            encoding_key,
+            decoding_key,
+            public_jwk: Some(public_jwk(Algorithm::EdDSA, parameters)),
+        })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.encoding_key
+    }
+
+    pub fn decoding_key(&self) -> &DecodingKey {
+        &self.decoding_key
+    }
+
+    /// Returns nothing for symmetric keys, as those must stay secret.
+    pub fn public_jwk(&self) -> Option<&Jwk> {
+        self.public_jwk.as_ref()
+    }
+}
+
+fn public_jwk(algorithm: Algorithm, parameters: AlgorithmParameters) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            algorithm: Some(algorithm),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    }
+}
+
+fn pem_to_der(pem: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let pem = std::str::from_utf8(pem).context("PEM is not valid UTF-8")?;
+    let body = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+        .collect::<String>();
+    STANDARD.decode(body).context("Failed to decode PEM")
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{jwk::AlgorithmParameters, Algorithm};
+
+    use super::SigningKey;
+    use crate::test_helpers::ed25519_pem;
+
+    #[test]
+    fn publishes_ed25519_public_key() {
+        let key = SigningKey::eddsa_from_pem(ed25519_pem().as_bytes()).unwrap();
+        assert_eq!(key.algorithm(), Algorithm::EdDSA);
+        let jwk = key.public_jwk().unwrap();
+        assert!(matches!(
+            jwk.algorithm,
+            AlgorithmParameters::OctetKeyPair(_)
+        ));
+    }
+
+    #[test]
+    fn keeps_hmac_secret_private() {
+        let key = SigningKey::hs256(b"secret");
+        assert!(key.public_jwk().is_none());
+    }
+}
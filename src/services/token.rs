@@ -1,44 +1,194 @@
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::Context;
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, Header, Validation};
 use oauth2::url::Host;
 use rand::{distributions::Alphanumeric, Rng};
-use secrecy::Secret;
-use serde::{Deserialize, Serialize};
+use secrecy::{ExposeSecret, Secret};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::services::{
+    keyring::{Keyring, LEGACY_KEY_ID},
+    signing_key::SigningKey,
+};
+
+/// Tells API keys apart from access tokens, which are JWTs.
+pub const API_KEY_PREFIX: &str = "ak_";
 
 #[derive(Clone)]
 pub struct TokenService {
-    algorithm: Algorithm,
     issuer: Host<String>,
     audience: Host<String>,
     token_ttl: Duration,
-    encoding_key: EncodingKey,
-    decoding_key: DecodingKey,
+    refresh_token_ttl: Duration,
+    service_token_ttl: Duration,
+    leeway: Duration,
+    signing_keys: Keyring<SigningKey>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Kind of principal a token was issued to, so that tokens
+/// of service accounts are never mistaken for tokens of users.
+/// Every token must state it, rather than being told apart by its shape.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubjectType {
+    User,
+    ServiceAccount,
+}
+
+/// Claims of an access token. `sid` is the session that issued the token,
+/// while `roles` and `scopes` limit what the token can be used for.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Claims {
-    aud: String,
-    iat: usize,
-    exp: usize,
-    iss: String,
-    sub: String,
-    user_id: i64,
+    pub aud: String,
+    pub iat: u64,
+    pub nbf: u64,
+    pub exp: u64,
+    pub iss: String,
+    pub sub: String,
+    pub sub_type: SubjectType,
+    pub jti: Uuid,
+    pub sid: Uuid,
+    pub user_id: i64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    #[serde(
+        default,
+        rename = "scope",
+        with = "space_delimited",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub scopes: Vec<String>,
 }
 
 impl Claims {
-    fn new(user_id: i64, aud: String, iss: String, ttl: Duration) -> Self {
+    fn new(
+        user_id: i64,
+        session_id: Uuid,
+        roles: Vec<String>,
+        scopes: Vec<String>,
+        aud: String,
+        iss: String,
+        ttl: Duration,
+    ) -> Self {
         let iat = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
         let exp = iat + ttl;
         Self {
             aud,
-            iat: iat.as_secs() as usize,
-            exp: exp.as_secs() as usize,
+            iat: iat.as_secs(),
+            nbf: iat.as_secs(),
+            exp: exp.as_secs(),
             iss,
             sub: format!("user-{user_id}"),
+            sub_type: SubjectType::User,
+            jti: Uuid::new_v4(),
+            sid: session_id,
             user_id,
+            roles,
+            scopes,
+        }
+    }
+}
+
+/// Claims of an access token issued to a service account
+/// through the client credentials grant.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceClaims {
+    pub aud: String,
+    pub iat: u64,
+    pub nbf: u64,
+    pub exp: u64,
+    pub iss: String,
+    pub sub: String,
+    pub sub_type: SubjectType,
+    pub jti: Uuid,
+    pub service_account_id: i64,
+    pub client_id: String,
+    #[serde(
+        default,
+        rename = "scope",
+        with = "space_delimited",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub scopes: Vec<String>,
+}
+
+impl ServiceClaims {
+    fn new(
+        service_account_id: i64,
+        client_id: String,
+        scopes: Vec<String>,
+        aud: String,
+        iss: String,
+        ttl: Duration,
+    ) -> Self {
+        let iat = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let exp = iat + ttl;
+        Self {
+            aud,
+            iat: iat.as_secs(),
+            nbf: iat.as_secs(),
+            exp: exp.as_secs(),
+            iss,
+            sub: format!("service-account-{service_account_id}"),
+            sub_type: SubjectType::ServiceAccount,
+            jti: Uuid::new_v4(),
+            service_account_id,
+            client_id,
+            scopes,
+        }
+    }
+}
+
+/// Standard claims about the user, disclosed as far as the scopes allow,
+/// both in ID tokens and by the userinfo endpoint.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
+
+/// Claims of an OpenID Connect ID token. Unlike access tokens,
+/// it is addressed to the client and issued by the base URL.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IdClaims {
+    pub aud: String,
+    pub iat: u64,
+    pub exp: u64,
+    pub iss: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub user_info: UserInfo,
+}
+
+impl IdClaims {
+    fn new(
+        user_info: UserInfo,
+        nonce: Option<String>,
+        aud: String,
+        iss: String,
+        ttl: Duration,
+    ) -> Self {
+        let iat = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let exp = iat + ttl;
+        Self {
+            aud,
+            iat: iat.as_secs(),
+            exp: exp.as_secs(),
+            iss,
+            nonce,
+            user_info,
         }
     }
 }
@@ -48,17 +198,19 @@ impl TokenService {
         issuer: Host<String>,
         audience: Host<String>,
         token_ttl: Duration,
-        secret: &[u8],
+        refresh_token_ttl: Duration,
+        service_token_ttl: Duration,
+        leeway: Duration,
+        signing_keys: Keyring<SigningKey>,
     ) -> Self {
-        let encoding_key = EncodingKey::from_secret(secret);
-        let decoding_key = DecodingKey::from_secret(secret);
         Self {
-            algorithm: Algorithm::HS256,
             issuer,
             audience,
             token_ttl,
-            encoding_key,
-            decoding_key,
+            refresh_token_ttl,
+            service_token_ttl,
+            leeway,
+            signing_keys,
         }
     }
 
@@ -66,39 +218,467 @@ impl TokenService {
     pub fn generate_access_token(
         &self,
         user_id: i64,
-    ) -> anyhow::Result<Secret<String>> {
+        session_id: Uuid,
+        roles: Vec<String>,
+        scopes: Vec<String>,
+    ) -> anyhow::Result<(Secret<String>, Claims)> {
         let claims = Claims::new(
             user_id,
+            session_id,
+            roles,
+            scopes,
             self.audience.to_string(),
             self.issuer.to_string(),
             self.token_ttl,
         );
-        jsonwebtoken::encode(
-            &Header::new(self.algorithm),
-            &claims,
-            &self.encoding_key,
-        )
-        .map(Secret::new)
-        .context("Failed to encode a JWT token")
+        self.encode(&claims).map(|token| (token, claims))
+    }
+
+    #[tracing::instrument(name = "Generate service token", skip(self))]
+    pub fn generate_service_token(
+        &self,
+        service_account_id: i64,
+        client_id: String,
+        scopes: Vec<String>,
+    ) -> anyhow::Result<(Secret<String>, ServiceClaims)> {
+        let claims = ServiceClaims::new(
+            service_account_id,
+            client_id,
+            scopes,
+            self.audience.to_string(),
+            self.issuer.to_string(),
+            self.service_token_ttl,
+        );
+        self.encode(&claims).map(|token| (token, claims))
+    }
+
+    /// Signs the ID token with the same keys as access tokens,
+    /// so clients verify it against the published JWKS,
+    /// which only asymmetric keys are part of.
+    #[tracing::instrument(name = "Generate ID token", skip(self))]
+    pub fn generate_id_token(
+        &self,
+        issuer: String,
+        client_id: String,
+        nonce: Option<String>,
+        user_info: UserInfo,
+    ) -> anyhow::Result<(Secret<String>, IdClaims)> {
+        anyhow::ensure!(
+            self.signing_keys.primary().public_jwk().is_some(),
+            "ID tokens cannot be verified without a public key"
+        );
+        let claims =
+            IdClaims::new(user_info, nonce, client_id, issuer, self.token_ttl);
+        self.encode(&claims).map(|token| (token, claims))
+    }
+
+    fn encode<T: Serialize>(
+        &self,
+        claims: &T,
+    ) -> anyhow::Result<Secret<String>> {
+        let signing_key = self.signing_keys.primary();
+        let mut header = Header::new(signing_key.algorithm());
+        header.kid = Some(self.signing_keys.primary_id().to_owned());
+        jsonwebtoken::encode(&header, claims, signing_key.encoding_key())
+            .map(Secret::new)
+            .context("Failed to encode a JWT token")
     }
 
     pub fn generate_refresh_token() -> Secret<String> {
-        let token = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect::<String>();
-        Secret::new(token)
+        Self::generate_random_token()
+    }
+
+    pub fn generate_password_reset_token() -> Secret<String> {
+        Self::generate_random_token()
+    }
+
+    pub fn generate_verification_token() -> Secret<String> {
+        Self::generate_random_token()
+    }
+
+    pub fn generate_magic_link_token() -> Secret<String> {
+        Self::generate_random_token()
+    }
+
+    pub fn generate_email_code() -> Secret<String> {
+        let code = rand::thread_rng().gen_range(0..1_000_000);
+        Secret::new(format!("{code:06}"))
+    }
+
+    pub fn generate_two_factor_challenge() -> Secret<String> {
+        Self::generate_random_token()
+    }
+
+    pub fn generate_authorization_code() -> Secret<String> {
+        Self::generate_random_token()
+    }
+
+    pub fn generate_oauth_access_token() -> Secret<String> {
+        Self::generate_random_token()
+    }
+
+    pub fn access_token_ttl(&self) -> Duration {
+        self.token_ttl
+    }
+
+    pub fn refresh_token_ttl(&self) -> Duration {
+        self.refresh_token_ttl
+    }
+
+    pub fn service_token_ttl(&self) -> Duration {
+        self.service_token_ttl
+    }
+
+    pub fn hash_token(token: &Secret<String>) -> String {
+        hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+    }
+
+    /// Checks a PKCE code verifier against its S256 code challenge.
+    pub fn verify_code_challenge(
+        code_verifier: &Secret<String>,
+        code_challenge: &str,
+    ) -> bool {
+        let digest = Sha256::digest(code_verifier.expose_secret().as_bytes());
+        URL_SAFE_NO_PAD.encode(digest) == code_challenge
+    }
+
+    pub fn signing_algorithm(&self) -> Algorithm {
+        self.signing_keys.primary().algorithm()
     }
 
     #[tracing::instrument(name = "Decode access token", skip(self))]
-    pub fn get_user_id(&self, token: &str) -> anyhow::Result<i64> {
-        jsonwebtoken::decode::<Claims>(
+    pub fn decode_access_token(&self, token: &str) -> anyhow::Result<Claims> {
+        let claims = self.decode::<Claims>(token)?;
+        anyhow::ensure!(
+            claims.sub_type == SubjectType::User,
+            "JWT was not issued to a user"
+        );
+        Ok(claims)
+    }
+
+    #[tracing::instrument(name = "Decode service token", skip(self))]
+    pub fn decode_service_token(
+        &self,
+        token: &str,
+    ) -> anyhow::Result<ServiceClaims> {
+        let claims = self.decode::<ServiceClaims>(token)?;
+        anyhow::ensure!(
+            claims.sub_type == SubjectType::ServiceAccount,
+            "JWT was not issued to a service account"
+        );
+        Ok(claims)
+    }
+
+    /// Verifies the signature along with the issuer, the audience
+    /// and the validity period, allowing for the configured clock skew.
+    fn decode<T: DeserializeOwned>(&self, token: &str) -> anyhow::Result<T> {
+        let header = jsonwebtoken::decode_header(token)
+            .context("Failed to decode a JWT header")?;
+        // tokens issued before the keyring was introduced have no `kid`,
+        // HS256 ones were signed with `hmac_secret`, others with the primary
+        let signing_key = match header.kid {
+            Some(kid) => self
+                .signing_keys
+                .get(&kid)
+                .context("JWT is signed with an unknown key")?,
+            None => {
+                tracing::warn!("Decoding JWT without key id");
+                self.signing_keys
+                    .get(LEGACY_KEY_ID)
+                    .unwrap_or_else(|| self.signing_keys.primary())
+            }
+        };
+        let mut validation = Validation::new(signing_key.algorithm());
+        validation.set_issuer(&[self.issuer.to_string()]);
+        validation.set_audience(&[self.audience.to_string()]);
+        validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud"]);
+        validation.validate_nbf = true;
+        validation.leeway = self.leeway.as_secs();
+        jsonwebtoken::decode::<T>(
             token,
-            &self.decoding_key,
-            &Validation::new(self.algorithm),
+            signing_key.decoding_key(),
+            &validation,
         )
-        .map(|t| t.claims.user_id)
+        .map(|t| t.claims)
         .context("Failed to decode a JWT token")
     }
+
+    /// Public keys that downstream services can verify access tokens with.
+    pub fn jwks(&self) -> JwkSet {
+        let keys = self
+            .signing_keys
+            .iter()
+            .filter_map(|(id, key)| {
+                let mut jwk = key.public_jwk()?.clone();
+                jwk.common.key_id = Some(id.to_owned());
+                Some(jwk)
+            })
+            .collect();
+        JwkSet { keys }
+    }
+
+    /// Returns the key along with its prefix, which is kept in the clear
+    /// so that users can tell their keys apart.
+    pub fn generate_api_key() -> (String, Secret<String>) {
+        let prefix = format!("{API_KEY_PREFIX}{}", random_alphanumeric(8));
+        let key = format!("{prefix}_{}", random_alphanumeric(32));
+        (prefix, Secret::new(key))
+    }
+
+    /// Returns the client id of a service account along with its secret.
+    pub fn generate_client_credentials() -> (String, Secret<String>) {
+        let client_id = format!("svc_{}", random_alphanumeric(16));
+        (client_id, Secret::new(random_alphanumeric(48)))
+    }
+
+    /// Returns the client id of a client application along with its secret,
+    /// which public clients go without.
+    pub fn generate_oauth_client_credentials() -> (String, Secret<String>) {
+        let client_id = format!("app_{}", random_alphanumeric(16));
+        (client_id, Secret::new(random_alphanumeric(48)))
+    }
+
+    fn generate_random_token() -> Secret<String> {
+        Secret::new(random_alphanumeric(32))
+    }
+}
+
+fn random_alphanumeric(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+mod space_delimited {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        values: &[String],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&values.join(" "))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<String>, D::Error> {
+        let values = String::deserialize(deserializer)?;
+        Ok(values.split_whitespace().map(str::to_owned).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use jsonwebtoken::{
+        Algorithm, DecodingKey, EncodingKey, Header, Validation,
+    };
+    use oauth2::url::Host;
+    use secrecy::{ExposeSecret, Secret};
+    use serde::Serialize;
+    use uuid::Uuid;
+
+    use super::{Claims, TokenService, UserInfo};
+    use crate::{
+        services::{keyring::Keyring, signing_key::SigningKey},
+        test_helpers::ed25519_pem,
+    };
+
+    const SECRET: &[u8] = b"secret";
+
+    #[test]
+    fn decodes_claims_of_issued_token() {
+        let service = token_service("issuer", "audience");
+        let session_id = Uuid::new_v4();
+        let (token, issued_claims) = service
+            .generate_access_token(
+                1,
+                session_id,
+                vec!["admin".into()],
+                vec!["users:read".into(), "users:write".into()],
+            )
+            .unwrap();
+        let claims =
+            service.decode_access_token(token.expose_secret()).unwrap();
+        assert_eq!(claims.jti, issued_claims.jti);
+        assert_eq!(claims.user_id, 1);
+        assert_eq!(claims.roles, ["admin"]);
+        assert_eq!(claims.scopes, ["users:read", "users:write"]);
+        assert_eq!(claims.sid, session_id);
+        let (token, _) = service
+            .generate_access_token(1, session_id, Vec::new(), Vec::new())
+            .unwrap();
+        let other_claims = service.decode_access_token(token.expose_secret());
+        assert_ne!(other_claims.unwrap().jti, claims.jti);
+    }
+
+    #[test]
+    fn rejects_token_for_another_issuer_or_audience() {
+        let service = token_service("issuer", "audience");
+        let (token, _) = service
+            .generate_access_token(1, Uuid::new_v4(), Vec::new(), Vec::new())
+            .unwrap();
+        let other_issuer = token_service("other", "audience");
+        assert!(other_issuer
+            .decode_access_token(token.expose_secret())
+            .is_err());
+        let other_audience = token_service("issuer", "other");
+        assert!(other_audience
+            .decode_access_token(token.expose_secret())
+            .is_err());
+    }
+
+    #[test]
+    fn tells_service_tokens_apart_from_user_tokens() {
+        let service = token_service("issuer", "audience");
+        let (token, _) = service
+            .generate_service_token(1, "client".into(), vec!["scope".into()])
+            .unwrap();
+        let claims =
+            service.decode_service_token(token.expose_secret()).unwrap();
+        assert_eq!(claims.service_account_id, 1);
+        assert_eq!(claims.scopes, ["scope"]);
+        assert!(service.decode_access_token(token.expose_secret()).is_err());
+        let (token, _) = service
+            .generate_access_token(1, Uuid::new_v4(), Vec::new(), Vec::new())
+            .unwrap();
+        assert!(service.decode_service_token(token.expose_secret()).is_err());
+    }
+
+    #[test]
+    fn signs_id_token_for_client() {
+        let signing_key =
+            SigningKey::eddsa_from_pem(ed25519_pem().as_bytes()).unwrap();
+        let service = token_service_for("issuer", "audience", signing_key);
+        let user_info = UserInfo {
+            sub: "user-1".into(),
+            email: Some("user@example.com".into()),
+            ..UserInfo::default()
+        };
+        let (token, _) = service
+            .generate_id_token(
+                "https://issuer.example.com".into(),
+                "client".into(),
+                Some("nonce".into()),
+                user_info,
+            )
+            .unwrap();
+        let header =
+            jsonwebtoken::decode_header(token.expose_secret()).unwrap();
+        assert_eq!(header.kid.as_deref(), Some("1"));
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_audience(&["client"]);
+        validation.set_issuer(&["https://issuer.example.com"]);
+        let jwks = service.jwks();
+        let claims = jsonwebtoken::decode::<serde_json::Value>(
+            token.expose_secret(),
+            &DecodingKey::from_jwk(jwks.find("1").unwrap()).unwrap(),
+            &validation,
+        )
+        .unwrap()
+        .claims;
+        assert_eq!(claims["sub"], "user-1");
+        assert_eq!(claims["nonce"], "nonce");
+        assert_eq!(claims["email"], "user@example.com");
+        assert!(claims.get("name").is_none());
+        assert!(service.decode_access_token(token.expose_secret()).is_err());
+    }
+
+    #[test]
+    fn refuses_id_token_without_public_key() {
+        let service = token_service("issuer", "audience");
+        let id_token = service.generate_id_token(
+            "https://issuer.example.com".into(),
+            "client".into(),
+            None,
+            UserInfo::default(),
+        );
+        assert!(id_token.is_err());
+    }
+
+    #[test]
+    fn verifies_code_challenge() {
+        // example from RFC 7636, appendix B
+        let code_verifier =
+            Secret::new("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".into());
+        let code_challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+        assert!(TokenService::verify_code_challenge(
+            &code_verifier,
+            code_challenge
+        ));
+        assert!(!TokenService::verify_code_challenge(
+            &Secret::new("other".into()),
+            code_challenge
+        ));
+    }
+
+    #[test]
+    fn rejects_token_without_subject_type() {
+        let service = token_service("issuer", "audience");
+        let claims = Claims::new(
+            1,
+            Uuid::new_v4(),
+            Vec::new(),
+            Vec::new(),
+            "audience".into(),
+            "issuer".into(),
+            Duration::from_secs(900),
+        );
+        let mut claims = serde_json::to_value(claims).unwrap();
+        claims.as_object_mut().unwrap().remove("subType");
+        assert!(service.decode_access_token(&encode(&claims)).is_err());
+    }
+
+    #[test]
+    fn rejects_token_not_yet_valid_beyond_leeway() {
+        let service = token_service("issuer", "audience");
+        let mut claims = Claims::new(
+            1,
+            Uuid::new_v4(),
+            Vec::new(),
+            Vec::new(),
+            "audience".into(),
+            "issuer".into(),
+            Duration::from_secs(900),
+        );
+        claims.nbf += 20;
+        assert!(service.decode_access_token(&encode(&claims)).is_ok());
+        claims.nbf += 60;
+        assert!(service.decode_access_token(&encode(&claims)).is_err());
+    }
+
+    fn token_service(issuer: &str, audience: &str) -> TokenService {
+        token_service_for(issuer, audience, SigningKey::hs256(SECRET))
+    }
+
+    fn token_service_for(
+        issuer: &str,
+        audience: &str,
+        signing_key: SigningKey,
+    ) -> TokenService {
+        let signing_keys = Keyring::new(
+            "1".into(),
+            HashMap::from([("1".into(), signing_key)]),
+        )
+        .unwrap();
+        TokenService::new(
+            Host::Domain(issuer.into()),
+            Host::Domain(audience.into()),
+            Duration::from_secs(900),
+            Duration::from_secs(3600),
+            Duration::from_secs(300),
+            Duration::from_secs(30),
+            signing_keys,
+        )
+    }
+
+    fn encode<T: Serialize>(claims: &T) -> String {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("1".into());
+        jsonwebtoken::encode(&header, claims, &EncodingKey::from_secret(SECRET))
+            .unwrap()
+    }
 }
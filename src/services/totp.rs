@@ -0,0 +1,329 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use anyhow::anyhow;
+use hmac::{Hmac, Mac};
+use oauth2::url::Url;
+use rand::{Rng, RngCore};
+use ring::constant_time;
+use secrecy::{ExposeSecret, Secret};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::services::key_derivation::{derive_key, Purpose};
+
+const SECRET_LENGTH: usize = 20;
+const NONCE_LENGTH: usize = 12;
+const DIGITS: u32 = 6;
+const PERIOD: u64 = 30;
+const ALLOWED_SKEW: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Secrets are encrypted with a key derived from the server secret.
+/// While `accept_legacy_keys` is set, those encrypted before
+/// with the plain SHA-256 of the secret are still decrypted
+/// with the legacy cipher and encrypted again on login.
+#[derive(Clone)]
+pub struct TotpService {
+    issuer: String,
+    cipher: Aes256Gcm,
+    legacy_cipher: Option<Aes256Gcm>,
+}
+
+impl TotpService {
+    pub fn new(
+        issuer: String,
+        secret: &[u8],
+        accept_legacy_keys: bool,
+    ) -> Self {
+        let key = derive_key(secret, Purpose::TotpEncryption);
+        let cipher = Aes256Gcm::new_from_slice(key.expose_secret())
+            .expect("derived key has the length of AES-256 key");
+        let legacy_cipher =
+            accept_legacy_keys.then(|| Aes256Gcm::new(&Sha256::digest(secret)));
+        Self {
+            issuer,
+            cipher,
+            legacy_cipher,
+        }
+    }
+
+    pub fn generate_secret() -> Secret<Vec<u8>> {
+        let mut secret = vec![0; SECRET_LENGTH];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Secret::new(secret)
+    }
+
+    pub fn encode_secret(secret: &Secret<Vec<u8>>) -> String {
+        base32::encode(
+            base32::Alphabet::RFC4648 { padding: false },
+            secret.expose_secret(),
+        )
+    }
+
+    pub fn provisioning_uri(
+        &self,
+        account_name: &str,
+        secret: &Secret<Vec<u8>>,
+    ) -> String {
+        let mut uri = Url::parse("otpauth://totp").unwrap();
+        uri.set_path(&format!("{}:{}", self.issuer, account_name));
+        uri.query_pairs_mut()
+            .append_pair("secret", &Self::encode_secret(secret))
+            .append_pair("issuer", &self.issuer)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &DIGITS.to_string())
+            .append_pair("period", &PERIOD.to_string());
+        uri.into()
+    }
+
+    /// Binds the ciphertext to the user, so that a secret copied
+    /// to another row fails to decrypt.
+    #[tracing::instrument(name = "Encrypt TOTP secret", skip(self, secret))]
+    pub fn encrypt_secret(
+        &self,
+        user_id: i64,
+        secret: &Secret<Vec<u8>>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: secret.expose_secret(),
+            aad: &user_id.to_be_bytes(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| anyhow!("Failed to encrypt TOTP secret"))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    /// Also tells whether the secret was encrypted with the legacy cipher
+    /// and has to be encrypted again.
+    #[tracing::instrument(name = "Decrypt TOTP secret", skip_all)]
+    pub fn decrypt_secret(
+        &self,
+        user_id: i64,
+        encrypted_secret: &[u8],
+    ) -> anyhow::Result<(Secret<Vec<u8>>, bool)> {
+        if encrypted_secret.len() < NONCE_LENGTH {
+            return Err(anyhow!("Encrypted TOTP secret is too short"));
+        }
+        let (nonce, ciphertext) = encrypted_secret.split_at(NONCE_LENGTH);
+        let nonce = Nonce::from_slice(nonce);
+        let aad = user_id.to_be_bytes();
+        let payload = || Payload {
+            msg: ciphertext,
+            aad: &aad,
+        };
+        if let Ok(secret) = self.cipher.decrypt(nonce, payload()) {
+            return Ok((Secret::new(secret), false));
+        }
+        self.legacy_cipher
+            .as_ref()
+            .and_then(|cipher| cipher.decrypt(nonce, payload()).ok())
+            .map(|secret| {
+                tracing::warn!("Decrypted TOTP secret with legacy cipher");
+                (Secret::new(secret), true)
+            })
+            .ok_or_else(|| anyhow!("Failed to decrypt TOTP secret"))
+    }
+
+    /// Returns the time step the code was generated for.
+    /// Steps up to `last_used_step` are rejected to prevent replays.
+    pub fn verify_code(
+        secret: &Secret<Vec<u8>>,
+        code: &str,
+        last_used_step: Option<i64>,
+    ) -> Option<i64> {
+        Self::verify_code_at(secret, code, last_used_step, Self::current_step())
+    }
+
+    /// Verifies the code as if `current_step` was the current time step.
+    fn verify_code_at(
+        secret: &Secret<Vec<u8>>,
+        code: &str,
+        last_used_step: Option<i64>,
+        current_step: i64,
+    ) -> Option<i64> {
+        if code.len() != DIGITS as usize
+            || !code.chars().all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+        (current_step - ALLOWED_SKEW..=current_step + ALLOWED_SKEW)
+            .filter(|step| last_used_step.is_none_or(|last| *step > last))
+            .find(|step| {
+                let expected = Self::generate_code(secret, *step);
+                constant_time::verify_slices_are_equal(
+                    expected.as_bytes(),
+                    code.as_bytes(),
+                )
+                .is_ok()
+            })
+    }
+
+    pub fn generate_code(secret: &Secret<Vec<u8>>, step: i64) -> String {
+        let mut mac =
+            <Hmac<Sha1> as Mac>::new_from_slice(secret.expose_secret())
+                .expect("HMAC accepts keys of any length");
+        mac.update(&step.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(DIGITS),
+            width = DIGITS as usize
+        )
+    }
+
+    /// Codes are formatted as `xxxxx-xxxxx` for readability.
+    pub fn generate_recovery_codes() -> Vec<Secret<String>> {
+        let mut rng = rand::thread_rng();
+        (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                let code = (0..RECOVERY_CODE_LENGTH)
+                    .map(|_| {
+                        let i = rng.gen_range(0..RECOVERY_CODE_ALPHABET.len());
+                        char::from(RECOVERY_CODE_ALPHABET[i])
+                    })
+                    .collect::<String>();
+                let (head, tail) = code.split_at(RECOVERY_CODE_LENGTH / 2);
+                Secret::new(format!("{head}-{tail}"))
+            })
+            .collect()
+    }
+
+    /// Strips formatting, so that codes can be typed in any case
+    /// with or without the dash.
+    pub fn normalize_recovery_code(code: &str) -> Option<Secret<String>> {
+        let code = code
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .map(|c| c.to_ascii_lowercase())
+            .collect::<String>();
+        (code.len() == RECOVERY_CODE_LENGTH
+            && code.bytes().all(|c| RECOVERY_CODE_ALPHABET.contains(&c)))
+        .then(|| Secret::new(code))
+    }
+
+    pub fn current_step() -> i64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        (now.as_secs() / PERIOD) as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm::{
+        aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+        Aes256Gcm, Nonce,
+    };
+    use secrecy::{ExposeSecret, Secret};
+    use sha2::{Digest, Sha256};
+
+    use super::TotpService;
+
+    fn rfc_secret() -> Secret<Vec<u8>> {
+        Secret::new(b"12345678901234567890".to_vec())
+    }
+
+    #[test]
+    fn generates_rfc_6238_codes() {
+        let cases = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ];
+        for (time, code) in cases {
+            let step = time / 30;
+            assert_eq!(TotpService::generate_code(&rfc_secret(), step), code);
+        }
+    }
+
+    #[test]
+    fn accepts_adjacent_steps_once() {
+        let secret = rfc_secret();
+        let step = 1_000;
+        let code = TotpService::generate_code(&secret, step + 1);
+        assert_eq!(
+            TotpService::verify_code_at(&secret, &code, None, step),
+            Some(step + 1)
+        );
+        assert_eq!(
+            TotpService::verify_code_at(&secret, &code, Some(step + 1), step),
+            None
+        );
+        let code = TotpService::generate_code(&secret, step + 2);
+        assert_eq!(
+            TotpService::verify_code_at(&secret, &code, None, step),
+            None
+        );
+    }
+
+    #[test]
+    fn normalizes_generated_recovery_codes() {
+        let codes = TotpService::generate_recovery_codes();
+        assert_eq!(codes.len(), 10);
+        for code in codes {
+            let code = code.expose_secret().to_uppercase();
+            let normalized = TotpService::normalize_recovery_code(&code);
+            assert_eq!(
+                normalized.unwrap().expose_secret(),
+                &code.to_lowercase().replace('-', "")
+            );
+        }
+        assert!(TotpService::normalize_recovery_code("123456").is_none());
+    }
+
+    #[test]
+    fn decrypts_secret_only_for_the_same_user() {
+        let service = TotpService::new("issuer".into(), b"secret", true);
+        let secret = TotpService::generate_secret();
+        let encrypted = service.encrypt_secret(1, &secret).unwrap();
+        let (decrypted, is_legacy) =
+            service.decrypt_secret(1, &encrypted).unwrap();
+        assert_eq!(decrypted.expose_secret(), secret.expose_secret());
+        assert!(!is_legacy);
+        assert!(service.decrypt_secret(2, &encrypted).is_err());
+    }
+
+    #[test]
+    fn decrypts_secret_encrypted_with_legacy_key() {
+        let service = TotpService::new("issuer".into(), b"secret", true);
+        let legacy_cipher = Aes256Gcm::new(&Sha256::digest(b"secret"));
+        let secret = TotpService::generate_secret();
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: secret.expose_secret(),
+            aad: &1i64.to_be_bytes(),
+        };
+        let ciphertext = legacy_cipher.encrypt(&nonce, payload).unwrap();
+        let encrypted = [nonce.as_slice(), &ciphertext].concat();
+        let (decrypted, is_legacy) =
+            service.decrypt_secret(1, &encrypted).unwrap();
+        assert_eq!(decrypted.expose_secret(), secret.expose_secret());
+        assert!(is_legacy);
+        let service = TotpService::new("issuer".into(), b"secret", false);
+        assert!(service.decrypt_secret(1, &encrypted).is_err());
+        let encrypted = service.encrypt_secret(1, &secret).unwrap();
+        let payload = Payload {
+            msg: &encrypted[12..],
+            aad: &1i64.to_be_bytes(),
+        };
+        let nonce = Nonce::from_slice(&encrypted[..12]);
+        assert!(legacy_cipher.decrypt(nonce, payload).is_err());
+    }
+}
@@ -0,0 +1,421 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, ensure, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::value::Value;
+use oauth2::url::Url;
+use rand::RngCore;
+use ring::signature::{self, UnparsedPublicKey};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+const CHALLENGE_LENGTH: usize = 32;
+const ES256: i32 = -7;
+const EDDSA: i32 = -8;
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// Relying party for passkeys. Only ES256 and Ed25519 keys are supported,
+/// and attestation statements are not verified, as `none` is requested.
+#[derive(Clone)]
+pub struct WebauthnService {
+    rp_id: String,
+    rp_name: String,
+    origin: String,
+    timeout: Duration,
+}
+
+/// Bytes that travel as unpadded base64url in JSON.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Base64Url(pub Vec<u8>);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    challenge: Base64Url,
+    rp: RelyingParty,
+    user: UserEntity,
+    pub_key_cred_params: Vec<CredentialParameters>,
+    timeout: u128,
+    attestation: &'static str,
+    exclude_credentials: Vec<CredentialDescriptor>,
+    authenticator_selection: AuthenticatorSelection,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    challenge: Base64Url,
+    rp_id: String,
+    timeout: u128,
+    user_verification: &'static str,
+}
+
+#[derive(Serialize)]
+struct RelyingParty {
+    id: String,
+    name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UserEntity {
+    id: Base64Url,
+    name: String,
+    display_name: String,
+}
+
+#[derive(Serialize)]
+struct CredentialParameters {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    alg: i32,
+}
+
+#[derive(Serialize)]
+struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: Base64Url,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthenticatorSelection {
+    resident_key: &'static str,
+    require_resident_key: bool,
+    user_verification: &'static str,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationCredential {
+    pub id: Base64Url,
+    pub response: AttestationResponse,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: Base64Url,
+    pub attestation_object: Base64Url,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationCredential {
+    pub id: Base64Url,
+    pub response: AssertionResponse,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: Base64Url,
+    pub authenticator_data: Base64Url,
+    pub signature: Base64Url,
+    pub user_handle: Option<Base64Url>,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: Base64Url,
+    origin: String,
+}
+
+/// The caller is responsible for checking that `challenge` was issued.
+#[derive(Clone, Debug)]
+pub struct NewCredential {
+    pub challenge: Vec<u8>,
+    pub id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub algorithm: i32,
+    pub sign_count: u32,
+}
+
+/// The caller is responsible for checking that `challenge` was issued.
+#[derive(Clone, Debug)]
+pub struct Assertion {
+    pub challenge: Vec<u8>,
+    pub sign_count: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct StoredCredential {
+    pub public_key: Vec<u8>,
+    pub algorithm: i32,
+    pub sign_count: u32,
+}
+
+struct AuthenticatorData<'a> {
+    flags: u8,
+    sign_count: u32,
+    attested_credential_data: &'a [u8],
+}
+
+impl WebauthnService {
+    pub fn new(
+        base_url: &Url,
+        rp_name: String,
+        timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let rp_id = base_url
+            .host_str()
+            .context("Base URL must have a host")?
+            .to_owned();
+        let origin = base_url.origin().ascii_serialization();
+        Ok(Self {
+            rp_id,
+            rp_name,
+            origin,
+            timeout,
+        })
+    }
+
+    pub fn generate_challenge() -> Vec<u8> {
+        let mut challenge = vec![0; CHALLENGE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut challenge);
+        challenge
+    }
+
+    /// User handles are opaque to authenticators,
+    /// so the user id is used instead of anything personal.
+    pub fn user_handle(user_id: i64) -> Vec<u8> {
+        user_id.to_be_bytes().to_vec()
+    }
+
+    pub fn creation_options(
+        &self,
+        challenge: Vec<u8>,
+        user_id: i64,
+        user_name: String,
+        display_name: String,
+        existing_credentials: Vec<Vec<u8>>,
+    ) -> CreationOptions {
+        CreationOptions {
+            challenge: Base64Url(challenge),
+            rp: RelyingParty {
+                id: self.rp_id.clone(),
+                name: self.rp_name.clone(),
+            },
+            user: UserEntity {
+                id: Base64Url(Self::user_handle(user_id)),
+                name: user_name,
+                display_name,
+            },
+            pub_key_cred_params: [EDDSA, ES256]
+                .into_iter()
+                .map(|alg| CredentialParameters {
+                    kind: "public-key",
+                    alg,
+                })
+                .collect(),
+            timeout: self.timeout.as_millis(),
+            attestation: "none",
+            exclude_credentials: existing_credentials
+                .into_iter()
+                .map(|id| CredentialDescriptor {
+                    kind: "public-key",
+                    id: Base64Url(id),
+                })
+                .collect(),
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "required",
+                require_resident_key: true,
+                user_verification: "required",
+            },
+        }
+    }
+
+    pub fn request_options(&self, challenge: Vec<u8>) -> RequestOptions {
+        RequestOptions {
+            challenge: Base64Url(challenge),
+            rp_id: self.rp_id.clone(),
+            timeout: self.timeout.as_millis(),
+            user_verification: "required",
+        }
+    }
+
+    fn client_challenge(
+        &self,
+        client_data_json: &[u8],
+        ceremony: &str,
+    ) -> anyhow::Result<Vec<u8>> {
+        let client_data =
+            serde_json::from_slice::<ClientData>(client_data_json)
+                .context("Failed to parse client data")?;
+        ensure!(
+            client_data.kind == format!("webauthn.{ceremony}"),
+            "Unexpected client data type `{}`",
+            client_data.kind
+        );
+        ensure!(
+            client_data.origin == self.origin,
+            "Unexpected origin `{}`",
+            client_data.origin
+        );
+        Ok(client_data.challenge.0)
+    }
+
+    #[tracing::instrument(name = "Verify WebAuthn registration", skip_all)]
+    pub fn verify_registration(
+        &self,
+        credential: &RegistrationCredential,
+    ) -> anyhow::Result<NewCredential> {
+        let challenge = self.client_challenge(
+            &credential.response.client_data_json.0,
+            "create",
+        )?;
+        let attestation_object = ciborium::de::from_reader::<Value, _>(
+            credential.response.attestation_object.0.as_slice(),
+        )
+        .context("Failed to parse attestation object")?;
+        let auth_data = map_get(&attestation_object, &Value::from("authData"))
+            .and_then(Value::as_bytes)
+            .context("Attestation object has no authenticator data")?;
+        let auth_data = self.parse_authenticator_data(auth_data)?;
+        ensure!(
+            auth_data.flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0,
+            "Authenticator data has no attested credential"
+        );
+        let data = auth_data.attested_credential_data;
+        ensure!(data.len() >= 18, "Attested credential data is too short");
+        let id_length = u16::from_be_bytes([data[16], data[17]]) as usize;
+        ensure!(data.len() >= 18 + id_length, "Credential id is truncated");
+        let id = data[18..18 + id_length].to_vec();
+        ensure!(id == credential.id.0, "Credential id does not match");
+        let cose_key =
+            ciborium::de::from_reader::<Value, _>(&data[18 + id_length..])
+                .context("Failed to parse credential public key")?;
+        let (algorithm, public_key) = parse_cose_key(&cose_key)?;
+        Ok(NewCredential {
+            challenge,
+            id,
+            public_key,
+            algorithm,
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    /// Passkeys stand in for both the password and the second factor,
+    /// so the authenticator must have verified the user, not just
+    /// their presence, for a stolen security key not to be enough.
+    #[tracing::instrument(name = "Verify WebAuthn assertion", skip_all)]
+    pub fn verify_assertion(
+        &self,
+        credential: &AuthenticationCredential,
+        stored: &StoredCredential,
+    ) -> anyhow::Result<Assertion> {
+        let response = &credential.response;
+        let challenge =
+            self.client_challenge(&response.client_data_json.0, "get")?;
+        let auth_data =
+            self.parse_authenticator_data(&response.authenticator_data.0)?;
+        ensure!(
+            auth_data.flags & FLAG_USER_VERIFIED != 0,
+            "User was not verified"
+        );
+        let signed_data = [
+            response.authenticator_data.0.as_slice(),
+            &Sha256::digest(&response.client_data_json.0),
+        ]
+        .concat();
+        let algorithm: &dyn signature::VerificationAlgorithm =
+            match stored.algorithm {
+                ES256 => &signature::ECDSA_P256_SHA256_ASN1,
+                EDDSA => &signature::ED25519,
+                alg => bail!("Unsupported algorithm {alg}"),
+            };
+        UnparsedPublicKey::new(algorithm, &stored.public_key)
+            .verify(&signed_data, &response.signature.0)
+            .map_err(|_| anyhow!("Invalid assertion signature"))?;
+        let counters_are_used =
+            stored.sign_count != 0 || auth_data.sign_count != 0;
+        ensure!(
+            !counters_are_used || auth_data.sign_count > stored.sign_count,
+            "Signature counter did not increase, authenticator may be cloned"
+        );
+        Ok(Assertion {
+            challenge,
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    fn parse_authenticator_data<'a>(
+        &self,
+        data: &'a [u8],
+    ) -> anyhow::Result<AuthenticatorData<'a>> {
+        ensure!(data.len() >= 37, "Authenticator data is too short");
+        ensure!(
+            data[..32] == Sha256::digest(self.rp_id.as_bytes())[..],
+            "Unexpected relying party id hash"
+        );
+        let flags = data[32];
+        ensure!(flags & FLAG_USER_PRESENT != 0, "User was not present");
+        let sign_count =
+            u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+        Ok(AuthenticatorData {
+            flags,
+            sign_count,
+            attested_credential_data: &data[37..],
+        })
+    }
+}
+
+/// Public keys are stored in the form `ring` verifies them in:
+/// uncompressed SEC1 points for P-256 and raw bytes for Ed25519.
+fn parse_cose_key(key: &Value) -> anyhow::Result<(i32, Vec<u8>)> {
+    let int = |label: i64| {
+        map_get(key, &Value::from(label))
+            .and_then(Value::as_integer)
+            .and_then(|i| i64::try_from(i).ok())
+    };
+    let bytes = |label: i64| {
+        map_get(key, &Value::from(label))
+            .and_then(Value::as_bytes)
+            .filter(|b| b.len() == 32)
+            .with_context(|| format!("COSE key parameter {label} is invalid"))
+    };
+    match (int(1), int(3), int(-1)) {
+        // EC2 key on P-256
+        (Some(2), Some(alg), Some(1)) if alg == ES256 as i64 => {
+            let public_key = [&[0x04][..], bytes(-2)?, bytes(-3)?].concat();
+            Ok((ES256, public_key))
+        }
+        // OKP key on Ed25519
+        (Some(1), Some(alg), Some(6)) if alg == EDDSA as i64 => {
+            Ok((EDDSA, bytes(-2)?.clone()))
+        }
+        _ => bail!("Unsupported COSE key"),
+    }
+}
+
+fn map_get<'a>(map: &'a Value, key: &Value) -> Option<&'a Value> {
+    map.as_map()?.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+impl Serialize for Base64Url {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&URL_SAFE_NO_PAD.encode(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Base64Url {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        URL_SAFE_NO_PAD
+            .decode(s.trim_end_matches('='))
+            .map(Self)
+            .map_err(serde::de::Error::custom)
+    }
+}